@@ -0,0 +1,179 @@
+use crate::api::{member_chat, DiscordeState};
+use crate::db::Upload;
+use crate::models::attachment::{Attachment, ALLOWED_MIME_TYPES, THUMBNAIL_SIZE};
+use crate::models::now;
+use crate::models::user::User;
+use axum::body::Body;
+use axum::extract::{Multipart, Path, Request, State};
+use axum::http::header::CONTENT_DISPOSITION;
+use axum::http::{HeaderValue, Response, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{middleware, Extension, Json, Router};
+use image::ImageFormat;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::error;
+
+/// Renders a PNG thumbnail of an uploaded image, checking it really is of the declared type
+fn thumbnail(data: &[u8], mime: &str) -> Result<Vec<u8>, String> {
+    let format = image::guess_format(data).map_err(|error| error.to_string())?;
+    if format.to_mime_type() != mime {
+        return Err(format!("Declared {mime} but got {}", format.to_mime_type()));
+    }
+    let image = image::load_from_memory_with_format(data, format).map_err(|e| e.to_string())?;
+    let mut out = Cursor::new(vec![]);
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut out, ImageFormat::Png)
+        .map_err(|error| error.to_string())?;
+    Ok(out.into_inner())
+}
+
+/// Uploads the `file` field of a multipart form to a chat
+#[axum::debug_handler]
+pub async fn upload(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+    mut multipart: Multipart,
+) -> Response<Body> {
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+
+    let field = loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("file") => break field,
+            Ok(Some(_)) => continue,
+            Ok(None) => return StatusCode::BAD_REQUEST.into_response(),
+            Err(error) => return error.status().into_response(),
+        }
+    };
+
+    let name = field.file_name().unwrap_or("file").to_string();
+    let mime = match field.content_type() {
+        Some(mime) if ALLOWED_MIME_TYPES.contains(&mime) => mime.to_string(),
+        _ => return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response(),
+    };
+    let data = match field.bytes().await {
+        Ok(data) if data.len() > state.upload_limit => {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response()
+        }
+        Ok(data) => data.to_vec(),
+        Err(error) => return error.status().into_response(),
+    };
+
+    let thumbnail = if mime.starts_with("image/") {
+        let (data, mime) = (data.clone(), mime.clone());
+        match tokio::task::spawn_blocking(move || thumbnail(&data, &mime)).await {
+            Ok(Ok(thumbnail)) => Some(thumbnail),
+            Ok(Err(_)) => return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response(),
+            Err(error) => {
+                error!(?error);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    } else {
+        None
+    };
+
+    let upload = Upload {
+        attachment: Attachment {
+            chat,
+            uploader: user.username,
+            name,
+            mime,
+            size: data.len() as u64,
+            hash: String::new(),
+            thumbnail: None,
+            timestamp: now(),
+        },
+        data,
+        thumbnail,
+    };
+    match state.db.insert_attachment(upload).await {
+        Ok(attachment) => (StatusCode::CREATED, Json(attachment)).into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn download(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(id): Path<String>,
+    request: Request,
+) -> Response<Body> {
+    serve(user, state, id, request, false).await
+}
+
+#[axum::debug_handler]
+async fn download_thumbnail(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(id): Path<String>,
+    request: Request,
+) -> Response<Body> {
+    serve(user, state, id, request, true).await
+}
+
+/// Streams a stored file, range requests are handled by `ServeFile`
+async fn serve(
+    user: User,
+    state: Arc<DiscordeState>,
+    id: String,
+    request: Request,
+    thumbnail: bool,
+) -> Response<Body> {
+    let stored = match state.db.get_attachment(id).await {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(error) => {
+            error!(?error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Err(status) = member_chat(&state, stored.attachment.chat.clone(), &user).await {
+        return status.into_response();
+    }
+
+    let (path, mime): (PathBuf, mime::Mime) = if thumbnail {
+        match stored.thumbnail {
+            Some(path) => (path, mime::IMAGE_PNG),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        }
+    } else {
+        let mime = stored
+            .attachment
+            .mime
+            .parse()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        (stored.path, mime)
+    };
+
+    let mut response = match ServeFile::new_with_mime(path, &mime).oneshot(request).await {
+        Ok(response) => response.map(Body::new),
+        Err(error) => match error {},
+    };
+    if !thumbnail {
+        let name = stored.attachment.name.replace(['"', '\\', '\r', '\n'], "_");
+        if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{name}\"")) {
+            response.headers_mut().insert(CONTENT_DISPOSITION, value);
+        }
+    }
+    response
+}
+
+pub fn routes(state: Arc<DiscordeState>) -> Router<Arc<DiscordeState>> {
+    Router::new()
+        .route("/:id", get(download))
+        .route("/:id/thumbnail", get(download_thumbnail))
+        .route_layer(middleware::from_fn_with_state(state, super::middleware))
+}
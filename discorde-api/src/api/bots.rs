@@ -0,0 +1,179 @@
+use crate::api::DiscordeState;
+use crate::models::bot::{
+    BotInput, BotToken, BotTokenInput, BotTokenView, BotView, BOT_TOKEN_PREFIX, MAX_BOTS_PER_OWNER,
+    MAX_TOKENS_PER_BOT,
+};
+use crate::models::chat::SYSTEM_AUTHOR;
+use crate::models::now;
+use crate::models::user::User;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{Response, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{delete, post};
+use axum::{middleware, Extension, Json, Router};
+use std::sync::Arc;
+use tracing::error;
+
+/// Bots owned by `user`
+async fn owned_bots(state: &DiscordeState, user: &User) -> Result<Vec<BotView>, StatusCode> {
+    let users = state.db.get_users().await.map_err(|error| {
+        error!(?error);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(users
+        .into_iter()
+        .filter(|u| u.bot.as_ref().is_some_and(|bot| bot.owner == user.username))
+        .filter_map(User::into_bot_view)
+        .collect())
+}
+
+/// Fetches a bot, making sure `user` owns it
+async fn owned_bot(state: &DiscordeState, name: String, user: &User) -> Result<User, StatusCode> {
+    match state.db.get_user(name).await {
+        Ok(Some(bot)) if bot.bot.as_ref().is_some_and(|b| b.owner == user.username) => Ok(bot),
+        Ok(Some(bot)) if bot.bot.is_some() => Err(StatusCode::FORBIDDEN),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(error) => {
+            error!(?error);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn create_bot(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Json(input): Json<BotInput>,
+) -> Response<Body> {
+    // Only humans own bots
+    if user.bot.is_some() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let username = input.username.trim().to_string();
+    if username.is_empty() || username == SYSTEM_AUTHOR || username.starts_with(BOT_TOKEN_PREFIX) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    match owned_bots(&state, &user).await {
+        Ok(bots) if bots.len() >= MAX_BOTS_PER_OWNER => {
+            return StatusCode::CONFLICT.into_response()
+        }
+        Ok(_) => {}
+        Err(status) => return status.into_response(),
+    }
+
+    match state.db.get_user(username.clone()).await {
+        Ok(Some(_)) => return StatusCode::BAD_REQUEST.into_response(),
+        Err(error) => {
+            error!(?error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Ok(None) => {}
+    }
+
+    let bot = User::bot(username, user.username, now());
+    match state.db.insert_user(bot.clone()).await {
+        Ok(_) => (StatusCode::CREATED, Json(bot.into_bot_view())).into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn get_bots(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+) -> Result<Json<Vec<BotView>>, StatusCode> {
+    owned_bots(&state, &user).await.map(Json)
+}
+
+/// Issues a token, the only time it can be seen in clear
+#[axum::debug_handler]
+async fn create_token(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(bot): Path<String>,
+    Json(mut input): Json<BotTokenInput>,
+) -> Response<Body> {
+    if let Err(status) = owned_bot(&state, bot.clone(), &user).await {
+        return status.into_response();
+    }
+    if input.validate().is_err() {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    match state.db.get_bot_tokens(bot.clone()).await {
+        Ok(tokens) if tokens.len() >= MAX_TOKENS_PER_BOT => {
+            return StatusCode::CONFLICT.into_response()
+        }
+        Ok(_) => {}
+        Err(error) => {
+            error!(?error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let (token, stored) = BotToken::generate(bot, input.name, now());
+    match state.db.insert_bot_token(stored.clone()).await {
+        Ok(id) => (StatusCode::CREATED, Json(stored.into_view(id, Some(token)))).into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn get_tokens(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(bot): Path<String>,
+) -> Response<Body> {
+    if let Err(status) = owned_bot(&state, bot.clone(), &user).await {
+        return status.into_response();
+    }
+
+    match state.db.get_bot_tokens(bot).await {
+        Ok(tokens) => Json(
+            tokens
+                .into_iter()
+                .map(|(id, token)| token.into_view(id, None))
+                .collect::<Vec<BotTokenView>>(),
+        )
+        .into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn revoke_token(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((bot, id)): Path<(String, String)>,
+) -> StatusCode {
+    if let Err(status) = owned_bot(&state, bot.clone(), &user).await {
+        return status;
+    }
+
+    match state.db.remove_bot_token(bot, id).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub fn routes(state: Arc<DiscordeState>) -> Router<Arc<DiscordeState>> {
+    Router::new()
+        .route("/", post(create_bot).get(get_bots))
+        .route("/:bot/tokens", post(create_token).get(get_tokens))
+        .route("/:bot/tokens/:id", delete(revoke_token))
+        .route_layer(middleware::from_fn_with_state(state, super::middleware))
+}
@@ -1,31 +1,56 @@
-use crate::api::DiscordeState;
-use crate::chat::WsCommand;
-use crate::models::chat::ChatInput;
+use crate::api::commands::Outcome;
+use crate::api::{admin_chat, commands, member_chat, DiscordeState};
+use crate::db::{MemberUpdate, PinUpdate, Reaction, TextUpdate, Vote};
+use crate::models::chat::{
+    ChatInput, ChatPatch, MessageEdit, MessageKind, MessageView, PinView, Retention,
+};
+use crate::models::now;
+use crate::models::page::PageQuery;
+use crate::models::poll::{Poll, VoteInput};
+use crate::models::settings::{SettingsPatch, UserChatView};
 use crate::models::user::User;
+use crate::models::ws::{WsCommand, WsEvent, WsMessage, WsRequest};
 use axum::body::Body;
-use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{ConnectInfo, Path, State, WebSocketUpgrade};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Path, Query, State, WebSocketUpgrade};
 use axum::http::{Response, StatusCode};
 use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, patch, post, put};
 use axum::{middleware, Extension, Json, Router};
+use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
-use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::io::ErrorKind;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::select;
 use tokio::sync::broadcast::{Receiver, Sender};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+/// Longest emoji (or emoji sequence) accepted as a reaction, in bytes
+const MAX_EMOJI_LEN: usize = 32;
 
 #[axum::debug_handler]
 async fn get_user_chats(
     Extension(user): Extension<User>,
     State(state): State<Arc<DiscordeState>>,
 ) -> Response<Body> {
+    let mut settings = user.settings;
     let mut cs = vec![];
     for chat in user.chats {
         let c = state.db.get_chat(chat.clone()).await.unwrap();
         if let Some(c) = c {
-            cs.push(c.into_view(chat));
+            let settings = settings.remove(&chat).unwrap_or_default();
+            let unread = c
+                .messages
+                .iter()
+                .filter(|m| m.timestamp > settings.last_read && m.author != user.username)
+                .count();
+            cs.push(UserChatView {
+                chat: c.into_view(chat),
+                settings: settings.into_view(now()),
+                unread,
+            });
         }
     }
 
@@ -34,11 +59,12 @@ async fn get_user_chats(
 
 #[axum::debug_handler]
 async fn create_chat(
+    Extension(user): Extension<User>,
     State(state): State<Arc<DiscordeState>>,
     Json(chat): Json<ChatInput>,
-) -> StatusCode {
+) -> Response<Body> {
+    let chat = chat.into_chat(user.username.clone());
     let members = chat.members.clone();
-    let chat = chat.into_chat();
     let id = state.db.insert_chat(chat).await.unwrap();
 
     for member in members {
@@ -48,15 +74,531 @@ async fn create_chat(
         }
     }
 
-    StatusCode::CREATED
+    let text = format!("{} created the chat", user.username);
+    state.chat.announce(id.clone(), text).await;
+
+    match state.db.get_chat(id.clone()).await {
+        Ok(Some(chat)) => (StatusCode::CREATED, Json(chat.into_view(id))).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
 
 #[axum::debug_handler]
 async fn get_chat_messages(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+) -> Response<Body> {
+    let chat = match member_chat(&state, chat, &user).await {
+        Ok(chat) => chat,
+        Err(status) => return status.into_response(),
+    };
+
+    // Thread replies live in their own history, roots only show how many there are
+    let mut replies = HashMap::<u64, usize>::new();
+    for root in chat.messages.iter().filter_map(|m| m.thread) {
+        *replies.entry(root).or_default() += 1;
+    }
+
+    Json(
+        chat.messages
+            .into_iter()
+            .filter(|message| message.thread.is_none())
+            .map(|message| {
+                let mut view = message.into_view(&user.username);
+                view.replies = replies.get(&view.timestamp).copied().unwrap_or_default();
+                view
+            })
+            .collect::<Vec<MessageView>>(),
+    )
+    .into_response()
+}
+
+#[axum::debug_handler]
+async fn get_thread_messages(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, root)): Path<(String, u64)>,
+    Query(page): Query<PageQuery>,
+) -> Response<Body> {
+    let chat = match member_chat(&state, chat, &user).await {
+        Ok(chat) => chat,
+        Err(status) => return status.into_response(),
+    };
+
+    if !chat
+        .messages
+        .iter()
+        .any(|m| m.timestamp == root && m.thread.is_none())
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let thread = chat
+        .messages
+        .into_iter()
+        .filter(|message| message.thread == Some(root))
+        .map(|message| message.into_view(&user.username))
+        .collect();
+
+    Json(page.apply(thread, |view: &MessageView| view.timestamp)).into_response()
+}
+
+/// Changes the text of a message, only its author can
+#[axum::debug_handler]
+async fn edit_message(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, message)): Path<(String, u64)>,
+    Json(input): Json<MessageEdit>,
+) -> StatusCode {
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status;
+    }
+
+    let update = TextUpdate {
+        chat: chat.clone(),
+        message,
+        user: user.username,
+        text: input.message,
+    };
+    match state.db.edit_message(update).await {
+        Ok(Some(edited)) => {
+            let event = WsEvent::MessageEdit {
+                message,
+                text: edited.message,
+                rich: edited.rich,
+                edited_at: edited.edited_at.unwrap_or_default(),
+            };
+            state.chat.publish(chat, WsMessage::Event(event));
+            StatusCode::NO_CONTENT
+        }
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) if error.kind() == ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+        Err(error) if error.kind() == ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn add_reaction(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, message, emoji)): Path<(String, u64, String)>,
+) -> StatusCode {
+    update_reaction(state, user, chat, message, emoji, true).await
+}
+
+#[axum::debug_handler]
+async fn remove_reaction(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, message, emoji)): Path<(String, u64, String)>,
+) -> StatusCode {
+    update_reaction(state, user, chat, message, emoji, false).await
+}
+
+async fn update_reaction(
+    state: Arc<DiscordeState>,
+    user: User,
+    chat: String,
+    message: u64,
+    emoji: String,
+    add: bool,
+) -> StatusCode {
+    if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN || emoji.chars().any(char::is_whitespace) {
+        return StatusCode::BAD_REQUEST;
+    }
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status;
+    }
+
+    let reaction = Reaction {
+        chat: chat.clone(),
+        message,
+        emoji: emoji.clone(),
+        user: user.username.clone(),
+    };
+    let res = if add {
+        state.db.add_reaction(reaction).await
+    } else {
+        state.db.remove_reaction(reaction).await
+    };
+
+    match res {
+        Ok(Some(true)) => {
+            let event = if add {
+                WsEvent::ReactionAdd {
+                    message,
+                    emoji,
+                    user: user.username,
+                }
+            } else {
+                WsEvent::ReactionRemove {
+                    message,
+                    emoji,
+                    user: user.username,
+                }
+            };
+            state.chat.publish(chat, WsMessage::Event(event));
+            StatusCode::NO_CONTENT
+        }
+        Ok(Some(false)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn get_pins(
+    Extension(user): Extension<User>,
     State(state): State<Arc<DiscordeState>>,
     Path(chat): Path<String>,
-) -> Json<BTreeSet<crate::models::chat::Message>> {
-    Json(state.db.get_chat(chat).await.unwrap().unwrap().messages)
+) -> Response<Body> {
+    let chat = match member_chat(&state, chat, &user).await {
+        Ok(chat) => chat,
+        Err(status) => return status.into_response(),
+    };
+
+    let pins: Vec<PinView> = chat
+        .pins
+        .into_iter()
+        .filter_map(|pin| {
+            let message = chat.messages.iter().find(|m| m.timestamp == pin.message)?;
+            Some(PinView {
+                pinned_by: pin.by,
+                pinned_at: pin.timestamp,
+                message: message.clone().into_view(&user.username),
+            })
+        })
+        .collect();
+
+    Json(pins).into_response()
+}
+
+#[axum::debug_handler]
+async fn pin_message(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, message)): Path<(String, u64)>,
+) -> StatusCode {
+    update_pin(state, user, chat, message, true).await
+}
+
+#[axum::debug_handler]
+async fn unpin_message(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, message)): Path<(String, u64)>,
+) -> StatusCode {
+    update_pin(state, user, chat, message, false).await
+}
+
+async fn update_pin(
+    state: Arc<DiscordeState>,
+    user: User,
+    chat: String,
+    message: u64,
+    pinned: bool,
+) -> StatusCode {
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status;
+    }
+
+    let pin = PinUpdate {
+        chat: chat.clone(),
+        message,
+        user: user.username.clone(),
+        pinned,
+    };
+    match state.db.set_pin(pin).await {
+        Ok(Some(true)) => {
+            let event = WsEvent::Pin {
+                message,
+                user: user.username,
+                pinned,
+            };
+            state.chat.publish(chat, WsMessage::Event(event));
+            StatusCode::NO_CONTENT
+        }
+        Ok(Some(false)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) if error.kind() == ErrorKind::QuotaExceeded => StatusCode::CONFLICT,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn vote(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, message)): Path<(String, u64)>,
+    Json(input): Json<VoteInput>,
+) -> Response<Body> {
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+
+    let vote = Vote {
+        chat,
+        message,
+        user: user.username.clone(),
+        options: input.options,
+    };
+    match cast_vote(&state, vote).await {
+        Ok(Some(poll)) => Json(poll.into_view(&user.username, now())).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(error) if error.kind() == ErrorKind::InvalidInput => {
+            StatusCode::BAD_REQUEST.into_response()
+        }
+        Err(error) if error.kind() == ErrorKind::PermissionDenied => {
+            StatusCode::CONFLICT.into_response()
+        }
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Records a vote and sends the new tally to the chat
+async fn cast_vote(state: &DiscordeState, vote: Vote) -> Result<Option<Poll>, std::io::Error> {
+    let (chat, message) = (vote.chat.clone(), vote.message);
+    let poll = state.db.vote(vote).await?;
+    if let Some(poll) = &poll {
+        let event = WsEvent::PollUpdate {
+            message,
+            tally: poll.tally(),
+            voters: poll.voters(),
+        };
+        state.chat.publish(chat, WsMessage::Event(event));
+    }
+    Ok(poll)
+}
+
+#[axum::debug_handler]
+async fn edit_chat(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+    Json(mut patch): Json<ChatPatch>,
+) -> Response<Body> {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+    if patch.validate().is_err() {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    // Icons are images uploaded to the chat beforehand
+    if let Some(Some(icon)) = &mut patch.icon {
+        match state.db.get_attachment(icon.id.clone()).await {
+            Ok(Some(stored))
+                if stored.attachment.chat == chat
+                    && stored.attachment.mime.starts_with("image/") =>
+            {
+                *icon = stored.attachment.into_ref(icon.id.clone());
+            }
+            Ok(_) => return StatusCode::BAD_REQUEST.into_response(),
+            Err(error) => {
+                error!(?error);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    let edited = match state.db.edit_chat(chat.clone(), patch).await {
+        Ok(Some(edited)) => edited,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(error) => {
+            error!(?error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if !edited.changes.is_empty() {
+        for change in &edited.changes {
+            let text = format!("{} {change}", user.username);
+            state.chat.announce(chat.clone(), text).await;
+        }
+        let event = WsEvent::ChatUpdate {
+            chat: edited.chat.clone(),
+        };
+        state.chat.publish(chat, WsMessage::Event(event));
+    }
+    Json(edited.chat).into_response()
+}
+
+/// Adds a member, anyone can also join public chats by themselves
+#[axum::debug_handler]
+async fn add_member(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, member)): Path<(String, String)>,
+) -> StatusCode {
+    update_member(state, user, chat, member, true).await
+}
+
+/// Removes a member, anyone can also leave by themselves
+#[axum::debug_handler]
+async fn remove_member(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, member)): Path<(String, String)>,
+) -> StatusCode {
+    update_member(state, user, chat, member, false).await
+}
+
+async fn update_member(
+    state: Arc<DiscordeState>,
+    user: User,
+    chat: String,
+    member: String,
+    add: bool,
+) -> StatusCode {
+    let c = match state.db.get_chat(chat.clone()).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let myself = member == user.username;
+    let allowed = match (myself, add) {
+        (true, true) => !c.private || c.members.contains(&member),
+        (true, false) => true,
+        (false, true) => c.admins.contains(&user.username),
+        // Admins can't be removed by anyone but themselves
+        (false, false) => c.admins.contains(&user.username) && !c.admins.contains(&member),
+    };
+    if !allowed {
+        return StatusCode::FORBIDDEN;
+    }
+
+    let update = MemberUpdate {
+        chat: chat.clone(),
+        user: member.clone(),
+        member: add,
+    };
+    match state.db.set_member(update).await {
+        Ok(Some(true)) => {
+            let text = match (myself, add) {
+                (true, true) => format!("{member} joined the chat"),
+                (true, false) => format!("{member} left the chat"),
+                (false, true) => format!("{} added {member}", user.username),
+                (false, false) => format!("{} removed {member}", user.username),
+            };
+            state.chat.announce(chat.clone(), text).await;
+            state.chat.publish_member(chat, member, user.username, add);
+            StatusCode::NO_CONTENT
+        }
+        Ok(Some(false)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn set_settings(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+    Json(patch): Json<SettingsPatch>,
+) -> Response<Body> {
+    match state.db.set_settings(user.username, chat, patch).await {
+        Ok(Some(settings)) => Json(settings.into_view(now())).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(error) if error.kind() == ErrorKind::InvalidInput => {
+            StatusCode::BAD_REQUEST.into_response()
+        }
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Sets the order chats are listed in, the body must list every chat of the user
+#[axum::debug_handler]
+async fn reorder_chats(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Json(order): Json<Vec<String>>,
+) -> StatusCode {
+    match state.db.reorder_chats(user.username, order).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) if error.kind() == ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn set_retention(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+    Json(retention): Json<Retention>,
+) -> StatusCode {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status;
+    }
+    if matches!(
+        retention,
+        Retention::Days { days: 0 } | Retention::Ephemeral { seconds: 0 }
+    ) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    match state.db.set_retention(chat, retention).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Marks every message up to the given one as read, starting the countdown of ephemeral ones
+#[axum::debug_handler]
+async fn read_messages(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, message)): Path<(String, u64)>,
+) -> StatusCode {
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status;
+    }
+
+    match state.db.read_messages(chat, user.username, message).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
 }
 
 /// The handler for the HTTP request (this gets called when the HTTP request lands at the start
@@ -72,30 +614,47 @@ async fn ws_handler(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Path(chat): Path<String>,
 ) -> impl IntoResponse {
-    // Maybe auth
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+
     let chan = state.chat.subscribe(chat.clone()).await;
+    let user_rx = state.chat.subscribe_user(user.username.clone()).await;
 
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
-    ws.on_upgrade(move |socket| {
-        handle_socket(socket, addr, chan, chat, user.username, state.clone())
+    // Clients pass their token as a second protocol, the first is the one agreed on
+    // Everything logged about the connection goes under this span, which is the request's child
+    let span = info_span!("websocket", %addr, chat, user = user.username);
+    ws.protocols(["realProtocol"]).on_upgrade(move |socket| {
+        handle_socket(socket, chan, user_rx, chat, user, state.clone()).instrument(span)
     })
 }
 
+/// Sends a message only this connection will see
+async fn send_ephemeral(sender: &mut SplitSink<WebSocket, Message>, message: String) {
+    let event = WsMessage::Event(WsEvent::Ephemeral { message });
+    _ = sender
+        .send(Message::Text(serde_json::to_string(&event).unwrap()))
+        .await;
+}
+
 /// Actual websocket statemachine (one will be spawned per connection)
 async fn handle_socket(
     mut socket: WebSocket,
-    who: SocketAddr,
-    (chat_tx, mut chat_rx): (Sender<WsCommand>, Receiver<WsCommand>),
+    (_, mut chat_rx): (Sender<WsMessage>, Receiver<WsMessage>),
+    mut user_rx: Receiver<WsMessage>,
     chat: String,
-    username: String,
+    user: User,
     state: Arc<DiscordeState>,
 ) {
+    let username = user.username;
+    let bot = user.bot.is_some();
     // send a ping (unsupported by some browsers) just to kick things off and get a response
     if socket.send(Message::Ping(vec![1, 2, 3])).await.is_ok() {
-        println!("Pinged {who}...");
+        debug!("Pinged");
     } else {
-        println!("Could not send ping {who}!");
+        warn!("Could not send ping");
         // no Error here since the only thing we can do is to close the connection.
         // If we can not send messages, there is no way to salvage the statemachine anyway.
         return;
@@ -107,12 +666,11 @@ async fn handle_socket(
     // connections.
     if let Some(msg) = socket.recv().await {
         if let Ok(msg) = msg {
-            match msg {
-                Message::Close(_) => return,
-                _ => {}
+            if let Message::Close(_) = msg {
+                return;
             }
         } else {
-            println!("client {who} abruptly disconnected");
+            info!("Disconnected abruptly");
             return;
         }
     }
@@ -121,32 +679,107 @@ async fn handle_socket(
     // unsolicited messages to client based on some sort of server's internal event (i.e .timer).
     let (mut sender, mut receiver) = socket.split();
 
+    info!("Connected");
     // Spawn a task that will push several messages to the client (does not matter what client does)
-    tokio::spawn(async move {
+    state.sockets.clone().spawn(async move {
         loop {
             select! {
-                Some(Ok(msg)) = receiver.next() => {
+                _ = state.closing.cancelled() => {
+                    let frame = CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "Server shutting down".into(),
+                    };
+                    _ = sender.send(Message::Close(Some(frame))).await;
+                    info!("Closed for shutdown");
+                    return;
+                }
+                msg = receiver.next() => {
+                    // Clients can go away without a Close frame
+                    let Some(Ok(msg)) = msg else {
+                        info!("Disconnected abruptly");
+                        return;
+                    };
                     match msg {
                         Message::Text(text) => {
-                            if let Ok(cmd) = serde_json::from_str::<WsCommand>(&text) {
-                                if cmd.from == username {
-                                    _ = state.db.insert_message(chat.clone(), cmd.message.clone()).await;
-
-                                    _ = chat_tx.send(cmd);
+                            if let Ok(WsRequest::Vote { message, options }) = serde_json::from_str(&text) {
+                                let vote = Vote {
+                                    chat: chat.clone(),
+                                    message,
+                                    user: username.clone(),
+                                    options,
+                                };
+                                let error = match cast_vote(&state, vote).await {
+                                    Ok(Some(_)) => continue,
+                                    Ok(None) => "There is no such poll".to_string(),
+                                    Err(error) if matches!(error.kind(), ErrorKind::InvalidInput | ErrorKind::PermissionDenied) => error.to_string(),
+                                    Err(error) => {
+                                        error!(?error);
+                                        continue;
+                                    }
+                                };
+                                send_ephemeral(&mut sender, error).await;
+                                continue;
+                            }
+                            let Ok(mut cmd) = serde_json::from_str::<WsCommand>(&text) else {
+                                continue;
+                            };
+                            if cmd.from != username {
+                                continue;
+                            }
+                            if bot && !state.bots.check(&username) {
+                                send_ephemeral(&mut sender, "Slow down, this message wasn't sent".to_string()).await;
+                                continue;
+                            }
+                            if cmd.message.message.starts_with('/') {
+                                match commands::run(&state, &chat, &username, &cmd.message.message).await {
+                                    Outcome::Send(text) => cmd.message.message = text,
+                                    Outcome::Reply(message) => {
+                                        send_ephemeral(&mut sender, message).await;
+                                        continue;
+                                    }
                                 }
                             }
+                            // Reactions are only ever added through their own endpoint
+                            cmd.message.reactions.clear();
+                            cmd.message.author = username.clone();
+                            cmd.message.kind = MessageKind::User;
+                            match state.chat.post(chat.clone(), cmd.message).await {
+                                Ok(_) => {}
+                                // Tell the sender why their message was refused
+                                Err(error) if matches!(error.kind(), ErrorKind::InvalidInput | ErrorKind::AlreadyExists) => {
+                                    send_ephemeral(&mut sender, error.to_string()).await;
+                                }
+                                Err(error) => error!(?error),
+                            }
+                        }
+                        Message::Close(_) => {
+                            info!("Disconnected");
+                            return;
                         }
-                        Message::Close(_) => return,
                         _ => {},
                     }
                 },
                 Ok(msg) = chat_rx.recv() => {
-                    if msg.from != username {
+                    // Senders already display their own messages, events go to everyone
+                    if !matches!(&msg, WsMessage::Command(cmd) if cmd.from == username) {
                         /*match msg.message {
 
                         }*/
                         _ = sender.send(Message::Text(serde_json::to_string(&msg).unwrap())).await;
                     }
+                    // Removed members don't get to read on
+                    if matches!(&msg, WsMessage::Event(WsEvent::MemberRemove { user, .. }) if *user == username) {
+                        let frame = CloseFrame {
+                            code: close_code::POLICY,
+                            reason: "Removed from the chat".into(),
+                        };
+                        _ = sender.send(Message::Close(Some(frame))).await;
+                        info!("Closed for removal");
+                        return;
+                    }
+                }
+                Ok(msg) = user_rx.recv() => {
+                    _ = sender.send(Message::Text(serde_json::to_string(&msg).unwrap())).await;
                 }
             }
         }
@@ -163,10 +796,7 @@ async fn handle_socket(
         //     }
         //
         //     tokio::time::sleep(std::time::Duration::from_millis(300)).await;
-    });
-
-    // returning from the handler closes the websocket connection
-    println!("Websocket context {who} destroyed");
+    }.in_current_span());
 }
 
 pub fn routes(state: Arc<DiscordeState>) -> Router<Arc<DiscordeState>> {
@@ -176,6 +806,45 @@ pub fn routes(state: Arc<DiscordeState>) -> Router<Arc<DiscordeState>> {
                 .route("/", post(create_chat))
                 .route("/", get(get_user_chats))
                 .route("/:id/messages", get(get_chat_messages))
+                .route("/:id/messages/:msg", patch(edit_message))
+                .route("/:id/messages/:msg/thread", get(get_thread_messages))
+                .route("/:id/pins", get(get_pins))
+                .route(
+                    "/:id/attachments",
+                    post(super::attachments::upload)
+                        .layer(DefaultBodyLimit::max(state.upload_limit + 64 * 1024)),
+                )
+                .route("/:id/pins/:msg", put(pin_message).delete(unpin_message))
+                .route("/:id/messages/:msg/votes", put(vote))
+                .route("/:id", patch(edit_chat))
+                .route("/:id/members/:user", put(add_member).delete(remove_member))
+                .route("/order", put(reorder_chats))
+                .route("/:id/settings", patch(set_settings))
+                .route("/:id/retention", put(set_retention))
+                .route("/:id/read/:msg", put(read_messages))
+                .route(
+                    "/:id/scheduled",
+                    post(super::scheduled::schedule).get(super::scheduled::list),
+                )
+                .route("/:id/scheduled/:sid", delete(super::scheduled::cancel))
+                .route(
+                    "/:id/webhooks",
+                    post(super::webhooks::create).get(super::webhooks::list),
+                )
+                .route("/:id/webhooks/:hid", delete(super::webhooks::delete))
+                .route(
+                    "/:id/hooks",
+                    post(super::hooks::create).get(super::hooks::list),
+                )
+                .route("/:id/hooks/:hid", delete(super::hooks::delete))
+                .route(
+                    "/:id/webhooks/:hid/failures",
+                    get(super::webhooks::failures),
+                )
+                .route(
+                    "/:id/messages/:msg/reactions/:emoji",
+                    put(add_reaction).delete(remove_reaction),
+                )
                 .route_layer(middleware::from_fn_with_state(
                     state.clone(),
                     super::middleware,
@@ -0,0 +1,260 @@
+use crate::api::DiscordeState;
+use crate::db::MemberUpdate;
+use crate::models::chat::Chat;
+use futures_util::future::BoxFuture;
+use tracing::error;
+
+/// Longest nickname accepted by `/nick`, in characters
+const MAX_NICK_LEN: usize = 32;
+
+/// What happens to a message once its command ran
+pub enum Outcome {
+    /// Post this text in place of the command
+    Send(String),
+    /// Only show this text to whoever ran the command
+    Reply(String),
+}
+
+#[derive(PartialEq)]
+enum Permission {
+    Member,
+    Admin,
+}
+
+/// Who runs a command, and where
+pub struct Context<'a> {
+    pub state: &'a DiscordeState,
+    pub chat_id: &'a str,
+    pub chat: &'a Chat,
+    pub username: &'a str,
+}
+
+type Handler = for<'a> fn(&'a Context<'a>, &'a str) -> BoxFuture<'a, Outcome>;
+
+struct SlashCommand {
+    name: &'static str,
+    usage: &'static str,
+    permission: Permission,
+    handler: Handler,
+}
+
+const COMMANDS: &[SlashCommand] = &[
+    SlashCommand {
+        name: "help",
+        usage: "/help",
+        permission: Permission::Member,
+        handler: help,
+    },
+    SlashCommand {
+        name: "me",
+        usage: "/me <action>",
+        permission: Permission::Member,
+        handler: me,
+    },
+    SlashCommand {
+        name: "shrug",
+        usage: "/shrug [message]",
+        permission: Permission::Member,
+        handler: shrug,
+    },
+    SlashCommand {
+        name: "tableflip",
+        usage: "/tableflip [message]",
+        permission: Permission::Member,
+        handler: tableflip,
+    },
+    // Animated by clients, the server only has to let it through
+    SlashCommand {
+        name: "type",
+        usage: "/type <message>",
+        permission: Permission::Member,
+        handler: verbatim,
+    },
+    SlashCommand {
+        name: "nick",
+        usage: "/nick [nickname]",
+        permission: Permission::Member,
+        handler: nick,
+    },
+    SlashCommand {
+        name: "invite",
+        usage: "/invite <username>",
+        permission: Permission::Admin,
+        handler: invite,
+    },
+    SlashCommand {
+        name: "kick",
+        usage: "/kick <username>",
+        permission: Permission::Admin,
+        handler: kick,
+    },
+];
+
+/// Runs the command `text` starts with, `//` escapes a leading slash
+pub async fn run(state: &DiscordeState, chat_id: &str, username: &str, text: &str) -> Outcome {
+    if let Some(escaped) = text.strip_prefix("//") {
+        return Outcome::Send(format!("/{escaped}"));
+    }
+
+    let (name, args) = match text[1..].split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (&text[1..], ""),
+    };
+    let Some(command) = COMMANDS.iter().find(|c| c.name == name) else {
+        return Outcome::Reply(format!("Unknown command /{name}, try /help"));
+    };
+
+    let chat = match state.db.get_chat(chat_id.to_string()).await {
+        Ok(Some(chat)) => chat,
+        Ok(None) => return Outcome::Reply("This chat doesn't exist anymore".to_string()),
+        Err(error) => {
+            error!(?error);
+            return Outcome::Reply("Something went wrong".to_string());
+        }
+    };
+    if command.permission == Permission::Admin && !chat.admins.iter().any(|a| a == username) {
+        return Outcome::Reply(format!("Only admins of this chat can use /{name}"));
+    }
+
+    let ctx = Context {
+        state,
+        chat_id,
+        chat: &chat,
+        username,
+    };
+    (command.handler)(&ctx, args).await
+}
+
+fn help<'a>(ctx: &'a Context<'a>, _: &'a str) -> BoxFuture<'a, Outcome> {
+    Box::pin(async move {
+        let admin = ctx.chat.admins.iter().any(|a| a == ctx.username);
+        let usages: Vec<_> = COMMANDS
+            .iter()
+            .filter(|c| admin || c.permission == Permission::Member)
+            .map(|c| c.usage)
+            .collect();
+        Outcome::Reply(usages.join("\n"))
+    })
+}
+
+fn me<'a>(ctx: &'a Context<'a>, args: &'a str) -> BoxFuture<'a, Outcome> {
+    Box::pin(async move {
+        if args.is_empty() {
+            return Outcome::Reply("Usage: /me <action>".to_string());
+        }
+        let name = ctx
+            .chat
+            .nicknames
+            .get(ctx.username)
+            .map_or(ctx.username, String::as_str);
+        Outcome::Send(format!("* {name} {args}"))
+    })
+}
+
+fn with_suffix(args: &str, suffix: &str) -> Outcome {
+    if args.is_empty() {
+        Outcome::Send(suffix.to_string())
+    } else {
+        Outcome::Send(format!("{args} {suffix}"))
+    }
+}
+
+fn shrug<'a>(_: &'a Context<'a>, args: &'a str) -> BoxFuture<'a, Outcome> {
+    Box::pin(async move { with_suffix(args, "¯\\_(ツ)_/¯") })
+}
+
+fn tableflip<'a>(_: &'a Context<'a>, args: &'a str) -> BoxFuture<'a, Outcome> {
+    Box::pin(async move { with_suffix(args, "(╯°□°)╯︵ ┻━┻") })
+}
+
+fn verbatim<'a>(_: &'a Context<'a>, args: &'a str) -> BoxFuture<'a, Outcome> {
+    Box::pin(async move { Outcome::Send(format!("/type {args}")) })
+}
+
+fn nick<'a>(ctx: &'a Context<'a>, args: &'a str) -> BoxFuture<'a, Outcome> {
+    Box::pin(async move {
+        if args.chars().count() > MAX_NICK_LEN {
+            return Outcome::Reply(format!(
+                "Nicknames can't be longer than {MAX_NICK_LEN} characters"
+            ));
+        }
+        let nick = (!args.is_empty()).then(|| args.to_string());
+        let res = ctx
+            .state
+            .db
+            .set_nick(ctx.chat_id.to_string(), ctx.username.to_string(), nick)
+            .await;
+        match res {
+            Ok(_) if args.is_empty() => Outcome::Reply("Your nickname was removed".to_string()),
+            Ok(_) => Outcome::Reply(format!("You are now known as {args}")),
+            Err(error) => {
+                error!(?error);
+                Outcome::Reply("Something went wrong".to_string())
+            }
+        }
+    })
+}
+
+async fn set_member(ctx: &Context<'_>, user: &str, member: bool) -> Result<Option<bool>, ()> {
+    let update = MemberUpdate {
+        chat: ctx.chat_id.to_string(),
+        user: user.to_string(),
+        member,
+    };
+    ctx.state
+        .db
+        .set_member(update)
+        .await
+        .map_err(|error| error!(?error))
+}
+
+fn invite<'a>(ctx: &'a Context<'a>, args: &'a str) -> BoxFuture<'a, Outcome> {
+    Box::pin(async move {
+        if args.is_empty() {
+            return Outcome::Reply("Usage: /invite <username>".to_string());
+        }
+        match set_member(ctx, args, true).await {
+            Ok(Some(true)) => {
+                let text = format!("{} added {args}", ctx.username);
+                ctx.state.chat.announce(ctx.chat_id.to_string(), text).await;
+                ctx.state.chat.publish_member(
+                    ctx.chat_id.to_string(),
+                    args.to_string(),
+                    ctx.username.to_string(),
+                    true,
+                );
+                Outcome::Reply(format!("{args} joined the chat"))
+            }
+            Ok(Some(false)) => Outcome::Reply(format!("{args} is already a member")),
+            Ok(None) => Outcome::Reply(format!("There is no user named {args}")),
+            Err(()) => Outcome::Reply("Something went wrong".to_string()),
+        }
+    })
+}
+
+fn kick<'a>(ctx: &'a Context<'a>, args: &'a str) -> BoxFuture<'a, Outcome> {
+    Box::pin(async move {
+        if args.is_empty() {
+            return Outcome::Reply("Usage: /kick <username>".to_string());
+        }
+        if ctx.chat.admins.iter().any(|a| a == args) {
+            return Outcome::Reply("Admins can't be kicked".to_string());
+        }
+        match set_member(ctx, args, false).await {
+            Ok(Some(true)) => {
+                let text = format!("{} removed {args}", ctx.username);
+                ctx.state.chat.announce(ctx.chat_id.to_string(), text).await;
+                ctx.state.chat.publish_member(
+                    ctx.chat_id.to_string(),
+                    args.to_string(),
+                    ctx.username.to_string(),
+                    false,
+                );
+                Outcome::Reply(format!("{args} was removed from the chat"))
+            }
+            Ok(Some(false)) => Outcome::Reply(format!("{args} isn't a member")),
+            Ok(None) => Outcome::Reply(format!("There is no user named {args}")),
+            Err(()) => Outcome::Reply("Something went wrong".to_string()),
+        }
+    })
+}
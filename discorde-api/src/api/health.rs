@@ -0,0 +1,118 @@
+use crate::api::DiscordeState;
+use crate::metrics::{Exposition, Family, Histogram};
+use axum::body::Body;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{header, Response, StatusCode};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long the database worker gets to answer a readiness check
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Requests served, per route
+#[derive(Default)]
+pub struct HttpMetrics {
+    requests: Family<u64>,
+    latencies: Family<Histogram>,
+}
+
+/// Counts and times the requests of matched routes
+///
+/// Routes are labelled by their pattern, such as `/chats/:id`, to keep the series few.
+pub async fn track(
+    State(state): State<Arc<DiscordeState>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    let mut labels = vec![("method", method), ("route", route)];
+    state
+        .http
+        .latencies
+        .observe(labels.clone(), start.elapsed());
+    labels.push(("status", response.status().as_u16().to_string()));
+    state.http.requests.inc(labels);
+    response
+}
+
+/// The process is up and serving requests
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// The database worker answers and the db lock is still ours
+async fn readyz(State(state): State<Arc<DiscordeState>>) -> Response<Body> {
+    if state.closing.is_cancelled() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down").into_response();
+    }
+    match tokio::time::timeout(READY_TIMEOUT, state.db.ready()).await {
+        Ok(true) => "ready".into_response(),
+        Ok(false) => (StatusCode::SERVICE_UNAVAILABLE, "database not locked").into_response(),
+        Err(_) => (StatusCode::SERVICE_UNAVAILABLE, "database not responding").into_response(),
+    }
+}
+
+async fn metrics(State(state): State<Arc<DiscordeState>>) -> Response<Body> {
+    let mut out = Exposition::default();
+    out.counters(
+        "discorde_http_requests_total",
+        "HTTP requests served, by route and status",
+        &state.http.requests,
+    );
+    out.histograms(
+        "discorde_http_request_duration_seconds",
+        "Time taken serving HTTP requests, by route",
+        &state.http.latencies,
+    );
+    out.gauge(
+        "discorde_websockets",
+        "Open chat websockets",
+        state.sockets.len(),
+    );
+    out.gauge(
+        "discorde_chat_channels",
+        "Chats with at least one websocket listening",
+        state.chat.active_channels().await,
+    );
+    out.counter(
+        "discorde_messages_sent_total",
+        "Messages posted to chats",
+        state.chat.messages_sent(),
+    );
+    let db = state.db.stats();
+    out.gauge(
+        "discorde_db_queue_depth",
+        "Database requests waiting for the worker",
+        db.queued.load(Ordering::Relaxed),
+    );
+    out.histograms(
+        "discorde_db_request_duration_seconds",
+        "Time taken serving database requests, by request type",
+        &db.latencies,
+    );
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        out.finish(),
+    )
+        .into_response()
+}
+
+pub fn routes() -> Router<Arc<DiscordeState>> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics))
+}
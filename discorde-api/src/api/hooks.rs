@@ -0,0 +1,129 @@
+use crate::api::{admin_chat, DiscordeState};
+use crate::models::chat::Message;
+use crate::models::hook::{validate_name, HookInput, HookMessage, HookView, IncomingHook};
+use crate::models::now;
+use crate::models::user::User;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{Response, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Extension, Json, Router};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tracing::error;
+
+#[axum::debug_handler]
+pub async fn create(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+    Json(input): Json<HookInput>,
+) -> Response<Body> {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+    let Ok(name) = validate_name(&input.name) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let hook = IncomingHook {
+        chat,
+        name,
+        token: uuid::Uuid::new_v4().simple().to_string(),
+        created_by: user.username,
+        timestamp: now(),
+    };
+    match state.db.insert_hook(hook.clone()).await {
+        Ok(id) => (StatusCode::CREATED, Json(hook.into_view(id))).into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn list(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+) -> Response<Body> {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+
+    match state.db.get_hooks(chat).await {
+        Ok(hooks) => Json(
+            hooks
+                .into_iter()
+                .map(|(id, hook)| hook.into_view(id))
+                .collect::<Vec<HookView>>(),
+        )
+        .into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn delete(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, id)): Path<(String, String)>,
+) -> StatusCode {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status;
+    }
+
+    match state.db.remove_hook(chat, id).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Posts a message through a hook, the token being the only credential
+#[axum::debug_handler]
+async fn post_message(
+    State(state): State<Arc<DiscordeState>>,
+    Path(token): Path<String>,
+    Json(input): Json<HookMessage>,
+) -> Response<Body> {
+    let (id, hook) = match state.db.get_hook_by_token(token).await {
+        Ok(Some(hook)) => hook,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(error) => {
+            error!(?error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let name = match input.name.as_deref().map(validate_name) {
+        Some(Ok(name)) => name,
+        Some(Err(_)) => return StatusCode::BAD_REQUEST.into_response(),
+        None => hook.name,
+    };
+
+    let message = Message::hook(now(), id, name, input.message);
+    match state.chat.post(hook.chat, message).await {
+        Ok(message) => (StatusCode::CREATED, Json(message)).into_response(),
+        Err(error) if error.kind() == ErrorKind::InvalidInput => {
+            StatusCode::BAD_REQUEST.into_response()
+        }
+        // The chat was deleted under the hook
+        Err(error) if error.kind() == ErrorKind::NotFound => StatusCode::NOT_FOUND.into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn routes() -> Router<Arc<DiscordeState>> {
+    Router::new().route("/:token", post(post_message))
+}
@@ -1,15 +1,13 @@
 use crate::api::DiscordeState;
 use crate::models::creds::{Credentials, Login};
-use crate::models::user::UserInput;
 use axum::body::Body;
 use axum::extract::State;
 use axum::http::{Response, StatusCode};
 use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::routing::post;
 use axum::{Json, Router};
-use libc::user;
 use std::sync::Arc;
-use tracing::{error, info};
+use tracing::error;
 
 #[axum::debug_handler]
 async fn login(State(state): State<Arc<DiscordeState>>, Json(user): Json<Login>) -> Response<Body> {
@@ -22,7 +20,8 @@ async fn login(State(state): State<Arc<DiscordeState>>, Json(user): Json<Login>)
         Ok(None) => return StatusCode::BAD_REQUEST.into_response(),
     };
 
-    if db_user.password == user.password {
+    // Bots have no password, only tokens
+    if db_user.bot.is_none() && db_user.password == user.password {
         Json(Credentials {
             token: db_user.username.clone(),
             user: db_user.into_view(),
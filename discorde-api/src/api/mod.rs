@@ -1,22 +1,109 @@
 use crate::chat::ChatSvc;
+use crate::config::Config;
 use crate::db::Database;
+use crate::models::bot::{hash_token, BOT_TOKEN_PREFIX};
+use crate::models::chat::Chat;
+use crate::models::user::User;
+use crate::ratelimit::RateLimiter;
+use crate::webhooks::Webhooks;
 use axum::body::Body;
+use axum::extract::DefaultBodyLimit;
 pub use axum::extract::{Request, State};
+use axum::http::HeaderName;
 use axum::http::StatusCode;
 pub use axum::middleware::Next;
 pub use axum::response::{IntoResponse, Response};
-use axum::Router;
+use axum::{middleware, Router};
 use std::sync::Arc;
-use tower_http::cors::{AllowCredentials, Any};
-use tracing::error;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tower::ServiceBuilder;
+use tower_http::cors::{AllowOrigin, Any};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{error, info_span, Level, Span};
 
+/// Header carrying the ID of a request, kept when the client sets it
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+mod attachments;
+mod bots;
 mod chats;
+mod commands;
+mod health;
+mod hooks;
 mod login;
+mod scheduled;
+mod search;
 mod user;
+mod webhooks;
 
 pub struct DiscordeState {
     pub db: Arc<Database>,
     pub chat: ChatSvc,
+    pub webhooks: Webhooks,
+    /// Requests and messages bots are allowed
+    pub bots: RateLimiter,
+    /// Largest uploaded file, in bytes
+    pub upload_limit: usize,
+    /// Cancelled once the server starts shutting down
+    pub closing: CancellationToken,
+    /// Open websockets, waited for when shutting down
+    pub sockets: TaskTracker,
+    pub http: health::HttpMetrics,
+}
+
+/// Fetches a chat, making sure `user` is one of its members
+async fn member_chat(state: &DiscordeState, id: String, user: &User) -> Result<Chat, StatusCode> {
+    match state.db.get_chat(id).await {
+        Ok(Some(chat)) if chat.members.contains(&user.username) => Ok(chat),
+        Ok(Some(_)) => Err(StatusCode::FORBIDDEN),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(error) => {
+            error!(?error);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Fetches a chat, making sure `user` is one of its admins
+async fn admin_chat(state: &DiscordeState, id: String, user: &User) -> Result<Chat, StatusCode> {
+    match state.db.get_chat(id).await {
+        Ok(Some(chat)) if chat.admins.contains(&user.username) => Ok(chat),
+        Ok(Some(_)) => Err(StatusCode::FORBIDDEN),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(error) => {
+            error!(?error);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Finds who a bearer token belongs to, either a bot token or a user session
+///
+/// Bots are held to a rate limit, which every authenticated request counts against.
+async fn authenticate(state: &DiscordeState, bearer: String) -> Result<User, StatusCode> {
+    let user = if bearer.starts_with(BOT_TOKEN_PREFIX) {
+        state.db.get_bot_by_token(hash_token(&bearer)).await
+    } else {
+        // Bots can't use sessions, they have no password to log in with
+        state
+            .db
+            .get_user(bearer)
+            .await
+            .map(|user| user.filter(|user| user.bot.is_none()))
+    };
+    match user {
+        Ok(Some(user)) if user.bot.is_some() && !state.bots.check(&user.username) => {
+            Err(StatusCode::TOO_MANY_REQUESTS)
+        }
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(StatusCode::UNAUTHORIZED),
+        Err(error) => {
+            error!(?error);
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
 }
 
 async fn middleware(
@@ -38,13 +125,9 @@ async fn middleware(
         Some(bearer) => bearer,
     };
 
-    let user = match state.db.get_user(username).await {
-        Ok(Some(user)) => user,
-        Ok(None) => return StatusCode::UNAUTHORIZED.into_response(),
-        Err(error) => {
-            error!(?error);
-            return StatusCode::UNAUTHORIZED.into_response();
-        }
+    let user = match authenticate(&state, username).await {
+        Ok(user) => user,
+        Err(status) => return status.into_response(),
     };
 
     request.extensions_mut().insert(user);
@@ -61,24 +144,15 @@ async fn ws_middleware(
         .headers()
         .get("Sec-WebSocket-Protocol")
         .and_then(|e| e.to_str().ok())
-        .and_then(|authorization| {
-            authorization
-                .to_string()
-                .split(", ")
-                .last()
-                .map(ToString::to_string)
-        }) {
+        .and_then(|authorization| authorization.split(", ").last().map(ToString::to_string))
+    {
         None => return StatusCode::UNAUTHORIZED.into_response(),
         Some(bearer) => bearer,
     };
 
-    let user = match state.db.get_user(username).await {
-        Ok(Some(user)) => user,
-        Ok(None) => return StatusCode::UNAUTHORIZED.into_response(),
-        Err(error) => {
-            error!(?error);
-            return StatusCode::UNAUTHORIZED.into_response();
-        }
+    let user = match authenticate(&state, username).await {
+        Ok(user) => user,
+        Err(status) => return status.into_response(),
     };
 
     request.extensions_mut().insert(user);
@@ -86,16 +160,57 @@ async fn ws_middleware(
     next.run(request).await
 }
 
-pub fn routes(discorde_state: DiscordeState) -> Router {
+/// Span every log line about a request goes under
+fn request_span<B>(request: &Request<B>) -> Span {
+    let id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    // Incoming hooks are authenticated by the token in their path
+    let path = match request.uri().path() {
+        path if path.starts_with("/hooks/") => "/hooks/<redacted>",
+        path => path,
+    };
+    info_span!("request", id, method = %request.method(), path)
+}
+
+pub fn routes(discorde_state: DiscordeState, config: &Config) -> Router {
     let discorde_state = Arc::new(discorde_state);
+    // Origins were validated with the rest of the config
+    let origins = match config.cors_origins.as_slice() {
+        [any] if any == "*" => AllowOrigin::any(),
+        origins => AllowOrigin::list(origins.iter().filter_map(|o| o.parse().ok())),
+    };
     let cors_layer = tower_http::cors::CorsLayer::new()
-        .allow_origin(Any) // Open access to selected route
+        .allow_origin(origins)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([REQUEST_ID]);
+    // The ID is set before the span is made, so that the span carries it
+    let tracing = ServiceBuilder::new()
+        .layer(SetRequestIdLayer::new(REQUEST_ID, MakeRequestUuid))
+        .layer(PropagateRequestIdLayer::new(REQUEST_ID))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        );
     Router::new()
         .nest("/users", user::routes(discorde_state.clone()))
         .nest("/chats", chats::routes(discorde_state.clone()))
+        .nest("/bots", bots::routes(discorde_state.clone()))
         .nest("/login", login::routes())
+        .nest("/hooks", hooks::routes())
+        .nest("/search", search::routes(discorde_state.clone()))
+        .nest("/attachments", attachments::routes(discorde_state.clone()))
+        .merge(health::routes())
+        .route_layer(middleware::from_fn_with_state(
+            discorde_state.clone(),
+            health::track,
+        ))
         .with_state(discorde_state)
+        .layer(DefaultBodyLimit::max(config.body_limit))
         .layer(cors_layer)
+        .layer(tracing)
 }
@@ -0,0 +1,110 @@
+use crate::api::{member_chat, DiscordeState};
+use crate::models::markdown;
+use crate::models::now;
+use crate::models::scheduled::{ScheduledInput, ScheduledView, MAX_SCHEDULED};
+use crate::models::user::User;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{Response, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use std::sync::Arc;
+use tracing::error;
+
+#[axum::debug_handler]
+pub async fn schedule(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+    Json(input): Json<ScheduledInput>,
+) -> Response<Body> {
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+    if input.send_at <= now() || markdown::parse(&input.message).is_err() {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    match state.db.get_scheduled().await {
+        Ok(scheduled) => {
+            let pending = scheduled
+                .iter()
+                .filter(|(_, s)| s.chat == chat && s.author == user.username)
+                .count();
+            if pending >= MAX_SCHEDULED {
+                return StatusCode::CONFLICT.into_response();
+            }
+        }
+        Err(error) => {
+            error!(?error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let scheduled = input.into_scheduled(chat, user.username);
+    match state.db.insert_scheduled(scheduled.clone()).await {
+        Ok(id) => (StatusCode::CREATED, Json(scheduled.into_view(id))).into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Messages the user scheduled in the chat, soonest first
+#[axum::debug_handler]
+pub async fn list(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+) -> Response<Body> {
+    if let Err(status) = member_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+
+    match state.db.get_scheduled().await {
+        Ok(scheduled) => {
+            let mut scheduled: Vec<ScheduledView> = scheduled
+                .into_iter()
+                .filter(|(_, s)| s.chat == chat && s.author == user.username)
+                .map(|(id, s)| s.into_view(id))
+                .collect();
+            scheduled.sort_by_key(|s| s.send_at);
+            Json(scheduled).into_response()
+        }
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn cancel(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, id)): Path<(String, String)>,
+) -> StatusCode {
+    let owned = match state.db.get_scheduled().await {
+        Ok(scheduled) => scheduled
+            .iter()
+            .any(|(i, s)| *i == id && s.chat == chat && s.author == user.username),
+        Err(error) => {
+            error!(?error);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    if !owned {
+        return StatusCode::NOT_FOUND;
+    }
+
+    match state.db.remove_scheduled(id).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        // Sent in the meantime
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::api::DiscordeState;
+use crate::db::{Search, SearchFilter};
+use crate::models::search::{SearchHit, SearchQuery};
+use crate::models::user::User;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{Response, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{middleware, Extension, Json, Router};
+use std::sync::Arc;
+use tracing::error;
+
+#[axum::debug_handler]
+async fn search(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Query(query): Query<SearchQuery>,
+) -> Response<Body> {
+    if query.q.trim().is_empty() {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let limit = query.limit();
+    let search = Search {
+        user: user.username.clone(),
+        query: query.q,
+        filter: SearchFilter {
+            chat: query.chat,
+            author: query.author,
+            before: query.before,
+            after: query.after,
+        },
+        limit,
+    };
+
+    match state.db.search(search).await {
+        Ok(hits) => Json(
+            hits.into_iter()
+                .map(|hit| SearchHit {
+                    chat: hit.chat,
+                    score: hit.score,
+                    message: hit.message.into_view(&user.username),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn routes(state: Arc<DiscordeState>) -> Router<Arc<DiscordeState>> {
+    Router::new()
+        .route("/", get(search))
+        .route_layer(middleware::from_fn_with_state(state, super::middleware))
+}
@@ -1,14 +1,15 @@
 use crate::api::DiscordeState;
+use crate::models::bot::BOT_TOKEN_PREFIX;
+use crate::models::chat::SYSTEM_AUTHOR;
+use crate::models::mention::{MentionFilter, MentionView};
+use crate::models::page::PageQuery;
 use crate::models::user::{User, UserInput, UserView};
 use axum::body::Body;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::{Response, StatusCode};
 use axum::response::IntoResponse;
-use axum::routing::{get, post};
-use axum::{middleware, Json, Router};
-use libc::user;
-use std::fmt::Debug;
-use std::io::Error;
+use axum::routing::{get, post, put};
+use axum::{middleware, Extension, Json, Router};
 use std::sync::Arc;
 use tracing::{error, info};
 
@@ -18,6 +19,10 @@ async fn create_user(
     Json(user): Json<UserInput>,
 ) -> StatusCode {
     info!(?user);
+    // Names starting like bot tokens would be mistaken for them when authenticating
+    if user.username == SYSTEM_AUTHOR || user.username.starts_with(BOT_TOKEN_PREFIX) {
+        return StatusCode::BAD_REQUEST;
+    }
 
     match state.db.get_user(user.username.clone()).await {
         Ok(Some(_)) => return StatusCode::BAD_REQUEST,
@@ -38,16 +43,18 @@ async fn create_user(
 }
 
 #[axum::debug_handler]
-async fn get_users(State(state): State<Arc<DiscordeState>>) -> Json<Vec<UserView>> {
-    Json(
-        state
-            .db
-            .get_users()
-            .await
-            .into_iter()
-            .map(|user| user.into_view())
-            .collect(),
-    )
+async fn get_users(
+    State(state): State<Arc<DiscordeState>>,
+) -> Result<Json<Vec<UserView>>, StatusCode> {
+    match state.db.get_users().await {
+        Ok(users) => Ok(Json(
+            users.into_iter().map(|user| user.into_view()).collect(),
+        )),
+        Err(error) => {
+            error!(?error);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
 #[axum::debug_handler]
@@ -67,12 +74,53 @@ async fn get_user(
     }
 }
 
+#[axum::debug_handler]
+async fn get_mentions(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Query(page): Query<PageQuery>,
+    Query(filter): Query<MentionFilter>,
+) -> Response<Body> {
+    match state.db.get_mentions(user.username).await {
+        Ok(mentions) => {
+            let mentions: Vec<MentionView> = mentions
+                .into_iter()
+                .filter(|(_, mention)| !filter.unread || !mention.read)
+                .map(|(id, mention)| mention.into_view(id))
+                .collect();
+            Json(page.apply(mentions, |mention| mention.message)).into_response()
+        }
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn read_mention(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    match state.db.read_mention(user.username, id).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
 pub fn routes(state: Arc<DiscordeState>) -> Router<Arc<DiscordeState>> {
     Router::new()
         .merge(
             Router::new()
                 .route("/", get(get_users))
                 .route("/:id", get(get_user))
+                .route("/me/mentions", get(get_mentions))
+                .route("/me/mentions/:id/read", put(read_mention))
                 .route_layer(middleware::from_fn_with_state(
                     state.clone(),
                     super::middleware,
@@ -0,0 +1,118 @@
+use crate::api::{admin_chat, DiscordeState};
+use crate::models::now;
+use crate::models::user::User;
+use crate::models::webhook::{Webhook, WebhookInput, WebhookView, WEBHOOK_EVENTS};
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{Response, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use std::sync::Arc;
+use tracing::error;
+
+#[axum::debug_handler]
+pub async fn create(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+    Json(input): Json<WebhookInput>,
+) -> Response<Body> {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+    let valid_url = state.webhooks.check_url(&input.url).is_ok();
+    let valid_events = input
+        .events
+        .iter()
+        .all(|event| WEBHOOK_EVENTS.contains(&event.as_str()));
+    if !valid_url || !valid_events {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let webhook = Webhook {
+        chat,
+        url: input.url,
+        secret: uuid::Uuid::new_v4().simple().to_string(),
+        events: input.events,
+        created_by: user.username,
+        timestamp: now(),
+    };
+    match state.db.insert_webhook(webhook.clone()).await {
+        Ok(id) => (StatusCode::CREATED, Json(webhook.into_view(id))).into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn list(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path(chat): Path<String>,
+) -> Response<Body> {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+
+    match state.db.get_webhooks(chat).await {
+        Ok(webhooks) => Json(
+            webhooks
+                .into_iter()
+                .map(|(id, webhook)| webhook.into_view(id))
+                .collect::<Vec<WebhookView>>(),
+        )
+        .into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn delete(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, id)): Path<(String, String)>,
+) -> StatusCode {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status;
+    }
+
+    match state.db.remove_webhook(chat, id).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Deliveries of a webhook that were given up on, oldest first
+#[axum::debug_handler]
+pub async fn failures(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<DiscordeState>>,
+    Path((chat, id)): Path<(String, String)>,
+) -> Response<Body> {
+    if let Err(status) = admin_chat(&state, chat.clone(), &user).await {
+        return status.into_response();
+    }
+
+    match state.db.get_dead_letters(id).await {
+        Ok(letters) => Json(
+            letters
+                .into_iter()
+                .filter(|letter| letter.chat == chat)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(error) => {
+            error!(?error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
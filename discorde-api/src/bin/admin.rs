@@ -0,0 +1,156 @@
+use discorde_api::admin::{self, Admin};
+use std::io::{BufRead, Error, ErrorKind};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: discorde-admin [--db <path>] <command>
+
+Commands:
+  users                      List users
+  create-user <username>     Create a user, reading their password from stdin
+  delete-user <username>     Delete a user and take them out of their chats
+  reset-password <username>  Set a user's password, reading it from stdin
+  chats                      List chats
+  members <chat>             List the members of a chat
+  dump <chat>                Print the messages of a chat, one JSON object per line
+  unlock                     Remove the lock a dead server left behind
+  check                      Look for inconsistencies in the db
+
+Every command but `unlock` refuses to run while a server uses the db.";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut path = PathBuf::from("database");
+    if args.first().map(String::as_str) == Some("--db") && args.len() > 1 {
+        path = PathBuf::from(args.remove(1));
+        args.remove(0);
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match run(path, &args).await {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(path: PathBuf, args: &[&str]) -> Result<ExitCode, Error> {
+    // Removing the lock must not go through `Admin`, which refuses locked dbs
+    if args == ["unlock"] {
+        match admin::remove_stale_lock(&path).await? {
+            Some(pid) => println!("Removed the lock left by process {pid}"),
+            None => println!("The db isn't locked"),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let known = matches!(
+        args,
+        ["users" | "chats" | "check"]
+            | [
+                "create-user" | "delete-user" | "reset-password" | "members" | "dump",
+                _
+            ]
+    );
+    if !known {
+        eprintln!("{USAGE}");
+        return Ok(ExitCode::from(2));
+    }
+
+    let admin = Admin::open(path).await?;
+    let res = command(&admin, args).await;
+    // Unlock even when the command failed
+    admin.close().await?;
+    res
+}
+
+async fn command(admin: &Admin, args: &[&str]) -> Result<ExitCode, Error> {
+    match args {
+        ["users"] => users(admin).await?,
+        ["create-user", username] => {
+            let password = read_password()?;
+            admin.create_user(username.to_string(), password).await?
+        }
+        ["delete-user", username] => admin.delete_user(username).await?,
+        ["reset-password", username] => {
+            let password = read_password()?;
+            admin.reset_password(username, password).await?
+        }
+        ["chats"] => chats(admin).await?,
+        ["members", chat] => members(admin, chat).await?,
+        ["dump", chat] => dump(admin, chat).await?,
+        ["check"] => {
+            let problems = admin.check().await?;
+            if problems.is_empty() {
+                println!("No problem found");
+            }
+            for problem in &problems {
+                println!("{problem}");
+            }
+            if !problems.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn read_password() -> Result<String, Error> {
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Expected a password on stdin",
+        ));
+    }
+    Ok(password)
+}
+
+async fn users(admin: &Admin) -> Result<(), Error> {
+    for user in admin.users().await? {
+        match &user.bot {
+            Some(bot) => println!("{}\tbot of {}", user.username, bot.owner),
+            None => println!("{}\t{} chats", user.username, user.chats.len()),
+        }
+    }
+    Ok(())
+}
+
+async fn chats(admin: &Admin) -> Result<(), Error> {
+    for (id, chat) in admin.chats().await? {
+        let visibility = if chat.private { "private" } else { "public" };
+        println!(
+            "{id}\t{}\t{visibility}\t{} members\t{} messages",
+            chat.name,
+            chat.members.len(),
+            chat.messages.len()
+        );
+    }
+    Ok(())
+}
+
+async fn members(admin: &Admin, id: &str) -> Result<(), Error> {
+    let chat = admin.chat(id).await?;
+    for member in &chat.members {
+        if chat.admins.contains(member) {
+            println!("{member}\tadmin");
+        } else {
+            println!("{member}");
+        }
+    }
+    Ok(())
+}
+
+async fn dump(admin: &Admin, id: &str) -> Result<(), Error> {
+    let chat = admin.chat(id).await?;
+    for message in &chat.messages {
+        println!("{}", serde_json::to_string(message)?);
+    }
+    Ok(())
+}
@@ -1,59 +1,99 @@
 use crate::db::Database;
 use crate::models::chat::Message;
-use serde::{Deserialize, Serialize};
+use crate::models::now;
+use crate::models::ws::{WsCommand, WsEvent, WsMessage};
+use crate::webhooks::Webhooks;
 use std::collections::HashMap;
+use std::io::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::{broadcast, oneshot};
 use tracing::error;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum WsMessage {}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct WsCommand {
-    pub from: String,
-    pub message: Message,
-}
-
 enum Command {
     Subscribe(
         String,
-        oneshot::Sender<(broadcast::Sender<WsCommand>, broadcast::Receiver<WsCommand>)>,
+        oneshot::Sender<(broadcast::Sender<WsMessage>, broadcast::Receiver<WsMessage>)>,
     ),
+    Publish(String, WsMessage),
+    SubscribeUser(String, oneshot::Sender<broadcast::Receiver<WsMessage>>),
+    PublishUser(String, WsMessage),
+    /// Counts the chat channels someone listens to
+    CountChannels(oneshot::Sender<usize>),
 }
 
+#[derive(Clone)]
 pub struct ChatSvc {
     tx: UnboundedSender<Command>,
+    db: Arc<Database>,
+    /// Messages posted since the server started
+    sent: Arc<AtomicU64>,
 }
 
 impl ChatSvc {
-    pub fn new(db: Arc<Database>) -> Self {
+    /// `capacity` is how many messages a subscriber can lag behind before missing some
+    pub fn new(db: Arc<Database>, webhooks: Webhooks, capacity: usize) -> Self {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Command>();
-        tokio::spawn(Self::worker(rx, db));
+        tokio::spawn(Self::worker(rx, webhooks, capacity));
 
-        Self { tx }
+        Self {
+            tx,
+            db,
+            sent: Arc::new(AtomicU64::new(0)),
+        }
     }
 
-    async fn worker(mut comm_rx: UnboundedReceiver<Command>, db: Arc<Database>) {
+    async fn worker(mut comm_rx: UnboundedReceiver<Command>, webhooks: Webhooks, capacity: usize) {
         let mut chats: HashMap<
             String,
-            (broadcast::Sender<WsCommand>, broadcast::Receiver<WsCommand>),
+            (broadcast::Sender<WsMessage>, broadcast::Receiver<WsMessage>),
         > = HashMap::new();
+        // Events targeting a user wherever they are connected
+        let mut users: HashMap<String, broadcast::Sender<WsMessage>> = HashMap::new();
 
         while let Some(command) = comm_rx.recv().await {
             match command {
                 Command::Subscribe(chat_id, reply) => {
                     if let Some((tx, rx)) = chats.get(&chat_id) {
-                        _ = reply.send((tx.clone(), rx.resubscribe())).unwrap();
+                        _ = reply.send((tx.clone(), rx.resubscribe()));
                     } else {
-                        let channel = broadcast::channel(10);
-                        _ = reply
-                            .send((channel.0.clone(), channel.1.resubscribe()))
-                            .unwrap();
+                        let channel = broadcast::channel(capacity);
+                        _ = reply.send((channel.0.clone(), channel.1.resubscribe()));
                         chats.insert(chat_id, channel);
                     }
                 }
+                Command::Publish(chat_id, msg) => {
+                    webhooks.notify(chat_id.clone(), &msg);
+                    // Nobody is listening if the channel was never opened
+                    if let Some((tx, _)) = chats.get(&chat_id) {
+                        _ = tx.send(msg);
+                    }
+                }
+                Command::SubscribeUser(username, reply) => {
+                    // Forgetting users who went away, so the map only holds those connected
+                    users.retain(|_, tx| tx.receiver_count() > 0);
+                    let tx = users
+                        .entry(username)
+                        .or_insert_with(|| broadcast::channel(capacity).0);
+                    _ = reply.send(tx.subscribe());
+                }
+                Command::PublishUser(username, msg) => {
+                    if let Some(tx) = users.get(&username) {
+                        if tx.send(msg).is_err() {
+                            // Nobody receives it anymore
+                            users.remove(&username);
+                        }
+                    }
+                }
+                Command::CountChannels(reply) => {
+                    // The map keeps a receiver of its own
+                    let active = chats
+                        .values()
+                        .filter(|(tx, _)| tx.receiver_count() > 1)
+                        .count();
+                    _ = reply.send(active);
+                }
             }
         }
     }
@@ -61,9 +101,76 @@ impl ChatSvc {
     pub async fn subscribe(
         &self,
         chat_id: String,
-    ) -> (broadcast::Sender<WsCommand>, broadcast::Receiver<WsCommand>) {
+    ) -> (broadcast::Sender<WsMessage>, broadcast::Receiver<WsMessage>) {
         let (tx, rx) = oneshot::channel();
         _ = self.tx.send(Command::Subscribe(chat_id, tx));
         rx.await.map_err(|error| error!(?error)).unwrap()
     }
+
+    pub fn publish(&self, chat_id: String, msg: WsMessage) {
+        _ = self.tx.send(Command::Publish(chat_id, msg));
+    }
+
+    pub async fn subscribe_user(&self, username: String) -> broadcast::Receiver<WsMessage> {
+        let (tx, rx) = oneshot::channel();
+        _ = self.tx.send(Command::SubscribeUser(username, tx));
+        rx.await.map_err(|error| error!(?error)).unwrap()
+    }
+
+    pub fn publish_user(&self, username: String, msg: WsMessage) {
+        _ = self.tx.send(Command::PublishUser(username, msg));
+    }
+
+    pub async fn active_channels(&self) -> usize {
+        let (tx, rx) = oneshot::channel();
+        _ = self.tx.send(Command::CountChannels(tx));
+        rx.await.unwrap_or_default()
+    }
+
+    pub fn messages_sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Tells the chat `user` joined or left it, `by` being whoever made it happen
+    pub fn publish_member(&self, chat: String, user: String, by: String, joined: bool) {
+        let event = if joined {
+            WsEvent::MemberAdd { user, by }
+        } else {
+            WsEvent::MemberRemove { user, by }
+        };
+        self.publish(chat, WsMessage::Event(event));
+    }
+
+    /// Records a system message in the chat history
+    pub async fn announce(&self, chat: String, text: String) {
+        if let Err(error) = self.post(chat, Message::system(now(), text)).await {
+            error!(?error);
+        }
+    }
+
+    /// Stores a message, then sends it to the chat and notifies the users it mentions
+    pub async fn post(&self, chat: String, message: Message) -> Result<Message, Error> {
+        let message = self.db.insert_message(chat.clone(), message).await?;
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        for mentioned in &message.mentions {
+            // Muted chats still record the mention, they just don't notify
+            match self.db.get_user(mentioned.clone()).await {
+                Ok(Some(user)) if user.is_muted(&chat, now()) => continue,
+                Ok(_) => {}
+                Err(error) => error!(?error),
+            }
+            let event = WsEvent::Mention {
+                chat: chat.clone(),
+                message: message.timestamp,
+                author: message.author.clone(),
+            };
+            self.publish_user(mentioned.clone(), WsMessage::Event(event));
+        }
+        let cmd = WsCommand {
+            from: message.author.clone(),
+            message: message.clone(),
+        };
+        self.publish(chat, WsMessage::Command(cmd));
+        Ok(message)
+    }
 }
@@ -0,0 +1,473 @@
+use crate::models::attachment::MAX_ATTACHMENT_SIZE;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File read when no other is given, if it exists
+const DEFAULT_FILE: &str = "discorde.toml";
+/// Broadcast channels allocate their capacity up front
+const MAX_BROADCAST_CAPACITY: usize = 1 << 16;
+/// Smallest body limit leaving room for ordinary requests
+const MIN_BODY_LIMIT: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected text or json".to_string()),
+        }
+    }
+}
+
+/// Settings of the server
+///
+/// They are read from a TOML file, then environment variables, then command line flags, each
+/// source overriding the ones before it.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub bind: SocketAddr,
+    pub database: PathBuf,
+    /// Origins browsers may call the API from, `*` allowing any
+    pub cors_origins: Vec<String>,
+    /// Largest request body, in bytes, uploads aside
+    pub body_limit: usize,
+    /// Largest uploaded file, in bytes
+    pub upload_limit: usize,
+    /// Messages a websocket can lag behind before it misses some
+    pub broadcast_capacity: usize,
+    pub log_format: LogFormat,
+    /// Seconds given to closing connections and the database before giving up
+    pub shutdown_timeout: u64,
+    /// PEM certificate chain, served over TLS along with `tls_key` when set
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`
+    pub tls_key: Option<PathBuf>,
+    /// Lets webhooks target loopback, link-local and private addresses
+    pub allow_private_webhooks: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            database: PathBuf::from("database"),
+            cors_origins: vec!["*".to_string()],
+            body_limit: 2 * 1024 * 1024,
+            upload_limit: MAX_ATTACHMENT_SIZE,
+            broadcast_capacity: 10,
+            log_format: LogFormat::Text,
+            shutdown_timeout: 30,
+            tls_cert: None,
+            tls_key: None,
+            allow_private_webhooks: false,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings from every source, `args` being the command line without the program
+    ///
+    /// Returns the arguments which aren't flags, such as subcommands.
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<(Config, Vec<String>), Error> {
+        let (cli, file, rest) = Layer::from_args(args)?;
+        let env = Layer::from_env(|name| std::env::var(name).ok())?;
+        let file = file.or_else(|| std::env::var_os("DISCORDE_CONFIG").map(PathBuf::from));
+        let toml = match file {
+            Some(path) => Layer::from_file(&path)?,
+            None if Path::new(DEFAULT_FILE).is_file() => Layer::from_file(Path::new(DEFAULT_FILE))?,
+            None => Layer::default(),
+        };
+
+        let config = cli.over(env).over(toml).resolve();
+        config.validate()?;
+        Ok((config, rest))
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.cors_origins.is_empty() {
+            return Err(invalid(
+                "cors_origins",
+                "expected at least one origin, or *",
+            ));
+        }
+        if self.cors_origins.len() > 1 && self.cors_origins.iter().any(|o| o == "*") {
+            return Err(invalid(
+                "cors_origins",
+                "* can't be listed with other origins",
+            ));
+        }
+        for origin in self.cors_origins.iter().filter(|o| *o != "*") {
+            if !is_origin(origin) {
+                return Err(invalid(
+                    "cors_origins",
+                    format!("{origin:?} isn't an origin, such as https://example.com"),
+                ));
+            }
+        }
+        if self.body_limit < MIN_BODY_LIMIT {
+            return Err(invalid(
+                "body_limit",
+                format!("expected at least {MIN_BODY_LIMIT} bytes"),
+            ));
+        }
+        if self.upload_limit == 0 {
+            return Err(invalid("upload_limit", "expected at least 1 byte"));
+        }
+        if !(1..=MAX_BROADCAST_CAPACITY).contains(&self.broadcast_capacity) {
+            return Err(invalid(
+                "broadcast_capacity",
+                format!("expected between 1 and {MAX_BROADCAST_CAPACITY}"),
+            ));
+        }
+        if self.shutdown_timeout == 0 {
+            return Err(invalid("shutdown_timeout", "expected at least 1 second"));
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => return Err(invalid("tls_key", "expected along with tls_cert")),
+            (None, Some(_)) => return Err(invalid("tls_cert", "expected along with tls_key")),
+            _ => {}
+        }
+        for (setting, path) in [("tls_cert", &self.tls_cert), ("tls_key", &self.tls_key)] {
+            if let Some(path) = path.as_ref().filter(|p| !p.is_file()) {
+                return Err(invalid(setting, format!("{} isn't a file", path.display())));
+            }
+        }
+        if self.database.exists() && !self.database.is_dir() {
+            return Err(invalid(
+                "database",
+                format!("{} isn't a directory", self.database.display()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Settings given by one source, the missing ones being left to the sources below it
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Layer {
+    bind: Option<SocketAddr>,
+    database: Option<PathBuf>,
+    cors_origins: Option<Vec<String>>,
+    body_limit: Option<usize>,
+    upload_limit: Option<usize>,
+    broadcast_capacity: Option<usize>,
+    log_format: Option<LogFormat>,
+    shutdown_timeout: Option<u64>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    allow_private_webhooks: Option<bool>,
+}
+
+impl Layer {
+    fn from_file(path: &Path) -> Result<Layer, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| Error::new(error.kind(), format!("{}: {error}", path.display())))?;
+        toml::from_str(&text).map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}: {error}", path.display()),
+            )
+        })
+    }
+
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Layer, Error> {
+        let var = &var;
+        Ok(Layer {
+            bind: parsed(var, "DISCORDE_BIND")?,
+            database: var("DISCORDE_DATABASE").map(PathBuf::from),
+            cors_origins: var("DISCORDE_CORS_ORIGINS")
+                .map(|origins| origins.split(',').map(|o| o.trim().to_string()).collect()),
+            body_limit: parsed(var, "DISCORDE_BODY_LIMIT")?,
+            upload_limit: parsed(var, "DISCORDE_UPLOAD_LIMIT")?,
+            broadcast_capacity: parsed(var, "DISCORDE_BROADCAST_CAPACITY")?,
+            log_format: parsed(var, "DISCORDE_LOG_FORMAT")?,
+            shutdown_timeout: parsed(var, "DISCORDE_SHUTDOWN_TIMEOUT")?,
+            tls_cert: var("DISCORDE_TLS_CERT").map(PathBuf::from),
+            tls_key: var("DISCORDE_TLS_KEY").map(PathBuf::from),
+            allow_private_webhooks: parsed(var, "DISCORDE_ALLOW_PRIVATE_WEBHOOKS")?,
+        })
+    }
+
+    /// Reads the flags of the command line, along with the config file it names
+    fn from_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(Layer, Option<PathBuf>, Vec<String>), Error> {
+        let mut layer = Layer::default();
+        let mut file = None;
+        let mut rest = vec![];
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                rest.push(arg);
+                continue;
+            }
+            let value = args.next().ok_or_else(|| {
+                Error::new(ErrorKind::InvalidInput, format!("{arg}: expected a value"))
+            })?;
+            match arg.as_str() {
+                "--config" => file = Some(PathBuf::from(value)),
+                "--bind" => layer.bind = Some(parse(&arg, &value)?),
+                "--database" => layer.database = Some(PathBuf::from(value)),
+                // Repeated for each allowed origin
+                "--cors-origin" => layer.cors_origins.get_or_insert_with(Vec::new).push(value),
+                "--body-limit" => layer.body_limit = Some(parse(&arg, &value)?),
+                "--upload-limit" => layer.upload_limit = Some(parse(&arg, &value)?),
+                "--broadcast-capacity" => layer.broadcast_capacity = Some(parse(&arg, &value)?),
+                "--log-format" => layer.log_format = Some(parse(&arg, &value)?),
+                "--shutdown-timeout" => layer.shutdown_timeout = Some(parse(&arg, &value)?),
+                "--tls-cert" => layer.tls_cert = Some(PathBuf::from(value)),
+                "--tls-key" => layer.tls_key = Some(PathBuf::from(value)),
+                "--allow-private-webhooks" => {
+                    layer.allow_private_webhooks = Some(parse(&arg, &value)?)
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{arg}: unknown flag"),
+                    ))
+                }
+            }
+        }
+        Ok((layer, file, rest))
+    }
+
+    /// Fills the settings this layer misses from `lower`
+    fn over(self, lower: Layer) -> Layer {
+        Layer {
+            bind: self.bind.or(lower.bind),
+            database: self.database.or(lower.database),
+            cors_origins: self.cors_origins.or(lower.cors_origins),
+            body_limit: self.body_limit.or(lower.body_limit),
+            upload_limit: self.upload_limit.or(lower.upload_limit),
+            broadcast_capacity: self.broadcast_capacity.or(lower.broadcast_capacity),
+            log_format: self.log_format.or(lower.log_format),
+            shutdown_timeout: self.shutdown_timeout.or(lower.shutdown_timeout),
+            tls_cert: self.tls_cert.or(lower.tls_cert),
+            tls_key: self.tls_key.or(lower.tls_key),
+            allow_private_webhooks: self.allow_private_webhooks.or(lower.allow_private_webhooks),
+        }
+    }
+
+    fn resolve(self) -> Config {
+        let default = Config::default();
+        Config {
+            bind: self.bind.unwrap_or(default.bind),
+            database: self.database.unwrap_or(default.database),
+            cors_origins: self.cors_origins.unwrap_or(default.cors_origins),
+            body_limit: self.body_limit.unwrap_or(default.body_limit),
+            upload_limit: self.upload_limit.unwrap_or(default.upload_limit),
+            broadcast_capacity: self
+                .broadcast_capacity
+                .unwrap_or(default.broadcast_capacity),
+            log_format: self.log_format.unwrap_or(default.log_format),
+            shutdown_timeout: self.shutdown_timeout.unwrap_or(default.shutdown_timeout),
+            tls_cert: self.tls_cert.or(default.tls_cert),
+            tls_key: self.tls_key.or(default.tls_key),
+            allow_private_webhooks: self
+                .allow_private_webhooks
+                .unwrap_or(default.allow_private_webhooks),
+        }
+    }
+}
+
+fn parse<T>(name: &str, value: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.parse().map_err(|error| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{name}: {value:?}: {error}"),
+        )
+    })
+}
+
+/// Parses the environment variable `name`, if set
+fn parsed<T>(var: impl Fn(&str) -> Option<String>, name: &str) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    var(name).map(|value| parse(name, &value)).transpose()
+}
+
+fn invalid(setting: &str, reason: impl Display) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("{setting}: {reason}"))
+}
+
+/// Whether `origin` is a scheme and a host, with an optional port, as browsers send them
+fn is_origin(origin: &str) -> bool {
+    let Some((scheme, host)) = origin.split_once("://") else {
+        return false;
+    };
+    matches!(scheme, "http" | "https")
+        && !host.is_empty()
+        && !host.contains(['/', '?', '#', ' '])
+        && origin.parse::<axum::http::HeaderValue>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Result<Layer, Error> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Layer::from_env(|name| vars.get(name).cloned())
+    }
+
+    fn file(text: &str) -> Result<Layer, Error> {
+        let path = std::env::temp_dir().join(format!("discorde-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, text).unwrap();
+        let layer = Layer::from_file(&path);
+        _ = std::fs::remove_file(&path);
+        layer
+    }
+
+    fn validated(config: Config) -> Result<(), String> {
+        config.validate().map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn overrides_file_with_env_and_env_with_flags() {
+        let toml = file(
+            "bind = \"127.0.0.1:1000\"\n\
+             body_limit = 4096\n\
+             upload_limit = 100\n\
+             log_format = \"json\"\n",
+        )
+        .unwrap();
+        let env = env(&[
+            ("DISCORDE_BIND", "127.0.0.1:2000"),
+            ("DISCORDE_BODY_LIMIT", "8192"),
+            (
+                "DISCORDE_CORS_ORIGINS",
+                "https://a.example, https://b.example",
+            ),
+        ])
+        .unwrap();
+        let (cli, config, rest) = Layer::from_args(args(&[
+            "reindex",
+            "--bind",
+            "127.0.0.1:3000",
+            "--config",
+            "other.toml",
+        ]))
+        .unwrap();
+        assert_eq!(config, Some(PathBuf::from("other.toml")));
+        assert_eq!(rest, ["reindex"]);
+
+        let config = cli.over(env).over(toml).resolve();
+        assert_eq!(config.bind, SocketAddr::from(([127, 0, 0, 1], 3000)));
+        assert_eq!(config.body_limit, 8192);
+        assert_eq!(config.upload_limit, 100);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(
+            config.cors_origins,
+            ["https://a.example", "https://b.example"]
+        );
+        // Set nowhere
+        assert_eq!(config.shutdown_timeout, Config::default().shutdown_timeout);
+        assert!(!config.allow_private_webhooks);
+    }
+
+    #[test]
+    fn refuses_malformed_sources() {
+        let error = env(&[("DISCORDE_BODY_LIMIT", "lots")]).err().unwrap();
+        assert!(error.to_string().starts_with("DISCORDE_BODY_LIMIT"));
+        let error = Layer::from_args(args(&["--bind"])).err().unwrap();
+        assert!(error.to_string().contains("expected a value"));
+        let error = Layer::from_args(args(&["--color", "red"])).err().unwrap();
+        assert!(error.to_string().contains("unknown flag"));
+        assert!(file("colour = \"red\"\n").is_err());
+    }
+
+    #[test]
+    fn repeats_cors_origin_flags() {
+        let (cli, _, _) = Layer::from_args(args(&[
+            "--cors-origin",
+            "https://a.example",
+            "--cors-origin",
+            "http://b.example:8080",
+        ]))
+        .unwrap();
+        let config = cli.resolve();
+        assert_eq!(
+            config.cors_origins,
+            ["https://a.example", "http://b.example:8080"]
+        );
+        assert_eq!(validated(config), Ok(()));
+    }
+
+    #[test]
+    fn validates_cors_origins() {
+        let origins = |origins: &[&str]| {
+            validated(Config {
+                cors_origins: args(origins),
+                ..Config::default()
+            })
+        };
+        assert_eq!(origins(&["*"]), Ok(()));
+        assert_eq!(origins(&["https://example.com"]), Ok(()));
+        assert!(origins(&[]).unwrap_err().starts_with("cors_origins"));
+        assert!(origins(&["*", "https://example.com"])
+            .unwrap_err()
+            .starts_with("cors_origins"));
+        for origin in [
+            "example.com",
+            "ftp://example.com",
+            "https://",
+            "https://example.com/",
+            "https://example.com/app",
+            "https://example.com?a",
+        ] {
+            assert!(origins(&[origin]).is_err(), "{origin}");
+        }
+    }
+
+    #[test]
+    fn pairs_tls_cert_and_key() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let tls = |cert: Option<&str>, key: Option<&str>| {
+            validated(Config {
+                tls_cert: cert.map(|cert| fixtures.join(cert)),
+                tls_key: key.map(|key| fixtures.join(key)),
+                ..Config::default()
+            })
+        };
+        assert_eq!(tls(None, None), Ok(()));
+        assert_eq!(tls(Some("cert.pem"), Some("key.pem")), Ok(()));
+        assert!(tls(Some("cert.pem"), None)
+            .unwrap_err()
+            .starts_with("tls_key"));
+        assert!(tls(None, Some("key.pem"))
+            .unwrap_err()
+            .starts_with("tls_cert"));
+        assert!(tls(Some("cert.pem"), Some("missing.pem"))
+            .unwrap_err()
+            .starts_with("tls_key"));
+    }
+}
@@ -0,0 +1,430 @@
+use crate::db::core::Db;
+use crate::db::Database;
+use crate::models::attachment::Attachment;
+use crate::models::bot::{BotToken, BOT_TOKEN_PREFIX};
+use crate::models::chat::{Chat, SYSTEM_AUTHOR};
+use crate::models::hook::IncomingHook;
+use crate::models::mention::Mention;
+use crate::models::user::User;
+use crate::models::webhook::Webhook;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// Who holds the lock of a db
+pub enum LockState {
+    Free,
+    /// Held by a running process, most likely a server
+    Live(String),
+    /// Left behind by a process which is gone
+    Stale(String),
+}
+
+impl LockState {
+    pub async fn of(path: &Path) -> Result<LockState, Error> {
+        let pid = match tokio::fs::read_to_string(path.join("lock")).await {
+            Ok(pid) => pid,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(LockState::Free),
+            Err(error) => return Err(error),
+        };
+        Ok(if pid.trim().parse().is_ok_and(is_alive) {
+            LockState::Live(pid)
+        } else {
+            LockState::Stale(pid)
+        })
+    }
+}
+
+/// Removes the lock of the db at `path` if the process holding it is gone
+///
+/// Returns the pid of that process, `None` if the db wasn't locked.
+pub async fn remove_stale_lock(path: &Path) -> Result<Option<String>, Error> {
+    match LockState::of(path).await? {
+        LockState::Free => Ok(None),
+        LockState::Live(pid) => Err(Error::new(
+            ErrorKind::ResourceBusy,
+            format!("The db is in use (pid = {pid})"),
+        )),
+        LockState::Stale(pid) => {
+            tokio::fs::remove_file(path.join("lock")).await?;
+            Ok(Some(pid))
+        }
+    }
+}
+
+/// Offline access to a db, for maintenance while no server is running
+///
+/// The db stays locked until [`Admin::close`], so a server can't start meanwhile.
+pub struct Admin {
+    db: Db,
+}
+
+impl Admin {
+    /// Locks the existing db at `path`
+    ///
+    /// Unlike a server, this doesn't take over stale locks: they must be removed explicitly.
+    pub async fn open(path: PathBuf) -> Result<Admin, Error> {
+        if !path.is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("No db at {}", path.display()),
+            ));
+        }
+        // Taking the lock fails if it exists, so a server starting meanwhile can't share the db
+        let created = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path.join("lock"))
+            .await;
+        let mut file = match created {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                return Err(match LockState::of(&path).await? {
+                    LockState::Live(pid) => Error::new(
+                        ErrorKind::ResourceBusy,
+                        format!("The db is in use (pid = {pid}), stop the server first"),
+                    ),
+                    LockState::Stale(pid) => Error::new(
+                        ErrorKind::ResourceBusy,
+                        format!("The db was left locked by a dead process (pid = {pid})"),
+                    ),
+                    LockState::Free => {
+                        Error::new(ErrorKind::ResourceBusy, "The db was being locked")
+                    }
+                })
+            }
+            Err(error) => return Err(error),
+        };
+        let written = file
+            .write_all(std::process::id().to_string().as_bytes())
+            .await;
+        if let Err(error) = written {
+            _ = tokio::fs::remove_file(path.join("lock")).await;
+            return Err(error);
+        }
+        // The db sees the lock holds our pid and takes it as its own
+        let mut db = Db::new(path).await?;
+        db.lock().await?;
+        Ok(Admin { db })
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.db.unlock().await
+    }
+
+    /// Every user, sorted by username
+    pub async fn users(&self) -> Result<Vec<User>, Error> {
+        let mut res = vec![];
+        for doc in self.db.clone().collection("users").get().await {
+            if let Some(user) = doc.doc.get::<User>().await? {
+                res.push(user);
+            }
+        }
+        res.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(res)
+    }
+
+    pub async fn create_user(&self, username: String, password: String) -> Result<(), Error> {
+        if username.is_empty()
+            || username == SYSTEM_AUTHOR
+            || username.starts_with(BOT_TOKEN_PREFIX)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{username:?} isn't a valid username"),
+            ));
+        }
+        if Database::find_user(&self.db, username.clone())
+            .await?
+            .is_some()
+        {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{username} already exists"),
+            ));
+        }
+        let user = User {
+            username,
+            password,
+            chats: vec![],
+            settings: Default::default(),
+            bot: None,
+        };
+        self.db
+            .clone()
+            .collection("users")
+            .add(user)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn reset_password(&self, username: &str, password: String) -> Result<(), Error> {
+        let res = Database::edit_user(&self.db, username, |user| {
+            if user.bot.is_some() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{username} is a bot, it has tokens instead of a password"),
+                ));
+            }
+            user.password = password;
+            Ok(Some(true))
+        })
+        .await?;
+        res.map(|_| ()).ok_or_else(|| not_found("user", username))
+    }
+
+    /// Deletes a user, taking them out of their chats along with their tokens and mentions
+    ///
+    /// Their messages stay in the history. Users owning bots can't be deleted before them.
+    pub async fn delete_user(&self, username: &str) -> Result<(), Error> {
+        let user = Database::find_user(&self.db, username.to_string())
+            .await?
+            .ok_or_else(|| not_found("user", username))?;
+        let bots: Vec<String> = self
+            .users()
+            .await?
+            .into_iter()
+            .filter(|u| u.bot.as_ref().is_some_and(|bot| bot.owner == username))
+            .map(|u| u.username)
+            .collect();
+        if !bots.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{username} owns bots, delete them first: {}",
+                    bots.join(", ")
+                ),
+            ));
+        }
+
+        for chat in &user.chats {
+            Database::update_chat(&self.db, chat, |c| {
+                c.members.retain(|m| m != username);
+                c.admins.retain(|m| m != username);
+                c.nicknames.remove(username);
+                Ok(Some(true))
+            })
+            .await?;
+        }
+        for (id, _) in Database::find_bot_tokens(&self.db, "bot", username.to_string()).await? {
+            self.db
+                .clone()
+                .collection("bot_tokens")
+                .doc(&id)
+                .delete()
+                .await?;
+        }
+        for doc in self.db.clone().collection("mentions").get().await {
+            if doc
+                .doc
+                .clone()
+                .get::<Mention>()
+                .await?
+                .is_some_and(|m| m.user == username)
+            {
+                doc.doc.clone().delete().await?;
+            }
+        }
+        for doc in self.db.clone().collection("users").get().await {
+            if doc
+                .doc
+                .clone()
+                .get::<User>()
+                .await?
+                .is_some_and(|u| u.username == username)
+            {
+                doc.doc.clone().delete().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every chat with its id, sorted by name
+    pub async fn chats(&self) -> Result<Vec<(String, Chat)>, Error> {
+        let mut res = vec![];
+        for doc in self.db.clone().collection("chats").get().await {
+            if let Some(chat) = doc.doc.get::<Chat>().await? {
+                res.push((doc.id, chat));
+            }
+        }
+        res.sort_by(|a, b| a.1.name.cmp(&b.1.name).then_with(|| a.0.cmp(&b.0)));
+        Ok(res)
+    }
+
+    pub async fn chat(&self, id: &str) -> Result<Chat, Error> {
+        self.db
+            .clone()
+            .collection("chats")
+            .doc(id)
+            .get::<Chat>()
+            .await?
+            .ok_or_else(|| not_found("chat", id))
+    }
+
+    /// Looks for references the rest of the db can't resolve, and describes each of them
+    pub async fn check(&self) -> Result<Vec<String>, Error> {
+        let mut problems = vec![];
+        let users: BTreeMap<String, User> = self
+            .users()
+            .await?
+            .into_iter()
+            .map(|u| (u.username.clone(), u))
+            .collect();
+        let chats: BTreeMap<String, Chat> = self.chats().await?.into_iter().collect();
+
+        let mut names = BTreeSet::new();
+        for doc in self.db.clone().collection("users").get().await {
+            match doc.doc.get::<User>().await {
+                Ok(Some(user)) if !names.insert(user.username.clone()) => {
+                    problems.push(format!("User {} is stored twice", user.username))
+                }
+                Ok(_) => {}
+                Err(error) => problems.push(format!("User {} can't be read: {error}", doc.id)),
+            }
+        }
+
+        for user in users.values() {
+            for chat in &user.chats {
+                match chats.get(chat) {
+                    None => problems.push(format!(
+                        "User {} is in chat {chat}, which doesn't exist",
+                        user.username
+                    )),
+                    Some(c) if !c.members.contains(&user.username) => problems.push(format!(
+                        "User {} lists chat {chat}, which doesn't list them",
+                        user.username
+                    )),
+                    Some(_) => {}
+                }
+            }
+            if let Some(bot) = &user.bot {
+                if !users.contains_key(&bot.owner) {
+                    problems.push(format!(
+                        "Bot {} belongs to {}, who doesn't exist",
+                        user.username, bot.owner
+                    ));
+                }
+            }
+        }
+
+        for (id, chat) in &chats {
+            for member in &chat.members {
+                match users.get(member) {
+                    None => {
+                        problems.push(format!("Chat {id} has member {member}, who doesn't exist"))
+                    }
+                    Some(user) if !user.chats.contains(id) => {
+                        problems.push(format!("Chat {id} lists {member}, who doesn't list it"))
+                    }
+                    Some(_) => {}
+                }
+            }
+            for admin in &chat.admins {
+                if !chat.members.contains(admin) {
+                    problems.push(format!("Chat {id} has admin {admin}, who isn't a member"));
+                }
+            }
+            let timestamps: BTreeSet<u64> = chat.messages.iter().map(|m| m.timestamp).collect();
+            for pin in &chat.pins {
+                if !timestamps.contains(&pin.message) {
+                    problems.push(format!(
+                        "Chat {id} pins message {}, which doesn't exist",
+                        pin.message
+                    ));
+                }
+            }
+            for message in &chat.messages {
+                if let Some(root) = message.thread.filter(|root| !timestamps.contains(root)) {
+                    problems.push(format!(
+                        "Message {} of chat {id} is in thread {root}, which doesn't exist",
+                        message.timestamp
+                    ));
+                }
+            }
+        }
+
+        for doc in self.db.clone().collection("mentions").get().await {
+            let Some(mention) = doc.doc.get::<Mention>().await? else {
+                continue;
+            };
+            let exists = chats
+                .get(&mention.chat)
+                .is_some_and(|c| c.messages.iter().any(|m| m.timestamp == mention.message));
+            if !exists {
+                problems.push(format!(
+                    "Mention {} is of message {} of chat {}, which doesn't exist",
+                    doc.id, mention.message, mention.chat
+                ));
+            }
+        }
+
+        for doc in self.db.clone().collection("attachments").get().await {
+            let Some(attachment) = doc.doc.get::<Attachment>().await? else {
+                continue;
+            };
+            let hashes = std::iter::once(&attachment.hash).chain(&attachment.thumbnail);
+            for hash in hashes {
+                if !self.db.blob(hash).is_file() {
+                    problems.push(format!("Attachment {} misses blob {hash}", doc.id));
+                }
+            }
+        }
+
+        for doc in self.db.clone().collection("bot_tokens").get().await {
+            let Some(token) = doc.doc.get::<BotToken>().await? else {
+                continue;
+            };
+            if users.get(&token.bot).is_none_or(|u| u.bot.is_none()) {
+                problems.push(format!(
+                    "Token {} belongs to bot {}, which doesn't exist",
+                    doc.id, token.bot
+                ));
+            }
+        }
+        for doc in self.db.clone().collection("hooks").get().await {
+            let Some(hook) = doc.doc.get::<IncomingHook>().await? else {
+                continue;
+            };
+            if !chats.contains_key(&hook.chat) {
+                problems.push(format!(
+                    "Hook {} posts into chat {}, which doesn't exist",
+                    doc.id, hook.chat
+                ));
+            }
+        }
+        for doc in self.db.clone().collection("webhooks").get().await {
+            let Some(webhook) = doc.doc.get::<Webhook>().await? else {
+                continue;
+            };
+            if !chats.contains_key(&webhook.chat) {
+                problems.push(format!(
+                    "Webhook {} follows chat {}, which doesn't exist",
+                    doc.id, webhook.chat
+                ));
+            }
+        }
+
+        Ok(problems)
+    }
+}
+
+fn is_alive(pid: i32) -> bool {
+    // 0 and negative pids stand for process groups, which kill would happily signal
+    if pid <= 0 {
+        return false;
+    }
+    // A process owned by someone else still exists when we can't signal it
+    let exists = unsafe { libc::kill(pid, 0) } == 0
+        || Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    // Killed processes linger as zombies until their parent reaps them
+    let zombie = std::fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|stat| {
+        stat.rsplit_once(')')
+            .is_some_and(|(_, rest)| rest.trim_start().starts_with('Z'))
+    });
+    exists && !zombie
+}
+
+fn not_found(what: &str, id: &str) -> Error {
+    Error::new(ErrorKind::NotFound, format!("No {what} {id}"))
+}
@@ -3,6 +3,7 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json::Map;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::io::{Error, ErrorKind};
@@ -10,7 +11,7 @@ use std::path::PathBuf;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio_stream::wrappers::ReadDirStream;
 use tokio_stream::StreamExt;
-use tracing::{debug, error, warn};
+use tracing::error;
 
 /// Base struct of the database
 ///
@@ -38,11 +39,7 @@ impl Db {
                 let mut pid = String::new();
                 file.read_to_string(&mut pid).await?;
                 let mpid = unsafe { getpid() };
-                if mpid.to_string() == pid {
-                    true
-                } else {
-                    false
-                }
+                mpid.to_string() == pid
             }
             Err(_) => false,
         };
@@ -57,6 +54,58 @@ impl Db {
         Collection::new(self, name)
     }
 
+    /// Path of a file stored at the root of the db, outside any collection
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.base.join(name)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock
+    }
+
+    /// Whether the lock file on disk is still this process's
+    pub async fn holds_lock(&self) -> bool {
+        self.lock
+            && tokio::fs::read_to_string(self.base.join("lock"))
+                .await
+                .is_ok_and(|pid| pid == self.pid)
+    }
+
+    /// Path of a blob of the content-addressed store
+    pub fn blob(&self, hash: &str) -> PathBuf {
+        self.base.join("blobs").join(hash)
+    }
+
+    /// Stores `data` in the content-addressed store and returns its hash
+    ///
+    /// Identical contents are only stored once.
+    pub async fn put_blob(&self, data: &[u8]) -> Result<String, Error> {
+        if !self.lock {
+            return Err(Error::other("You should lock the db"));
+        }
+        let hash = format!("{:x}", Sha256::digest(data));
+        let path = self.blob(&hash);
+        if !path.is_file() {
+            tokio::fs::create_dir_all(self.base.join("blobs")).await?;
+            // Write aside first so a crash never leaves a truncated blob behind its hash
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, data).await?;
+            tokio::fs::rename(tmp, path).await?;
+        }
+        Ok(hash)
+    }
+
+    /// Deletes a blob nothing references anymore
+    pub async fn remove_blob(&self, hash: &str) -> Result<(), Error> {
+        if !self.lock {
+            return Err(Error::other("You should lock the db"));
+        }
+        match tokio::fs::remove_file(self.blob(hash)).await {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
     pub async fn lock(&mut self) -> Result<(), Error> {
         if !self.lock {
             match tokio::fs::File::open(self.base.join("lock")).await {
@@ -68,10 +117,10 @@ impl Db {
                         self.lock = true;
                     } else {
                         if unsafe { kill(pid.parse().unwrap(), 0) } == 0 {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                format!("Another process is locking the db (pid = {})", pid),
-                            ));
+                            return Err(Error::other(format!(
+                                "Another process is locking the db (pid = {})",
+                                pid
+                            )));
                         } else {
                             error!("Seems that a dead process forgot to unlock the db");
                             tokio::fs::File::create(self.base.join("lock"))
@@ -180,7 +229,8 @@ impl Collection {
     }
 
     pub async fn get(&self) -> Vec<IdDocument> {
-        if !self.exist {
+        // The collection may have been created since `exist` was computed
+        if !self.exist && !self.path.is_dir() {
             vec![]
         } else {
             ReadDirStream::new(tokio::fs::read_dir(&self.path).await.unwrap())
@@ -203,7 +253,7 @@ impl Collection {
     async fn mkdir(&self) -> Result<(), Error> {
         if !self.exist {
             if !self.db.lock {
-                return Err(Error::new(ErrorKind::Other, "You should lock the db"));
+                return Err(Error::other("You should lock the db"));
             }
             tokio::fs::create_dir_all(self.path.clone()).await?;
         }
@@ -212,11 +262,11 @@ impl Collection {
 
     pub async fn index<T: Serialize>(&self, data: T) -> Result<(), Error> {
         if !self.db.lock {
-            return Err(Error::new(ErrorKind::Other, "You should lock the db"));
+            return Err(Error::other("You should lock the db"));
         }
         let relative_path = match self.path.strip_prefix(self.db.base.clone()) {
             Ok(p) => Ok(p),
-            Err(e) => Err(Error::new(ErrorKind::Other, e)),
+            Err(e) => Err(Error::other(e)),
         }?;
         let file = tokio::fs::File::open(self.db.base.join("index.json")).await?;
         let mut buf_reader = tokio::io::BufReader::new(file);
@@ -245,7 +295,7 @@ impl Collection {
                             }
                         }
                     },
-                    Err(e) => continue,
+                    Err(_) => continue,
                 }
 
                 new_docs.push((
@@ -256,7 +306,7 @@ impl Collection {
 
             let mut docs = new_docs;
 
-            if docs.len() != 0 {
+            if !docs.is_empty() {
                 docs.sort_by(|a, b| value_cmp(a.1.get(key).unwrap(), b.1.get(key).unwrap()));
                 let a: Vec<Vec<Value>> = docs
                     .iter()
@@ -268,6 +318,9 @@ impl Collection {
                     })
                     .collect();
                 map.insert(key.to_string(), Value::from(a));
+            } else {
+                // The last document having this key is gone
+                map.remove(key);
             }
         }
 
@@ -304,6 +357,7 @@ impl IdDocument {
 #[derive(Clone)]
 pub struct Document {
     path: PathBuf,
+    #[allow(dead_code)]
     pub name: String,
     collection: Collection,
     pub exist: bool,
@@ -328,7 +382,7 @@ impl Document {
 
     pub async fn set_with_index<T: Serialize>(&self, data: T, index: bool) -> Result<(), Error> {
         if !self.collection.db.lock {
-            return Err(Error::new(ErrorKind::Other, "You should lock the db"));
+            return Err(Error::other("You should lock the db"));
         }
         self.collection.mkdir().await?;
         let serialized = serde_json::to_string(&data)?;
@@ -358,9 +412,9 @@ impl Document {
     }
 
     #[allow(dead_code)]
-    pub async fn update<T: Serialize>(&mut self, data: T) -> Result<(), Error>
+    pub async fn update<T>(&mut self, data: T) -> Result<(), Error>
     where
-        T: DeserializeOwned,
+        T: Serialize + DeserializeOwned,
     {
         if self.exist {
             let mut content = self.clone().get::<Map<String, Value>>().await?.unwrap();
@@ -377,13 +431,13 @@ impl Document {
 
     pub async fn delete(&mut self) -> Result<(), Error> {
         if !self.collection.db.lock {
-            return Err(Error::new(ErrorKind::Other, "You should lock the db"));
+            return Err(Error::other("You should lock the db"));
         }
         let data = self.clone().get::<Map<String, Value>>().await?;
         std::fs::remove_file(self.path.clone())?;
         self.exist = false;
-        if data.is_some() {
-            self.collection.index(data.unwrap()).await?;
+        if let Some(data) = data {
+            self.collection.index(data).await?;
         }
         Ok(())
     }
@@ -443,7 +497,7 @@ impl Where {
             .strip_prefix(self.collection.db.base.clone())
         {
             Ok(p) => Ok(p),
-            Err(e) => Err(Error::new(ErrorKind::Other, e)),
+            Err(e) => Err(Error::other(e)),
         }?;
         let file = tokio::fs::File::open(self.collection.db.base.join("index.json")).await?;
         let mut buf_reader = tokio::io::BufReader::new(file);
@@ -469,7 +523,7 @@ impl Where {
             .iter()
             .map(|v| {
                 (
-                    v.as_array().unwrap().get(0).unwrap(),
+                    v.as_array().unwrap().first().unwrap(),
                     v.as_array().unwrap().get(1).unwrap().as_str().unwrap(),
                 )
             })
@@ -483,9 +537,9 @@ impl Where {
                 let r = Where::get_equal(sorted.clone(), value);
                 let itv = r.0..r.0 + r.1;
                 result = Vec::<(&Value, &str)>::with_capacity(sorted.len() - r.1);
-                for i in 0..sorted.len() {
+                for (i, entry) in sorted.iter().enumerate() {
                     if !itv.contains(&i) {
-                        result.push(sorted[i]);
+                        result.push(*entry);
                     }
                 }
             }
@@ -564,11 +618,11 @@ impl Where {
                 (0, 0)
             }
         } else {
-            if value_cmp(v[(bounds.0 + bounds.1 + 1) / 2].0, &val) == Ordering::Less
+            if value_cmp(v[(bounds.0 + bounds.1).div_ceil(2)].0, &val) == Ordering::Less
                 || (!strict
-                    && value_cmp(v[(bounds.0 + bounds.1 + 1) / 2].0, &val) == Ordering::Equal)
+                    && value_cmp(v[(bounds.0 + bounds.1).div_ceil(2)].0, &val) == Ordering::Equal)
             {
-                bounds.0 = (bounds.0 + bounds.1 + 1) / 2;
+                bounds.0 = (bounds.0 + bounds.1).div_ceil(2);
                 Where::get_greater(v, val, strict, Some(bounds))
             } else {
                 bounds.1 = (bounds.0 + bounds.1) / 2;
@@ -1,58 +1,314 @@
-use crate::db::core::{Condition, Db, IdDocument};
-use crate::models::chat::{Chat, Message};
+use crate::db::core::{Condition, Db};
+use crate::db::search::SearchIndex;
+use crate::metrics::{Family, Histogram};
+use crate::models::attachment::{Attachment, AttachmentRef};
+use crate::models::bot::BotToken;
+use crate::models::chat::{
+    Chat, ChatPatch, ChatView, Message, MessageKind, Pin, Retention, MAX_CLOCK_SKEW, MAX_PINS,
+};
+use crate::models::hook::IncomingHook;
+use crate::models::markdown;
+use crate::models::mention::Mention;
+use crate::models::now;
+use crate::models::poll::Poll;
+use crate::models::scheduled::Scheduled;
+use crate::models::settings::{ChatSettings, SettingsPatch};
 use crate::models::user::User;
+use crate::models::webhook::{DeadLetter, Webhook};
 use serde_json::Value;
-use std::io::Error;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Error, ErrorKind};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 use tracing::{error, warn};
 
+pub mod admin;
 mod core;
+mod search;
+
+pub use search::SearchFilter;
 
 enum Request {
     InsertUser(User, oneshot::Sender<Result<(), Error>>),
     GetUser(String, oneshot::Sender<Result<Option<User>, Error>>),
-    GetUsers(oneshot::Sender<Vec<User>>),
+    GetUsers(oneshot::Sender<Result<Vec<User>, Error>>),
     UpdateUser(User, oneshot::Sender<Result<(), Error>>),
     GetChat(String, oneshot::Sender<Result<Option<Chat>, Error>>),
     InsertChat(Chat, oneshot::Sender<Result<String, Error>>),
-    InsertMessage(String, Message, oneshot::Sender<Result<(), Error>>),
+    InsertMessage(String, Message, oneshot::Sender<Result<Message, Error>>),
+    AddReaction(Reaction, oneshot::Sender<Result<Option<bool>, Error>>),
+    RemoveReaction(Reaction, oneshot::Sender<Result<Option<bool>, Error>>),
+    EditMessage(TextUpdate, oneshot::Sender<Result<Option<Message>, Error>>),
+    SetPin(PinUpdate, oneshot::Sender<Result<Option<bool>, Error>>),
+    Vote(Vote, oneshot::Sender<Result<Option<Poll>, Error>>),
+    GetMentions(
+        String,
+        oneshot::Sender<Result<Vec<(String, Mention)>, Error>>,
+    ),
+    ReadMention(String, String, oneshot::Sender<Result<Option<bool>, Error>>),
+    Search(Search, oneshot::Sender<Result<Vec<Hit>, Error>>),
+    InsertAttachment(Upload, oneshot::Sender<Result<AttachmentRef, Error>>),
+    GetAttachment(
+        String,
+        oneshot::Sender<Result<Option<StoredAttachment>, Error>>,
+    ),
+    InsertScheduled(Scheduled, oneshot::Sender<Result<String, Error>>),
+    GetScheduled(oneshot::Sender<Result<Vec<(String, Scheduled)>, Error>>),
+    RemoveScheduled(String, oneshot::Sender<Result<Option<Scheduled>, Error>>),
+    EditChat(
+        String,
+        ChatPatch,
+        oneshot::Sender<Result<Option<Edited>, Error>>,
+    ),
+    SetSettings(
+        String,
+        String,
+        SettingsPatch,
+        oneshot::Sender<Result<Option<ChatSettings>, Error>>,
+    ),
+    ReorderChats(
+        String,
+        Vec<String>,
+        oneshot::Sender<Result<Option<bool>, Error>>,
+    ),
+    SetRetention(
+        String,
+        Retention,
+        oneshot::Sender<Result<Option<bool>, Error>>,
+    ),
+    ReadMessages(
+        String,
+        String,
+        u64,
+        oneshot::Sender<Result<Option<bool>, Error>>,
+    ),
+    ExpireMessages(u64, oneshot::Sender<Result<Vec<Expired>, Error>>),
+    SetMember(MemberUpdate, oneshot::Sender<Result<Option<bool>, Error>>),
+    InsertBotToken(BotToken, oneshot::Sender<Result<String, Error>>),
+    GetBotTokens(
+        String,
+        oneshot::Sender<Result<Vec<(String, BotToken)>, Error>>,
+    ),
+    GetBotByToken(String, oneshot::Sender<Result<Option<User>, Error>>),
+    RemoveBotToken(String, String, oneshot::Sender<Result<Option<bool>, Error>>),
+    InsertHook(IncomingHook, oneshot::Sender<Result<String, Error>>),
+    GetHooks(
+        String,
+        oneshot::Sender<Result<Vec<(String, IncomingHook)>, Error>>,
+    ),
+    GetHookByToken(
+        String,
+        oneshot::Sender<Result<Option<(String, IncomingHook)>, Error>>,
+    ),
+    RemoveHook(String, String, oneshot::Sender<Result<Option<bool>, Error>>),
+    InsertWebhook(Webhook, oneshot::Sender<Result<String, Error>>),
+    GetWebhooks(
+        String,
+        oneshot::Sender<Result<Vec<(String, Webhook)>, Error>>,
+    ),
+    RemoveWebhook(String, String, oneshot::Sender<Result<Option<bool>, Error>>),
+    InsertDeadLetter(DeadLetter, oneshot::Sender<Result<String, Error>>),
+    GetDeadLetters(String, oneshot::Sender<Result<Vec<DeadLetter>, Error>>),
+    SetNick(
+        String,
+        String,
+        Option<String>,
+        oneshot::Sender<Result<Option<bool>, Error>>,
+    ),
+    Ready(oneshot::Sender<bool>),
+    Shutdown(oneshot::Sender<Result<(), Error>>),
 }
 
-pub struct Database(UnboundedSender<Request>);
+impl Request {
+    /// Name of the request in metrics
+    fn kind(&self) -> &'static str {
+        match self {
+            Request::InsertUser(..) => "insert_user",
+            Request::GetUser(..) => "get_user",
+            Request::GetUsers(..) => "get_users",
+            Request::UpdateUser(..) => "update_user",
+            Request::GetChat(..) => "get_chat",
+            Request::InsertChat(..) => "insert_chat",
+            Request::InsertMessage(..) => "insert_message",
+            Request::AddReaction(..) => "add_reaction",
+            Request::RemoveReaction(..) => "remove_reaction",
+            Request::EditMessage(..) => "edit_message",
+            Request::SetPin(..) => "set_pin",
+            Request::Vote(..) => "vote",
+            Request::GetMentions(..) => "get_mentions",
+            Request::ReadMention(..) => "read_mention",
+            Request::Search(..) => "search",
+            Request::InsertAttachment(..) => "insert_attachment",
+            Request::GetAttachment(..) => "get_attachment",
+            Request::InsertScheduled(..) => "insert_scheduled",
+            Request::GetScheduled(..) => "get_scheduled",
+            Request::RemoveScheduled(..) => "remove_scheduled",
+            Request::EditChat(..) => "edit_chat",
+            Request::SetSettings(..) => "set_settings",
+            Request::ReorderChats(..) => "reorder_chats",
+            Request::SetRetention(..) => "set_retention",
+            Request::ReadMessages(..) => "read_messages",
+            Request::ExpireMessages(..) => "expire_messages",
+            Request::SetMember(..) => "set_member",
+            Request::InsertBotToken(..) => "insert_bot_token",
+            Request::GetBotTokens(..) => "get_bot_tokens",
+            Request::GetBotByToken(..) => "get_bot_by_token",
+            Request::RemoveBotToken(..) => "remove_bot_token",
+            Request::InsertHook(..) => "insert_hook",
+            Request::GetHooks(..) => "get_hooks",
+            Request::GetHookByToken(..) => "get_hook_by_token",
+            Request::RemoveHook(..) => "remove_hook",
+            Request::InsertWebhook(..) => "insert_webhook",
+            Request::GetWebhooks(..) => "get_webhooks",
+            Request::RemoveWebhook(..) => "remove_webhook",
+            Request::InsertDeadLetter(..) => "insert_dead_letter",
+            Request::GetDeadLetters(..) => "get_dead_letters",
+            Request::SetNick(..) => "set_nick",
+            Request::Ready(..) => "ready",
+            Request::Shutdown(..) => "shutdown",
+        }
+    }
+}
+
+/// Chat as edited, with a description of each change
+pub struct Edited {
+    pub changes: Vec<String>,
+    pub chat: ChatView,
+}
+
+/// Messages of a chat deleted by its retention policy
+pub struct Expired {
+    pub chat: String,
+    pub messages: BTreeSet<u64>,
+}
+
+pub struct MemberUpdate {
+    pub chat: String,
+    pub user: String,
+    pub member: bool,
+}
+
+pub struct Upload {
+    pub attachment: Attachment,
+    pub data: Vec<u8>,
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+pub struct StoredAttachment {
+    pub attachment: Attachment,
+    pub path: PathBuf,
+    pub thumbnail: Option<PathBuf>,
+}
+
+pub struct Search {
+    pub user: String,
+    pub query: String,
+    pub filter: SearchFilter,
+    pub limit: usize,
+}
+
+pub struct Hit {
+    pub chat: String,
+    pub score: f64,
+    pub message: Message,
+}
+
+pub struct Reaction {
+    pub chat: String,
+    pub message: u64,
+    pub emoji: String,
+    pub user: String,
+}
+
+pub struct TextUpdate {
+    pub chat: String,
+    pub message: u64,
+    pub user: String,
+    pub text: String,
+}
+
+pub struct Vote {
+    pub chat: String,
+    pub message: u64,
+    pub user: String,
+    pub options: Vec<usize>,
+}
+
+pub struct PinUpdate {
+    pub chat: String,
+    pub message: u64,
+    pub user: String,
+    pub pinned: bool,
+}
+
+/// What the worker is up to, for metrics
+#[derive(Default)]
+pub struct DbStats {
+    /// Requests sent but not picked up by the worker yet
+    pub queued: AtomicUsize,
+    /// Time spent serving each kind of request
+    pub latencies: Family<Histogram>,
+}
+
+/// Earliest time a message has to be deleted at, as far as the worker knows
+type Expiry = watch::Sender<Option<u64>>;
+
+pub struct Database(
+    UnboundedSender<Request>,
+    Arc<DbStats>,
+    watch::Receiver<Option<u64>>,
+);
 
 impl Database {
     pub async fn new(path: PathBuf) -> Self {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-        tokio::task::spawn(Self::worker(path, rx));
-        Self(tx)
+        let stats = Arc::new(DbStats::default());
+        let (expiry, next_expiry) = watch::channel(None);
+        tokio::task::spawn(Self::worker(path, rx, stats.clone(), expiry));
+        Self(tx, stats, next_expiry)
     }
 
-    async fn worker(path: PathBuf, mut rx: UnboundedReceiver<Request>) {
+    async fn worker(
+        path: PathBuf,
+        mut rx: UnboundedReceiver<Request>,
+        stats: Arc<DbStats>,
+        expiry: Expiry,
+    ) {
         let mut db = Db::new(path).await.map_err(|error| error!(?error)).unwrap();
         db.lock().await.map_err(|error| error!(?error)).unwrap();
+        let mut search = SearchIndex::load(&db)
+            .await
+            .map_err(|error| error!(?error))
+            .unwrap();
+        match Self::find_next_expiry(&db).await {
+            Ok(next) => _ = expiry.send_replace(next),
+            Err(error) => error!(?error),
+        }
 
+        let mut shutdown = None;
         while let Some(req) = rx.recv().await {
+            stats.queued.fetch_sub(1, Ordering::Relaxed);
+            let kind = req.kind();
+            let start = Instant::now();
             match req {
+                // Requests already queued are still served, new ones are refused
+                Request::Shutdown(reply) => {
+                    rx.close();
+                    shutdown = Some(reply);
+                }
+                Request::Ready(reply) => {
+                    _ = reply.send(db.holds_lock().await);
+                }
                 Request::InsertUser(user, reply) => {
                     let res = db.clone().collection("users").add(user).await.map(|_| ());
                     _ = reply.send(res);
                 }
                 Request::GetUser(id, reply) => {
-                    let res = db
-                        .clone()
-                        .collection("users")
-                        .wherr("username".to_string(), Condition::Equal, Value::String(id))
-                        .await
-                        .unwrap()
-                        .get()
-                        .first()
-                        .cloned();
-                    let res = match res {
-                        None => Ok(None),
-                        Some(doc) => doc.doc.get().await,
-                    };
+                    let res = Self::find_user(&db, id).await;
                     _ = reply.send(res);
                 }
                 Request::GetUsers(reply) => {
@@ -64,7 +320,7 @@ impl Database {
                             Err(error) => error!(?error),
                         }
                     }
-                    _ = reply.send(res);
+                    _ = reply.send(Ok(res));
                 }
                 Request::GetChat(id, reply) => {
                     let res = db.clone().collection("chats").doc(&id).get().await;
@@ -89,67 +345,1360 @@ impl Database {
                         .first()
                         .cloned();
                     let res = match res {
-                        None => continue,
+                        None => Err(Error::new(
+                            ErrorKind::NotFound,
+                            format!("No user {}", user.username),
+                        )),
                         Some(mut doc) => doc.doc.update(user).await,
                     };
 
                     _ = reply.send(res);
                 }
                 Request::InsertMessage(id, message, reply) => {
-                    let res = db.clone().collection("chats").doc(&id).get::<Chat>().await;
-                    let res = match res {
-                        Ok(Some(mut chat)) => {
-                            chat.messages.insert(message);
-                            db.clone().collection("chats").doc(&id).update(chat).await
+                    let res = Self::store_message(&db, &mut search, &expiry, id, message).await;
+                    _ = reply.send(res);
+                }
+                Request::InsertAttachment(upload, reply) => {
+                    let res = Self::store_attachment(&db, upload).await;
+                    _ = reply.send(res);
+                }
+                Request::GetAttachment(id, reply) => {
+                    let res = db
+                        .clone()
+                        .collection("attachments")
+                        .doc(&id)
+                        .get::<Attachment>()
+                        .await
+                        .map(|attachment| {
+                            attachment.map(|attachment| StoredAttachment {
+                                path: db.blob(&attachment.hash),
+                                thumbnail: attachment.thumbnail.as_ref().map(|hash| db.blob(hash)),
+                                attachment,
+                            })
+                        });
+                    _ = reply.send(res);
+                }
+                Request::InsertScheduled(scheduled, reply) => {
+                    let res = db.clone().collection("scheduled").add(scheduled).await;
+                    _ = reply.send(res);
+                }
+                Request::GetScheduled(reply) => {
+                    let mut res = vec![];
+                    for doc in db.clone().collection("scheduled").get().await {
+                        match doc.doc.get::<Scheduled>().await {
+                            Ok(Some(scheduled)) => res.push((doc.id, scheduled)),
+                            Ok(None) => {}
+                            Err(error) => error!(?error),
+                        }
+                    }
+                    _ = reply.send(Ok(res));
+                }
+                Request::RemoveScheduled(id, reply) => {
+                    let mut doc = db.clone().collection("scheduled").doc(&id);
+                    let res = match doc.clone().get::<Scheduled>().await {
+                        Ok(Some(scheduled)) => doc.delete().await.map(|_| Some(scheduled)),
+                        res => res,
+                    };
+                    _ = reply.send(res);
+                }
+                Request::EditChat(id, patch, reply) => {
+                    let mut edited = None;
+                    let res = Self::update_chat(&db, &id, |c| {
+                        let changes = patch.apply(c);
+                        let changed = !changes.is_empty();
+                        edited = Some(Edited {
+                            changes,
+                            chat: c.to_view(id.clone()),
+                        });
+                        Ok(Some(changed))
+                    })
+                    .await
+                    .map(|_| edited);
+                    _ = reply.send(res);
+                }
+                Request::SetRetention(chat, retention, reply) => {
+                    let res = Self::update_chat(&db, &chat, |c| {
+                        let changed = c.retention != retention;
+                        c.retention = retention;
+                        Self::expire_by(&expiry, c.next_expiry());
+                        Ok(Some(changed))
+                    })
+                    .await;
+                    _ = reply.send(res);
+                }
+                Request::ReadMessages(chat, user, timestamp, reply) => {
+                    let res = match Self::update_chat(&db, &chat, |c| {
+                        let changed = c.read(&user, timestamp, now());
+                        Self::expire_by(&expiry, c.next_expiry());
+                        Ok(Some(changed))
+                    })
+                    .await
+                    {
+                        Ok(Some(_)) => {
+                            Self::edit_user(&db, &user, |u| {
+                                let settings = u.settings.entry(chat).or_default();
+                                let changed = timestamp > settings.last_read;
+                                settings.last_read = settings.last_read.max(timestamp);
+                                Ok(Some(changed))
+                            })
+                            .await
+                        }
+                        res => res,
+                    };
+                    _ = reply.send(res);
+                }
+                Request::SetSettings(user, chat, patch, reply) => {
+                    let mut settings = None;
+                    let res = Self::edit_user(&db, &user, |u| {
+                        if !u.chats.contains(&chat) {
+                            return Ok(None);
+                        }
+                        let entry = u.settings.entry(chat).or_default();
+                        patch
+                            .apply(entry, now())
+                            .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+                        settings = Some(entry.clone());
+                        Ok(Some(true))
+                    })
+                    .await
+                    .map(|_| settings);
+                    _ = reply.send(res);
+                }
+                Request::ReorderChats(user, order, reply) => {
+                    let res = Self::edit_user(&db, &user, |u| {
+                        let mut sorted = order.clone();
+                        sorted.sort();
+                        let mut current = u.chats.clone();
+                        current.sort();
+                        if sorted != current {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                "The new order must list every chat exactly once",
+                            ));
+                        }
+                        let changed = u.chats != order;
+                        u.chats = order;
+                        Ok(Some(changed))
+                    })
+                    .await;
+                    _ = reply.send(res);
+                }
+                Request::ExpireMessages(now, reply) => {
+                    let res = Self::delete_expired(&db, &mut search, &expiry, now).await;
+                    _ = reply.send(res);
+                }
+                Request::SetMember(update, reply) => {
+                    let res = Self::store_member(&db, update).await;
+                    _ = reply.send(res);
+                }
+                Request::InsertBotToken(token, reply) => {
+                    let res = db.clone().collection("bot_tokens").add(token).await;
+                    _ = reply.send(res);
+                }
+                Request::GetBotTokens(bot, reply) => {
+                    let res = Self::find_bot_tokens(&db, "bot", bot).await;
+                    _ = reply.send(res);
+                }
+                Request::GetBotByToken(hash, reply) => {
+                    let res = match Self::find_bot_tokens(&db, "hash", hash).await {
+                        Ok(tokens) => match tokens.into_iter().next() {
+                            Some((_, token)) => Self::find_user(&db, token.bot).await,
+                            None => Ok(None),
+                        },
+                        Err(error) => Err(error),
+                    };
+                    _ = reply.send(res);
+                }
+                Request::RemoveBotToken(bot, id, reply) => {
+                    let mut doc = db.clone().collection("bot_tokens").doc(&id);
+                    let res = match doc.clone().get::<BotToken>().await {
+                        Ok(Some(token)) if token.bot == bot => {
+                            doc.delete().await.map(|_| Some(true))
+                        }
+                        Ok(_) => Ok(None),
+                        Err(error) => Err(error),
+                    };
+                    _ = reply.send(res);
+                }
+                Request::InsertHook(hook, reply) => {
+                    let res = db.clone().collection("hooks").add(hook).await;
+                    _ = reply.send(res);
+                }
+                Request::GetHooks(chat, reply) => {
+                    let res = Self::find_hooks(&db, "chat", chat).await;
+                    _ = reply.send(res);
+                }
+                Request::GetHookByToken(token, reply) => {
+                    let res = Self::find_hooks(&db, "token", token)
+                        .await
+                        .map(|hooks| hooks.into_iter().next());
+                    _ = reply.send(res);
+                }
+                Request::RemoveHook(chat, id, reply) => {
+                    let mut doc = db.clone().collection("hooks").doc(&id);
+                    let res = match doc.clone().get::<IncomingHook>().await {
+                        Ok(Some(hook)) if hook.chat == chat => {
+                            doc.delete().await.map(|_| Some(true))
                         }
+                        Ok(_) => Ok(None),
                         Err(error) => Err(error),
-                        Ok(None) => Ok(()),
                     };
                     _ = reply.send(res);
                 }
+                Request::InsertWebhook(webhook, reply) => {
+                    let res = db.clone().collection("webhooks").add(webhook).await;
+                    _ = reply.send(res);
+                }
+                Request::GetWebhooks(chat, reply) => {
+                    let res = Self::find_webhooks(&db, chat).await;
+                    _ = reply.send(res);
+                }
+                Request::RemoveWebhook(chat, id, reply) => {
+                    let mut doc = db.clone().collection("webhooks").doc(&id);
+                    let res = match doc.clone().get::<Webhook>().await {
+                        Ok(Some(webhook)) if webhook.chat == chat => {
+                            doc.delete().await.map(|_| Some(true))
+                        }
+                        Ok(_) => Ok(None),
+                        Err(error) => Err(error),
+                    };
+                    _ = reply.send(res);
+                }
+                Request::InsertDeadLetter(letter, reply) => {
+                    let res = db.clone().collection("dead_letters").add(letter).await;
+                    _ = reply.send(res);
+                }
+                Request::GetDeadLetters(webhook, reply) => {
+                    let res = Self::find_dead_letters(&db, webhook).await;
+                    _ = reply.send(res);
+                }
+                Request::SetNick(chat, user, nick, reply) => {
+                    let res = Self::update_chat(&db, &chat, |c| {
+                        if !c.members.contains(&user) {
+                            return Ok(None);
+                        }
+                        let changed = match nick {
+                            Some(nick) => c.nicknames.insert(user, nick.clone()) != Some(nick),
+                            None => c.nicknames.remove(&user).is_some(),
+                        };
+                        Ok(Some(changed))
+                    })
+                    .await;
+                    _ = reply.send(res);
+                }
+                Request::EditMessage(update, reply) => {
+                    let res = Self::store_edit(&db, &mut search, update).await;
+                    _ = reply.send(res);
+                }
+                Request::AddReaction(reaction, reply) => {
+                    let res = Self::update_message(&db, &reaction.chat, reaction.message, |m| {
+                        m.reactions
+                            .entry(reaction.emoji.clone())
+                            .or_default()
+                            .insert(reaction.user.clone())
+                    })
+                    .await;
+                    _ = reply.send(res);
+                }
+                Request::RemoveReaction(reaction, reply) => {
+                    let res = Self::update_message(&db, &reaction.chat, reaction.message, |m| {
+                        let Some(users) = m.reactions.get_mut(&reaction.emoji) else {
+                            return false;
+                        };
+                        let removed = users.remove(&reaction.user);
+                        if users.is_empty() {
+                            m.reactions.remove(&reaction.emoji);
+                        }
+                        removed
+                    })
+                    .await;
+                    _ = reply.send(res);
+                }
+                Request::GetMentions(user, reply) => {
+                    let res = Self::find_mentions(&db, user).await;
+                    _ = reply.send(res);
+                }
+                Request::ReadMention(user, id, reply) => {
+                    let mut doc = db.clone().collection("mentions").doc(&id);
+                    let res = match doc.clone().get::<Mention>().await {
+                        Ok(Some(mention)) if mention.user == user => {
+                            if mention.read {
+                                Ok(Some(false))
+                            } else {
+                                let mention = Mention {
+                                    read: true,
+                                    ..mention
+                                };
+                                doc.update(mention).await.map(|_| Some(true))
+                            }
+                        }
+                        Ok(_) => Ok(None),
+                        Err(error) => Err(error),
+                    };
+                    _ = reply.send(res);
+                }
+                Request::Search(query, reply) => {
+                    let res = Self::find_messages(&db, &search, query).await;
+                    _ = reply.send(res);
+                }
+                Request::Vote(vote, reply) => {
+                    let res = Self::store_vote(&db, vote).await;
+                    _ = reply.send(res);
+                }
+                Request::SetPin(pin, reply) => {
+                    let res = Self::update_chat(&db, &pin.chat, |c| {
+                        if !c.messages.iter().any(|m| m.timestamp == pin.message) {
+                            return Ok(None);
+                        }
+                        if c.pins.iter().any(|p| p.message == pin.message) == pin.pinned {
+                            return Ok(Some(false));
+                        }
+                        if pin.pinned {
+                            if c.pins.len() >= MAX_PINS {
+                                return Err(Error::new(
+                                    ErrorKind::QuotaExceeded,
+                                    format!("A chat can't have more than {MAX_PINS} pins"),
+                                ));
+                            }
+                            c.pins.push(Pin {
+                                message: pin.message,
+                                by: pin.user,
+                                timestamp: now(),
+                            });
+                        } else {
+                            c.pins.retain(|p| p.message != pin.message);
+                        }
+                        Ok(Some(true))
+                    })
+                    .await;
+                    _ = reply.send(res);
+                }
             }
+            stats
+                .latencies
+                .observe(vec![("request", kind.to_string())], start.elapsed());
+        }
+
+        // The queue is drained, nothing will write to the db anymore
+        let res = Self::close(&mut db, &mut search).await;
+        if let Err(error) = &res {
+            error!(?error);
         }
+        if let Some(reply) = shutdown {
+            _ = reply.send(res);
+        }
+    }
+
+    /// Flushes what is only kept in memory and releases the db for other processes
+    async fn close(db: &mut Db, search: &mut SearchIndex) -> Result<(), Error> {
+        search.save(db).await?;
+        db.unlock().await
+    }
+
+    async fn store_message(
+        db: &Db,
+        search: &mut SearchIndex,
+        expiry: &Expiry,
+        id: String,
+        mut message: Message,
+    ) -> Result<Message, Error> {
+        let Some(mut chat) = db
+            .clone()
+            .collection("chats")
+            .doc(&id)
+            .get::<Chat>()
+            .await?
+        else {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Chat {id} doesn't exist"),
+            ));
+        };
+
+        if message.kind != MessageKind::User {
+            // Several events can happen within the same millisecond
+            while chat.messages.contains(&Message::at(message.timestamp)) {
+                message.timestamp += 1;
+            }
+        } else if !chat.members.contains(&message.author) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} isn't a member of {id}", message.author),
+            ));
+        }
+        // Messages are keyed by timestamp, users choose theirs so they must pick another
+        if chat.messages.contains(&Message::at(message.timestamp)) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("There already is a message at {}", message.timestamp),
+            ));
+        }
+        if message.kind == MessageKind::User && message.timestamp.abs_diff(now()) > MAX_CLOCK_SKEW {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The time of the message is more than a minute off, check your clock",
+            ));
+        }
+
+        message.rich = markdown::parse(&message.message)
+            .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+        if let Some(poll) = &mut message.poll {
+            poll.validate(now())
+                .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+        }
+        match message.kind {
+            MessageKind::User => {
+                message.nick = chat.nicknames.get(&message.author).cloned();
+                chat.resolve_mentions(&mut message);
+            }
+            // Hooks come with their own name
+            MessageKind::Webhook => chat.resolve_mentions(&mut message),
+            MessageKind::System => message.nick = None,
+        }
+        chat.resolve_references(&mut message)
+            .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+        Self::resolve_attachments(db, &id, &mut message).await?;
+
+        // Only reading the message starts its countdown
+        message.expires_at = None;
+        message.edited_at = None;
+        Self::expire_by(expiry, chat.retention.expires_at(&message));
+        chat.messages.insert(message.clone());
+        db.clone().collection("chats").doc(&id).update(chat).await?;
+
+        Self::record_mentions(db, &id, &message).await;
+        search.insert(&id, &message);
+        if let Err(error) = search.save_batch(db).await {
+            error!(?error);
+        }
+        Ok(message)
+    }
+
+    /// Replaces the text of a message, which only its author may do
+    ///
+    /// Returns the message as edited, or `None` when there is no such message.
+    async fn store_edit(
+        db: &Db,
+        search: &mut SearchIndex,
+        update: TextUpdate,
+    ) -> Result<Option<Message>, Error> {
+        let rich = markdown::parse(&update.text)
+            .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+        let mut edited = None;
+        Self::update_chat(db, &update.chat, |c| {
+            let Some(mut message) = c
+                .messages
+                .iter()
+                .find(|m| m.timestamp == update.message)
+                .cloned()
+            else {
+                return Ok(None);
+            };
+            if message.kind != MessageKind::User || message.author != update.user {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Only the author of a message can edit it",
+                ));
+            }
+            message.message = update.text;
+            message.rich = rich;
+            message.edited_at = Some(now());
+            edited = Some(message.clone());
+            c.messages.replace(message);
+            Ok(Some(true))
+        })
+        .await?;
+
+        if let Some(message) = &edited {
+            search.insert(&update.chat, message);
+            if let Err(error) = search.save_batch(db).await {
+                error!(?error);
+            }
+        }
+        Ok(edited)
+    }
+
+    /// Records a ballot, returns the poll as updated or `None` when there is no such poll
+    async fn store_vote(db: &Db, vote: Vote) -> Result<Option<Poll>, Error> {
+        let mut updated = None;
+        Self::update_chat(db, &vote.chat, |c| {
+            let Some(mut message) = c
+                .messages
+                .iter()
+                .find(|m| m.timestamp == vote.message)
+                .cloned()
+            else {
+                return Ok(None);
+            };
+            let Some(poll) = &mut message.poll else {
+                return Ok(None);
+            };
+            if poll.is_closed(now()) {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "This poll is closed",
+                ));
+            }
+            let changed = poll
+                .vote(&vote.user, &vote.options)
+                .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+            updated = Some(poll.clone());
+            if changed {
+                c.messages.replace(message);
+            }
+            Ok(Some(changed))
+        })
+        .await?;
+        Ok(updated)
+    }
+
+    /// Adds or removes a member, keeping the chat list of the user in sync
+    ///
+    /// Returns `None` when either the chat or the user doesn't exist.
+    async fn store_member(db: &Db, update: MemberUpdate) -> Result<Option<bool>, Error> {
+        let Some(doc) = db
+            .clone()
+            .collection("users")
+            .wherr(
+                "username".to_string(),
+                Condition::Equal,
+                Value::String(update.user.clone()),
+            )
+            .await?
+            .get()
+            .first()
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        let Some(mut user) = doc.doc.clone().get::<User>().await? else {
+            return Ok(None);
+        };
+
+        let res = Self::update_chat(db, &update.chat, |c| {
+            let is_member = c.members.contains(&update.user);
+            if is_member == update.member {
+                return Ok(Some(false));
+            }
+            if update.member {
+                c.members.push(update.user.clone());
+            } else {
+                c.members.retain(|m| *m != update.user);
+                c.admins.retain(|m| *m != update.user);
+            }
+            Ok(Some(true))
+        })
+        .await?;
+
+        if res == Some(true) {
+            if update.member {
+                user.chats.push(update.chat);
+            } else {
+                user.chats.retain(|c| *c != update.chat);
+                user.settings.remove(&update.chat);
+            }
+            doc.doc.clone().update(user).await?;
+        }
+        Ok(res)
+    }
+
+    /// Fills the attachment references of a message, they must have been uploaded to its chat
+    async fn resolve_attachments(db: &Db, chat: &str, message: &mut Message) -> Result<(), Error> {
+        for reference in message.attachments.iter_mut() {
+            let attachment = db
+                .clone()
+                .collection("attachments")
+                .doc(&reference.id)
+                .get::<Attachment>()
+                .await?
+                .filter(|attachment| attachment.chat == chat)
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Attachment {} doesn't exist", reference.id),
+                    )
+                })?;
+            *reference = attachment.into_ref(reference.id.clone());
+        }
+        Ok(())
+    }
+
+    async fn store_attachment(db: &Db, upload: Upload) -> Result<AttachmentRef, Error> {
+        let mut attachment = upload.attachment;
+        attachment.hash = db.put_blob(&upload.data).await?;
+        attachment.thumbnail = match upload.thumbnail {
+            Some(thumbnail) => Some(db.put_blob(&thumbnail).await?),
+            None => None,
+        };
+        let id = db
+            .clone()
+            .collection("attachments")
+            .add(attachment.clone())
+            .await?;
+        Ok(attachment.into_ref(id))
+    }
+
+    /// Resolves index hits to messages of chats `query.user` is a member of
+    async fn find_messages(db: &Db, index: &SearchIndex, query: Search) -> Result<Vec<Hit>, Error> {
+        let mut chats = HashMap::<String, Option<Chat>>::new();
+        let mut res = vec![];
+        for (chat, timestamp, score) in index.search(&query.query, &query.filter) {
+            if res.len() >= query.limit {
+                break;
+            }
+            if !chats.contains_key(&chat) {
+                let c = db
+                    .clone()
+                    .collection("chats")
+                    .doc(&chat)
+                    .get::<Chat>()
+                    .await?;
+                let c = c.filter(|c| c.members.contains(&query.user));
+                chats.insert(chat.clone(), c);
+            }
+            let message = chats[&chat]
+                .as_ref()
+                .and_then(|c| c.messages.iter().find(|m| m.timestamp == timestamp));
+            if let Some(message) = message {
+                res.push(Hit {
+                    chat,
+                    score,
+                    message: message.clone(),
+                });
+            }
+        }
+        Ok(res)
+    }
+
+    /// Rebuilds the search index of the db at `path` from every stored message
+    ///
+    /// Meant to run offline, it fails if a server currently holds the db.
+    pub async fn reindex(path: PathBuf) -> Result<usize, Error> {
+        let mut db = Db::new(path).await?;
+        db.lock().await?;
+
+        let mut search = SearchIndex::default();
+        let mut count = 0;
+        for doc in db.clone().collection("chats").get().await {
+            if let Some(chat) = doc.doc.get::<Chat>().await? {
+                for message in &chat.messages {
+                    search.insert(&doc.id, message);
+                    count += 1;
+                }
+            }
+        }
+        search.save(&db).await?;
+
+        db.unlock().await?;
+        Ok(count)
+    }
+
+    async fn record_mentions(db: &Db, chat: &str, message: &Message) {
+        let mentions = db.clone().collection("mentions");
+        for user in &message.mentions {
+            let mention = Mention {
+                user: user.clone(),
+                chat: chat.to_string(),
+                message: message.timestamp,
+                author: message.author.clone(),
+                text: message.message.clone(),
+                read: false,
+            };
+            if let Err(error) = mentions.add(mention).await {
+                error!(?error);
+            }
+        }
+    }
+
+    /// Deletes the messages retention policies say are expired
+    ///
+    /// Quotes of them are emptied and their attachments deleted, so nothing of them is left.
+    async fn delete_expired(
+        db: &Db,
+        search: &mut SearchIndex,
+        expiry: &Expiry,
+        now: u64,
+    ) -> Result<Vec<Expired>, Error> {
+        let mut res = vec![];
+        let mut next = None;
+        for doc in db.clone().collection("chats").get().await {
+            let Some(mut chat) = doc.doc.clone().get::<Chat>().await? else {
+                continue;
+            };
+            let expired: Vec<Message> = chat
+                .messages
+                .iter()
+                .filter(|m| chat.retention.is_expired(m, now))
+                .cloned()
+                .collect();
+            let timestamps: BTreeSet<u64> = expired.iter().map(|m| m.timestamp).collect();
+            chat.messages.retain(|m| !timestamps.contains(&m.timestamp));
+            next = next.into_iter().chain(chat.next_expiry()).min();
+            if expired.is_empty() {
+                continue;
+            }
+
+            let quoting: Vec<Message> = chat
+                .messages
+                .iter()
+                .filter(|m| {
+                    m.reply_to
+                        .as_ref()
+                        .is_some_and(|q| timestamps.contains(&q.timestamp))
+                })
+                .cloned()
+                .collect();
+            for mut message in quoting {
+                if let Some(quote) = &mut message.reply_to {
+                    quote.author.clear();
+                    quote.message.clear();
+                }
+                chat.messages.replace(message);
+            }
+            chat.pins.retain(|p| !timestamps.contains(&p.message));
+            doc.doc.clone().update(chat).await?;
+
+            for timestamp in &timestamps {
+                search.remove(&doc.id, *timestamp);
+            }
+            Self::forget_mentions(db, &doc.id, &timestamps).await;
+            let attachments = expired.into_iter().flat_map(|m| m.attachments);
+            Self::forget_attachments(db, attachments.map(|a| a.id)).await;
+            res.push(Expired {
+                chat: doc.id,
+                messages: timestamps,
+            });
+        }
+        expiry.send_replace(next);
+
+        if let Err(error) = search.save_batch(db).await {
+            error!(?error);
+        }
+        Ok(res)
+    }
+
+    /// Earliest time a message of any chat has to be deleted at
+    async fn find_next_expiry(db: &Db) -> Result<Option<u64>, Error> {
+        let mut next = None;
+        for doc in db.clone().collection("chats").get().await {
+            if let Some(chat) = doc.doc.get::<Chat>().await? {
+                next = next.into_iter().chain(chat.next_expiry()).min();
+            }
+        }
+        Ok(next)
+    }
+
+    /// Brings the next expiry forward to `at` if it comes sooner
+    fn expire_by(expiry: &Expiry, at: Option<u64>) {
+        let Some(at) = at else {
+            return;
+        };
+        expiry.send_if_modified(|next| {
+            let sooner = next.is_none_or(|next| at < next);
+            if sooner {
+                *next = Some(at);
+            }
+            sooner
+        });
+    }
+
+    /// Deletes attachments of deleted messages, and their files unless other uploads share them
+    async fn forget_attachments(db: &Db, ids: impl Iterator<Item = String>) {
+        let mut hashes = BTreeSet::new();
+        for id in ids {
+            let mut doc = db.clone().collection("attachments").doc(&id);
+            match doc.clone().get::<Attachment>().await {
+                Ok(Some(attachment)) => {
+                    if let Err(error) = doc.delete().await {
+                        error!(?error);
+                        continue;
+                    }
+                    hashes.insert(attachment.hash);
+                    hashes.extend(attachment.thumbnail);
+                }
+                Ok(None) => {}
+                Err(error) => error!(?error),
+            }
+        }
+
+        for hash in hashes {
+            let mut shared = false;
+            for key in ["hash", "thumbnail"] {
+                match db
+                    .clone()
+                    .collection("attachments")
+                    .wherr(
+                        key.to_string(),
+                        Condition::Equal,
+                        Value::String(hash.clone()),
+                    )
+                    .await
+                {
+                    Ok(docs) => shared |= !docs.get().is_empty(),
+                    Err(error) => {
+                        error!(?error);
+                        shared = true;
+                    }
+                }
+            }
+            if !shared {
+                if let Err(error) = db.remove_blob(&hash).await {
+                    error!(?error);
+                }
+            }
+        }
+    }
+
+    /// Deletes the mentions made by deleted messages, they hold a copy of the text
+    async fn forget_mentions(db: &Db, chat: &str, messages: &BTreeSet<u64>) {
+        let docs = match db
+            .clone()
+            .collection("mentions")
+            .wherr(
+                "chat".to_string(),
+                Condition::Equal,
+                Value::String(chat.to_string()),
+            )
+            .await
+        {
+            Ok(docs) => docs.get(),
+            Err(error) => {
+                error!(?error);
+                return;
+            }
+        };
+        for doc in docs {
+            match doc.doc.clone().get::<Mention>().await {
+                Ok(Some(mention)) if messages.contains(&mention.message) => {
+                    if let Err(error) = doc.doc.clone().delete().await {
+                        error!(?error);
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => error!(?error),
+            }
+        }
+    }
+
+    /// Mentions of `user` with their ids, oldest first
+    async fn find_mentions(db: &Db, user: String) -> Result<Vec<(String, Mention)>, Error> {
+        let docs = db
+            .clone()
+            .collection("mentions")
+            .wherr("user".to_string(), Condition::Equal, Value::String(user))
+            .await?
+            .get();
+        let mut res = vec![];
+        for doc in docs {
+            if let Some(mention) = doc.doc.get::<Mention>().await? {
+                res.push((doc.id, mention));
+            }
+        }
+        res.sort_by_key(|(_, mention)| mention.message);
+        Ok(res)
+    }
+
+    async fn find_user(db: &Db, username: String) -> Result<Option<User>, Error> {
+        let doc = db
+            .clone()
+            .collection("users")
+            .wherr(
+                "username".to_string(),
+                Condition::Equal,
+                Value::String(username),
+            )
+            .await?
+            .get()
+            .first()
+            .cloned();
+        match doc {
+            None => Ok(None),
+            Some(doc) => doc.doc.get().await,
+        }
+    }
+
+    /// Bot tokens whose `key` is `value`, oldest first
+    async fn find_bot_tokens(
+        db: &Db,
+        key: &str,
+        value: String,
+    ) -> Result<Vec<(String, BotToken)>, Error> {
+        let docs = db
+            .clone()
+            .collection("bot_tokens")
+            .wherr(key.to_string(), Condition::Equal, Value::String(value))
+            .await?
+            .get();
+        let mut res = vec![];
+        for doc in docs {
+            if let Some(token) = doc.doc.get::<BotToken>().await? {
+                res.push((doc.id, token));
+            }
+        }
+        res.sort_by_key(|(_, token)| token.created_at);
+        Ok(res)
+    }
+
+    /// Incoming hooks whose `key` is `value`, oldest first
+    async fn find_hooks(
+        db: &Db,
+        key: &str,
+        value: String,
+    ) -> Result<Vec<(String, IncomingHook)>, Error> {
+        let docs = db
+            .clone()
+            .collection("hooks")
+            .wherr(key.to_string(), Condition::Equal, Value::String(value))
+            .await?
+            .get();
+        let mut res = vec![];
+        for doc in docs {
+            if let Some(hook) = doc.doc.get::<IncomingHook>().await? {
+                res.push((doc.id, hook));
+            }
+        }
+        res.sort_by_key(|(_, hook)| hook.timestamp);
+        Ok(res)
+    }
+
+    async fn find_webhooks(db: &Db, chat: String) -> Result<Vec<(String, Webhook)>, Error> {
+        let docs = db
+            .clone()
+            .collection("webhooks")
+            .wherr("chat".to_string(), Condition::Equal, Value::String(chat))
+            .await?
+            .get();
+        let mut res = vec![];
+        for doc in docs {
+            if let Some(webhook) = doc.doc.get::<Webhook>().await? {
+                res.push((doc.id, webhook));
+            }
+        }
+        res.sort_by_key(|(_, webhook)| webhook.timestamp);
+        Ok(res)
+    }
+
+    /// Deliveries to `webhook` that were given up on, oldest first
+    async fn find_dead_letters(db: &Db, webhook: String) -> Result<Vec<DeadLetter>, Error> {
+        let docs = db
+            .clone()
+            .collection("dead_letters")
+            .wherr(
+                "webhook".to_string(),
+                Condition::Equal,
+                Value::String(webhook),
+            )
+            .await?
+            .get();
+        let mut res = vec![];
+        for doc in docs {
+            if let Some(letter) = doc.doc.get::<DeadLetter>().await? {
+                res.push(letter);
+            }
+        }
+        res.sort_by_key(|letter| letter.timestamp);
+        Ok(res)
+    }
+
+    /// Applies `f` to a stored chat and writes it back if `f` reports a change
+    ///
+    /// Returns `None` when the chat doesn't exist, or when `f` says so.
+    async fn update_chat<F>(db: &Db, chat: &str, f: F) -> Result<Option<bool>, Error>
+    where
+        F: FnOnce(&mut Chat) -> Result<Option<bool>, Error>,
+    {
+        let Some(mut c) = db
+            .clone()
+            .collection("chats")
+            .doc(chat)
+            .get::<Chat>()
+            .await?
+        else {
+            return Ok(None);
+        };
+        let res = f(&mut c)?;
+        if res == Some(true) {
+            db.clone().collection("chats").doc(chat).update(c).await?;
+        }
+        Ok(res)
+    }
+
+    /// Applies `f` to a stored user and writes it back if `f` reports a change
+    ///
+    /// Returns `None` when the user doesn't exist, or when `f` says so.
+    async fn edit_user<F>(db: &Db, username: &str, f: F) -> Result<Option<bool>, Error>
+    where
+        F: FnOnce(&mut User) -> Result<Option<bool>, Error>,
+    {
+        let Some(doc) = db
+            .clone()
+            .collection("users")
+            .wherr(
+                "username".to_string(),
+                Condition::Equal,
+                Value::String(username.to_string()),
+            )
+            .await?
+            .get()
+            .first()
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        let Some(mut user) = doc.doc.clone().get::<User>().await? else {
+            return Ok(None);
+        };
+        let res = f(&mut user)?;
+        if res == Some(true) {
+            doc.doc.clone().update(user).await?;
+        }
+        Ok(res)
+    }
+
+    /// Applies `f` to a stored message and writes the chat back if `f` reports a change
+    ///
+    /// Returns `None` when either the chat or the message doesn't exist.
+    async fn update_message<F>(
+        db: &Db,
+        chat: &str,
+        timestamp: u64,
+        f: F,
+    ) -> Result<Option<bool>, Error>
+    where
+        F: FnOnce(&mut Message) -> bool,
+    {
+        Self::update_chat(db, chat, |c| {
+            let Some(mut message) = c
+                .messages
+                .iter()
+                .find(|m| m.timestamp == timestamp)
+                .cloned()
+            else {
+                return Ok(None);
+            };
+            if !f(&mut message) {
+                return Ok(Some(false));
+            }
+            c.messages.replace(message);
+            Ok(Some(true))
+        })
+        .await
+    }
+
+    fn send(&self, request: Request) {
+        self.1.queued.fetch_add(1, Ordering::Relaxed);
+        if self.0.send(request).is_err() {
+            self.1.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> &DbStats {
+        &self.1
+    }
+
+    /// Earliest time a message has to be deleted at, updated as it changes
+    pub fn next_expiry(&self) -> watch::Receiver<Option<u64>> {
+        self.2.clone()
+    }
+
+    /// Whether the worker answers and still holds the lock of the db
+    pub async fn ready(&self) -> bool {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::Ready(tx));
+        rx.await.unwrap_or(false)
+    }
+
+    /// Serves the requests already queued, then flushes and unlocks the db
+    ///
+    /// Later requests are refused, so this must be the last use of the db.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::Shutdown(tx));
+        reply(rx).await
     }
 
     pub async fn insert_user(&self, user: User) -> Result<(), Error> {
         let (tx, rx) = oneshot::channel();
-        _ = self.0.send(Request::InsertUser(user, tx));
-        rx.await.map_err(|error| error!(?error)).unwrap()
+        self.send(Request::InsertUser(user, tx));
+        reply(rx).await
     }
 
     pub async fn update_user(&self, user: User) -> Result<(), Error> {
         let (tx, rx) = oneshot::channel();
-        _ = self.0.send(Request::UpdateUser(user, tx));
-        rx.await.map_err(|error| error!(?error)).unwrap()
+        self.send(Request::UpdateUser(user, tx));
+        reply(rx).await
     }
 
     pub async fn get_user(&self, user: String) -> Result<Option<User>, Error> {
         let (tx, rx) = oneshot::channel();
-        _ = self.0.send(Request::GetUser(user, tx));
-        rx.await.map_err(|error| error!(?error)).unwrap()
+        self.send(Request::GetUser(user, tx));
+        reply(rx).await
     }
 
-    pub async fn get_users(&self) -> Vec<User> {
+    pub async fn get_users(&self) -> Result<Vec<User>, Error> {
         let (tx, rx) = oneshot::channel();
-        _ = self.0.send(Request::GetUsers(tx));
-        rx.await.map_err(|error| error!(?error)).unwrap()
+        self.send(Request::GetUsers(tx));
+        reply(rx).await
     }
 
     pub async fn get_chat(&self, chat: String) -> Result<Option<Chat>, Error> {
         let (tx, rx) = oneshot::channel();
-        _ = self.0.send(Request::GetChat(chat, tx));
-        rx.await.map_err(|error| error!(?error)).unwrap()
+        self.send(Request::GetChat(chat, tx));
+        reply(rx).await
     }
 
     pub async fn insert_chat(&self, chat: Chat) -> Result<String, Error> {
         let (tx, rx) = oneshot::channel();
-        _ = self.0.send(Request::InsertChat(chat, tx));
-        rx.await.map_err(|error| error!(?error)).unwrap()
+        self.send(Request::InsertChat(chat, tx));
+        reply(rx).await
+    }
+
+    pub async fn insert_message(&self, chat: String, message: Message) -> Result<Message, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::InsertMessage(chat, message, tx));
+        reply(rx).await
+    }
+
+    pub async fn add_reaction(&self, reaction: Reaction) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::AddReaction(reaction, tx));
+        reply(rx).await
     }
 
-    pub async fn insert_message(&self, chat: String, message: Message) -> Result<(), Error> {
+    pub async fn remove_reaction(&self, reaction: Reaction) -> Result<Option<bool>, Error> {
         let (tx, rx) = oneshot::channel();
-        _ = self.0.send(Request::InsertMessage(chat, message, tx));
-        rx.await.map_err(|error| error!(?error)).unwrap()
+        self.send(Request::RemoveReaction(reaction, tx));
+        reply(rx).await
     }
+
+    /// Changes the text of a message, returns it as edited or `None` when there is no such message
+    pub async fn edit_message(&self, update: TextUpdate) -> Result<Option<Message>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::EditMessage(update, tx));
+        reply(rx).await
+    }
+
+    pub async fn vote(&self, vote: Vote) -> Result<Option<Poll>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::Vote(vote, tx));
+        reply(rx).await
+    }
+
+    pub async fn insert_scheduled(&self, scheduled: Scheduled) -> Result<String, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::InsertScheduled(scheduled, tx));
+        reply(rx).await
+    }
+
+    /// Every message waiting to be sent, across all chats
+    pub async fn get_scheduled(&self) -> Result<Vec<(String, Scheduled)>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetScheduled(tx));
+        reply(rx).await
+    }
+
+    pub async fn remove_scheduled(&self, id: String) -> Result<Option<Scheduled>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::RemoveScheduled(id, tx));
+        reply(rx).await
+    }
+
+    pub async fn edit_chat(&self, chat: String, patch: ChatPatch) -> Result<Option<Edited>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::EditChat(chat, patch, tx));
+        reply(rx).await
+    }
+
+    pub async fn set_retention(
+        &self,
+        chat: String,
+        retention: Retention,
+    ) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::SetRetention(chat, retention, tx));
+        reply(rx).await
+    }
+
+    /// Marks the messages of a chat up to `timestamp` as read by `user`
+    pub async fn read_messages(
+        &self,
+        chat: String,
+        user: String,
+        timestamp: u64,
+    ) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::ReadMessages(chat, user, timestamp, tx));
+        reply(rx).await
+    }
+
+    /// Changes the settings of `user` for one of their chats, returns them as updated
+    pub async fn set_settings(
+        &self,
+        user: String,
+        chat: String,
+        patch: SettingsPatch,
+    ) -> Result<Option<ChatSettings>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::SetSettings(user, chat, patch, tx));
+        reply(rx).await
+    }
+
+    pub async fn reorder_chats(
+        &self,
+        user: String,
+        order: Vec<String>,
+    ) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::ReorderChats(user, order, tx));
+        reply(rx).await
+    }
+
+    pub async fn expire_messages(&self, now: u64) -> Result<Vec<Expired>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::ExpireMessages(now, tx));
+        reply(rx).await
+    }
+
+    pub async fn set_pin(&self, pin: PinUpdate) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::SetPin(pin, tx));
+        reply(rx).await
+    }
+
+    pub async fn get_mentions(&self, user: String) -> Result<Vec<(String, Mention)>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetMentions(user, tx));
+        reply(rx).await
+    }
+
+    pub async fn read_mention(&self, user: String, id: String) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::ReadMention(user, id, tx));
+        reply(rx).await
+    }
+
+    pub async fn search(&self, search: Search) -> Result<Vec<Hit>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::Search(search, tx));
+        reply(rx).await
+    }
+
+    pub async fn insert_attachment(&self, upload: Upload) -> Result<AttachmentRef, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::InsertAttachment(upload, tx));
+        reply(rx).await
+    }
+
+    pub async fn get_attachment(&self, id: String) -> Result<Option<StoredAttachment>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetAttachment(id, tx));
+        reply(rx).await
+    }
+
+    pub async fn set_member(&self, update: MemberUpdate) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::SetMember(update, tx));
+        reply(rx).await
+    }
+
+    pub async fn insert_bot_token(&self, token: BotToken) -> Result<String, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::InsertBotToken(token, tx));
+        reply(rx).await
+    }
+
+    pub async fn get_bot_tokens(&self, bot: String) -> Result<Vec<(String, BotToken)>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetBotTokens(bot, tx));
+        reply(rx).await
+    }
+
+    /// Bot a token was issued to, looked up by the hash of the token
+    pub async fn get_bot_by_token(&self, hash: String) -> Result<Option<User>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetBotByToken(hash, tx));
+        reply(rx).await
+    }
+
+    pub async fn remove_bot_token(&self, bot: String, id: String) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::RemoveBotToken(bot, id, tx));
+        reply(rx).await
+    }
+
+    pub async fn insert_hook(&self, hook: IncomingHook) -> Result<String, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::InsertHook(hook, tx));
+        reply(rx).await
+    }
+
+    /// Incoming hooks of a chat, oldest first
+    pub async fn get_hooks(&self, chat: String) -> Result<Vec<(String, IncomingHook)>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetHooks(chat, tx));
+        reply(rx).await
+    }
+
+    pub async fn get_hook_by_token(
+        &self,
+        token: String,
+    ) -> Result<Option<(String, IncomingHook)>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetHookByToken(token, tx));
+        reply(rx).await
+    }
+
+    pub async fn remove_hook(&self, chat: String, id: String) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::RemoveHook(chat, id, tx));
+        reply(rx).await
+    }
+
+    pub async fn insert_webhook(&self, webhook: Webhook) -> Result<String, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::InsertWebhook(webhook, tx));
+        reply(rx).await
+    }
+
+    /// Webhooks registered on a chat, oldest first
+    pub async fn get_webhooks(&self, chat: String) -> Result<Vec<(String, Webhook)>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetWebhooks(chat, tx));
+        reply(rx).await
+    }
+
+    pub async fn remove_webhook(&self, chat: String, id: String) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::RemoveWebhook(chat, id, tx));
+        reply(rx).await
+    }
+
+    pub async fn insert_dead_letter(&self, letter: DeadLetter) -> Result<String, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::InsertDeadLetter(letter, tx));
+        reply(rx).await
+    }
+
+    pub async fn get_dead_letters(&self, webhook: String) -> Result<Vec<DeadLetter>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::GetDeadLetters(webhook, tx));
+        reply(rx).await
+    }
+
+    pub async fn set_nick(
+        &self,
+        chat: String,
+        user: String,
+        nick: Option<String>,
+    ) -> Result<Option<bool>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::SetNick(chat, user, nick, tx));
+        reply(rx).await
+    }
+}
+
+/// Answer of the worker to a request, an error once it has shut down
+async fn reply<T>(rx: oneshot::Receiver<Result<T, Error>>) -> Result<T, Error> {
+    rx.await
+        .unwrap_or_else(|_| Err(Error::other("The database is shut down")))
 }
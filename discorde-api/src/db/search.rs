@@ -0,0 +1,172 @@
+use crate::db::core::Db;
+use crate::models::chat::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Error;
+use tokio::io::AsyncWriteExt;
+
+const INDEX_FILE: &str = "search.json";
+/// Changes kept in memory only before the index is written back
+const SAVE_EVERY: usize = 100;
+
+/// Inverted index over message bodies
+///
+/// Messages are identified by their chat and timestamp, like everywhere else.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SearchIndex {
+    /// term -> chat -> timestamp -> occurrences
+    terms: HashMap<String, HashMap<String, BTreeMap<u64, u32>>>,
+    /// chat -> timestamp -> indexed message
+    docs: HashMap<String, BTreeMap<u64, Entry>>,
+    /// Messages inserted or removed since the last save
+    #[serde(skip)]
+    unsaved: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    author: String,
+    terms: Vec<String>,
+    len: usize,
+}
+
+/// Restrictions applied to the hits of a query
+pub struct SearchFilter {
+    pub chat: Option<String>,
+    pub author: Option<String>,
+    pub before: Option<u64>,
+    pub after: Option<u64>,
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+impl SearchIndex {
+    pub async fn load(db: &Db) -> Result<Self, Error> {
+        match tokio::fs::read_to_string(db.file(INDEX_FILE)).await {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub async fn save(&mut self, db: &Db) -> Result<(), Error> {
+        if !db.is_locked() {
+            return Err(Error::other("You should lock the db"));
+        }
+        let serialized = serde_json::to_string(self)?;
+        let mut file = tokio::fs::File::create(db.file(INDEX_FILE)).await?;
+        file.write_all(serialized.as_ref()).await?;
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    /// Saves the index once enough changes piled up since the last save
+    ///
+    /// What is lost when the server dies in between can be rebuilt with `reindex`.
+    pub async fn save_batch(&mut self, db: &Db) -> Result<(), Error> {
+        if self.unsaved < SAVE_EVERY {
+            return Ok(());
+        }
+        self.save(db).await
+    }
+
+    pub fn insert(&mut self, chat: &str, message: &Message) {
+        self.remove(chat, message.timestamp);
+        self.unsaved += 1;
+
+        let terms = tokenize(&message.message);
+        let mut counts = HashMap::<&str, u32>::new();
+        for term in &terms {
+            *counts.entry(term).or_default() += 1;
+        }
+        for (term, count) in &counts {
+            self.terms
+                .entry(term.to_string())
+                .or_default()
+                .entry(chat.to_string())
+                .or_default()
+                .insert(message.timestamp, *count);
+        }
+        self.docs.entry(chat.to_string()).or_default().insert(
+            message.timestamp,
+            Entry {
+                author: message.author.clone(),
+                terms: counts.keys().map(ToString::to_string).collect(),
+                len: terms.len(),
+            },
+        );
+    }
+
+    pub fn remove(&mut self, chat: &str, timestamp: u64) {
+        let Some(entry) = self
+            .docs
+            .get_mut(chat)
+            .and_then(|docs| docs.remove(&timestamp))
+        else {
+            return;
+        };
+        self.unsaved += 1;
+        for term in entry.terms {
+            if let Some(chats) = self.terms.get_mut(&term) {
+                if let Some(postings) = chats.get_mut(chat) {
+                    postings.remove(&timestamp);
+                    if postings.is_empty() {
+                        chats.remove(chat);
+                    }
+                }
+                if chats.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Messages containing every term of `query`, best match first
+    ///
+    /// Scores are tf-idf sums normalized by the length of the message.
+    pub fn search(&self, query: &str, filter: &SearchFilter) -> Vec<(String, u64, f64)> {
+        let terms = tokenize(query);
+        let total: usize = self.docs.values().map(BTreeMap::len).sum();
+        let mut scores = HashMap::<(&str, u64), (usize, f64)>::new();
+
+        for term in &terms {
+            let Some(chats) = self.terms.get(term) else {
+                return vec![];
+            };
+            let frequency: usize = chats.values().map(BTreeMap::len).sum();
+            let idf = (1.0 + total as f64 / frequency as f64).ln();
+            for (chat, postings) in chats {
+                if filter.chat.as_ref().is_some_and(|c| c != chat) {
+                    continue;
+                }
+                for (timestamp, count) in postings {
+                    let score = scores.entry((chat, *timestamp)).or_default();
+                    score.0 += 1;
+                    score.1 += *count as f64 * idf;
+                }
+            }
+        }
+
+        let mut hits: Vec<_> = scores
+            .into_iter()
+            .filter(|(_, (matched, _))| *matched == terms.len())
+            .filter_map(|((chat, timestamp), (_, score))| {
+                let entry = self.docs.get(chat)?.get(&timestamp)?;
+                let keep = filter.author.as_ref().is_none_or(|a| *a == entry.author)
+                    && filter.before.is_none_or(|before| timestamp < before)
+                    && filter.after.is_none_or(|after| timestamp > after);
+                keep.then(|| {
+                    let score = score / (entry.len.max(1) as f64).sqrt();
+                    (chat.to_string(), timestamp, score)
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.2.total_cmp(&a.2).then(b.1.cmp(&a.1)));
+        hits
+    }
+}
@@ -0,0 +1,151 @@
+//! Discorde chat server
+//!
+//! The `models` are shared with clients, which can turn off the default `server` feature to
+//! build them without the rest of the server.
+
+pub mod models;
+
+#[cfg(feature = "server")]
+mod api;
+#[cfg(feature = "server")]
+mod chat;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+mod db;
+#[cfg(feature = "server")]
+mod metrics;
+#[cfg(feature = "server")]
+mod ratelimit;
+#[cfg(feature = "server")]
+mod scheduler;
+#[cfg(feature = "server")]
+mod sweeper;
+#[cfg(feature = "server")]
+mod tls;
+#[cfg(feature = "server")]
+mod webhooks;
+
+#[cfg(feature = "server")]
+pub use db::{admin, Database};
+
+/// Serves the API on `listener` as `config` says, its bind address aside
+///
+/// Connections are over TLS when the config has a certificate.
+///
+/// Once `shutdown` completes, the server stops accepting connections, closes its websockets,
+/// stops its background tasks and shuts the database down, giving up after the configured
+/// timeout.
+#[cfg(feature = "server")]
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    config: config::Config,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    use crate::api::DiscordeState;
+    use crate::chat::ChatSvc;
+    use crate::ratelimit::{RateLimiter, BOT_BURST, BOT_RATE};
+    use crate::webhooks::Webhooks;
+    use std::future::IntoFuture;
+    use std::io::{Error, ErrorKind};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
+    use tokio_util::task::TaskTracker;
+    use tracing::{error, info};
+
+    // Fail before locking the db if the certificate is unusable
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some((tls::load(cert, key).await?, cert.clone(), key.clone())),
+        _ => None,
+    };
+
+    let db = Arc::new(Database::new(config.database.clone()).await);
+    let webhooks = Webhooks::new(db.clone(), config.allow_private_webhooks);
+    let chat = ChatSvc::new(db.clone(), webhooks.clone(), config.broadcast_capacity);
+    let closing = CancellationToken::new();
+    // Background tasks using the db, stopped before it
+    let workers = TaskTracker::new();
+    scheduler::spawn(db.clone(), chat.clone(), closing.clone(), &workers);
+    sweeper::spawn(db.clone(), chat.clone(), closing.clone(), &workers);
+    let sockets = TaskTracker::new();
+    let routes = api::routes(
+        DiscordeState {
+            chat,
+            webhooks: webhooks.clone(),
+            db: db.clone(),
+            bots: RateLimiter::new(BOT_BURST, BOT_RATE),
+            upload_limit: config.upload_limit,
+            closing: closing.clone(),
+            sockets: sockets.clone(),
+            http: Default::default(),
+        },
+        &config,
+    );
+    let service = routes.into_make_service_with_connect_info::<SocketAddr>();
+    let mut server = match tls {
+        None => tokio::spawn(
+            axum::serve(listener, service)
+                .with_graceful_shutdown(closing.clone().cancelled_owned())
+                .into_future(),
+        ),
+        Some((tls_config, cert, key)) => match listener.into_std() {
+            Ok(listener) => {
+                tls::spawn(tls_config.clone(), cert, key, closing.clone(), &workers);
+                let handle = axum_server::Handle::new();
+                let signal = closing.clone();
+                let graceful = handle.clone();
+                tokio::spawn(async move {
+                    signal.cancelled().await;
+                    graceful.graceful_shutdown(None);
+                });
+                tokio::spawn(
+                    axum_server::from_tcp_rustls(listener, tls_config)
+                        .handle(handle)
+                        .serve(service),
+                )
+            }
+            // Failing like the server would, so the db is shut down all the same
+            Err(error) => tokio::spawn(async { Err(error) }),
+        },
+    };
+    let signal = closing.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        signal.cancel();
+    });
+
+    // The server failing stops everything else as a shutdown would
+    let failed = tokio::select! {
+        res = &mut server => Some(res),
+        _ = closing.cancelled() => None,
+    };
+    match &failed {
+        Some(res) => error!(?res, "The server stopped, shutting down"),
+        None => info!("Shutting down"),
+    }
+    closing.cancel();
+    let drain = async {
+        let served = match failed {
+            Some(res) => res,
+            None => server.await,
+        };
+        sockets.close();
+        sockets.wait().await;
+        workers.close();
+        workers.wait().await;
+        // Last, as the workers and sockets may have notified it
+        webhooks.shutdown().await;
+        let closed = db.shutdown().await;
+        // Why the server stopped matters more than the db failing to close after it
+        served?.and(closed)
+    };
+    match tokio::time::timeout(Duration::from_secs(config.shutdown_timeout), drain).await {
+        Ok(res) => res,
+        Err(_) => Err(Error::new(
+            ErrorKind::TimedOut,
+            "Connections, background tasks or the database didn't close in time",
+        )),
+    }
+}
@@ -1,39 +1,79 @@
-use crate::api::DiscordeState;
-use crate::chat::ChatSvc;
-use crate::db::Database;
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::Arc;
+use discorde_api::config::{Config, LogFormat};
+use discorde_api::Database;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
-mod api;
-mod chat;
-mod db;
-mod models;
-
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(if std::env::var("RUST_LOG").is_ok() {
-            tracing_subscriber::EnvFilter::from_default_env()
-        } else {
-            tracing_subscriber::EnvFilter::new("info")
-        })
-        .init();
+    // Logging isn't set up yet, it depends on the config
+    let (config, args) = match Config::load(std::env::args().skip(1)) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            eprintln!("Invalid configuration: {error}");
+            std::process::exit(2);
+        }
+    };
+
+    let registry = tracing_subscriber::registry().with(if std::env::var("RUST_LOG").is_ok() {
+        tracing_subscriber::EnvFilter::from_default_env()
+    } else {
+        tracing_subscriber::EnvFilter::new("info")
+    });
+    match config.log_format {
+        LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).init(),
+        LogFormat::Json => registry
+            .with(tracing_subscriber::fmt::layer().json())
+            .init(),
+    }
+
+    match args.as_slice() {
+        [] => {}
+        // `discorde-api reindex` rebuilds the search index while the server is stopped
+        [command] if command == "reindex" => {
+            match Database::reindex(config.database).await {
+                Ok(count) => info!("Indexed {count} messages"),
+                Err(error) => {
+                    error!(?error);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        _ => {
+            eprintln!("Unexpected arguments: {}", args.join(" "));
+            std::process::exit(2);
+        }
+    }
+
+    let listener = match tokio::net::TcpListener::bind(config.bind).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!(%error, bind = %config.bind, "Could not listen");
+            std::process::exit(1);
+        }
+    };
+    info!("Listening on {}", config.bind);
+    if let Err(error) = discorde_api::serve(listener, config, shutdown_signal()).await {
+        error!(%error, "Unclean shutdown");
+        std::process::exit(1);
+    }
+    info!("Stopped");
+}
 
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    let db = Arc::new(Database::new(PathBuf::from("database")).await);
-    axum::serve(
-        listener,
-        api::routes(DiscordeState {
-            chat: ChatSvc::new(db.clone()),
-            db,
-        })
-        .into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await
-    .unwrap();
+/// Completes on SIGTERM or Ctrl+C
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(error) => {
+            error!(%error, "Could not listen for SIGTERM");
+            _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
 }
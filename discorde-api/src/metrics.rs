@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the latency buckets, in seconds
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Label names and values identifying one series of a family
+pub type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+pub struct Histogram {
+    /// Observations per bucket, not cumulative, the last one being above every bound
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Series of one metric, told apart by their labels
+pub struct Family<T>(Mutex<BTreeMap<Labels, T>>);
+
+impl<T> Default for Family<T> {
+    fn default() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+}
+
+impl Family<u64> {
+    pub fn inc(&self, labels: Labels) {
+        *self.0.lock().unwrap().entry(labels).or_default() += 1;
+    }
+}
+
+impl Family<Histogram> {
+    pub fn observe(&self, labels: Labels, duration: Duration) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(labels)
+            .or_default()
+            .observe(duration);
+    }
+}
+
+/// Metrics written in the Prometheus text format
+#[derive(Default)]
+pub struct Exposition(String);
+
+impl Exposition {
+    pub fn gauge(&mut self, name: &str, help: &str, value: usize) {
+        self.header(name, help, "gauge");
+        _ = writeln!(self.0, "{name} {value}");
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, help, "counter");
+        _ = writeln!(self.0, "{name} {value}");
+    }
+
+    pub fn counters(&mut self, name: &str, help: &str, family: &Family<u64>) {
+        self.header(name, help, "counter");
+        for (labels, value) in family.0.lock().unwrap().iter() {
+            _ = writeln!(self.0, "{name}{} {value}", format_labels(labels, None));
+        }
+    }
+
+    pub fn histograms(&mut self, name: &str, help: &str, family: &Family<Histogram>) {
+        self.header(name, help, "histogram");
+        for (labels, histogram) in family.0.lock().unwrap().iter() {
+            let mut count = 0;
+            for (bound, observed) in BUCKETS.iter().zip(histogram.buckets) {
+                count += observed;
+                let le = format_labels(labels, Some(&bound.to_string()));
+                _ = writeln!(self.0, "{name}_bucket{le} {count}");
+            }
+            count += histogram.buckets[BUCKETS.len()];
+            let le = format_labels(labels, Some("+Inf"));
+            _ = writeln!(self.0, "{name}_bucket{le} {count}");
+            let labels = format_labels(labels, None);
+            _ = writeln!(self.0, "{name}_sum{labels} {}", histogram.sum);
+            _ = writeln!(self.0, "{name}_count{labels} {count}");
+        }
+    }
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        _ = writeln!(self.0, "# HELP {name} {help}");
+        _ = writeln!(self.0, "# TYPE {name} {kind}");
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+    }
+}
+
+/// `{name="value",...}`, with the `le` label of histogram buckets last
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs = labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
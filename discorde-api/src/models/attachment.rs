@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+/// Largest accepted upload, in bytes
+pub const MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+/// Edge of the square thumbnails are fitted in, in pixels
+pub const THUMBNAIL_SIZE: u32 = 256;
+/// Types that can be uploaded, images get a thumbnail
+pub const ALLOWED_MIME_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "application/zip",
+    "text/plain",
+    "audio/mpeg",
+    "video/mp4",
+];
+
+/// Metadata of an uploaded file, its content lives in the blob store under `hash`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    pub chat: String,
+    pub uploader: String,
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    pub hash: String,
+    pub thumbnail: Option<String>,
+    pub timestamp: u64,
+}
+
+impl Attachment {
+    pub fn into_ref(self, id: String) -> AttachmentRef {
+        AttachmentRef {
+            id,
+            name: self.name,
+            mime: self.mime,
+            size: self.size,
+            thumbnail: self.thumbnail.is_some(),
+        }
+    }
+}
+
+/// Attachment as referenced by a message
+///
+/// Clients only need to provide the id, the rest is filled by the server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentRef {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub mime: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub thumbnail: bool,
+}
@@ -0,0 +1,107 @@
+use crate::models::Redacted;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Bot tokens start with this, which tells them apart from user sessions
+pub const BOT_TOKEN_PREFIX: &str = "bot.";
+pub const MAX_BOTS_PER_OWNER: usize = 10;
+pub const MAX_TOKENS_PER_BOT: usize = 10;
+/// Longest token name, in characters
+const MAX_TOKEN_NAME_LEN: usize = 64;
+
+/// What makes a user account a bot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bot {
+    /// Human user who created the bot and manages its tokens
+    pub owner: String,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BotInput {
+    pub username: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BotView {
+    pub username: String,
+    pub owner: String,
+    pub created_at: u64,
+    pub chats: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BotTokenInput {
+    #[serde(default)]
+    pub name: String,
+}
+
+impl BotTokenInput {
+    pub fn validate(&mut self) -> Result<(), String> {
+        self.name = self.name.trim().to_string();
+        if self.name.chars().count() > MAX_TOKEN_NAME_LEN {
+            return Err(format!(
+                "Token names can't be longer than {MAX_TOKEN_NAME_LEN} characters"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Long-lived credential of a bot, of which only a hash is kept
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BotToken {
+    pub bot: String,
+    pub name: String,
+    pub hash: String,
+    pub created_at: u64,
+}
+
+impl BotToken {
+    /// Makes a new token for `bot`, returned in clear next to what gets stored
+    pub fn generate(bot: String, name: String, now: u64) -> (String, BotToken) {
+        let token = format!("{BOT_TOKEN_PREFIX}{}", uuid::Uuid::new_v4().simple());
+        let stored = BotToken {
+            bot,
+            name,
+            hash: hash_token(&token),
+            created_at: now,
+        };
+        (token, stored)
+    }
+
+    pub fn into_view(self, id: String, token: Option<String>) -> BotTokenView {
+        BotTokenView {
+            id,
+            name: self.name,
+            created_at: self.created_at,
+            token,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BotTokenView {
+    pub id: String,
+    pub name: String,
+    pub created_at: u64,
+    /// Only ever shown when the token is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl fmt::Debug for BotTokenView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BotTokenView")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("created_at", &self.created_at)
+            .field("token", &self.token.as_ref().map(|_| Redacted))
+            .finish()
+    }
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -1,8 +1,29 @@
+use crate::models::attachment::AttachmentRef;
+use crate::models::markdown::{self, Node};
+use crate::models::now;
+use crate::models::poll::{Poll, PollView};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
-#[derive(Debug, Deserialize)]
+/// Maximum number of messages pinned at once in a chat
+pub const MAX_PINS: usize = 50;
+/// Mention notifying every member of a chat, reserved to its admins
+pub const EVERYONE: &str = "everyone";
+/// Author of system messages, reserved so no user can impersonate the server
+pub const SYSTEM_AUTHOR: &str = "discorde";
+/// How far the time users give their messages may be from the server's, in milliseconds
+///
+/// Retention counts from that time, so it can't be pushed back or forward by much.
+pub const MAX_CLOCK_SKEW: u64 = 60 * 1000;
+/// Longest chat name, in characters
+const MAX_NAME_LEN: usize = 100;
+/// Longest chat topic, in characters
+const MAX_TOPIC_LEN: usize = 250;
+/// Longest chat description, in characters
+const MAX_DESCRIPTION_LEN: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ChatInput {
     pub private: bool,
     pub name: String,
@@ -10,12 +31,23 @@ pub struct ChatInput {
 }
 
 impl ChatInput {
-    pub fn into_chat(self) -> Chat {
+    /// Builds the chat, making its creator a member and its first admin
+    pub fn into_chat(mut self, creator: String) -> Chat {
+        if !self.members.contains(&creator) {
+            self.members.push(creator.clone());
+        }
         Chat {
             private: self.private,
             name: self.name,
+            topic: String::new(),
+            description: String::new(),
+            icon: None,
             members: self.members,
+            admins: vec![creator],
+            nicknames: Default::default(),
             messages: Default::default(),
+            pins: vec![],
+            retention: Retention::Forever,
         }
     }
 }
@@ -24,26 +56,401 @@ impl ChatInput {
 pub struct Chat {
     pub private: bool,
     pub name: String,
+    #[serde(default)]
+    pub topic: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub icon: Option<AttachmentRef>,
     pub members: Vec<String>,
+    #[serde(default)]
+    pub admins: Vec<String>,
+    /// Names members chose for themselves in this chat
+    #[serde(default)]
+    pub nicknames: BTreeMap<String, String>,
     pub messages: BTreeSet<Message>,
+    /// Pinned messages, in the order they were pinned
+    #[serde(default)]
+    pub pins: Vec<Pin>,
+    #[serde(default)]
+    pub retention: Retention,
+}
+
+/// Changes to the metadata of a chat, missing fields are left as they are
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ChatPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// New icon, an image uploaded to the chat, or `null` to remove it
+    #[serde(
+        default,
+        deserialize_with = "crate::models::nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub icon: Option<Option<AttachmentRef>>,
+}
+
+impl ChatPatch {
+    pub fn validate(&mut self) -> Result<(), String> {
+        if let Some(name) = &mut self.name {
+            *name = name.trim().to_string();
+            if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+                return Err(format!(
+                    "Chat names must be 1 to {MAX_NAME_LEN} characters long"
+                ));
+            }
+        }
+        if let Some(topic) = &mut self.topic {
+            *topic = topic.trim().to_string();
+            if topic.chars().count() > MAX_TOPIC_LEN {
+                return Err(format!(
+                    "Topics can't be longer than {MAX_TOPIC_LEN} characters"
+                ));
+            }
+        }
+        if self
+            .description
+            .as_ref()
+            .is_some_and(|d| d.chars().count() > MAX_DESCRIPTION_LEN)
+        {
+            return Err(format!(
+                "Descriptions can't be longer than {MAX_DESCRIPTION_LEN} characters"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies the changes, describing each one that made a difference
+    pub fn apply(self, chat: &mut Chat) -> Vec<String> {
+        let mut changes = vec![];
+        if let Some(name) = self.name.filter(|name| *name != chat.name) {
+            changes.push(format!("renamed the chat to {name}"));
+            chat.name = name;
+        }
+        if let Some(topic) = self.topic.filter(|topic| *topic != chat.topic) {
+            changes.push(if topic.is_empty() {
+                "cleared the topic".to_string()
+            } else {
+                format!("set the topic to {topic}")
+            });
+            chat.topic = topic;
+        }
+        if let Some(description) = self.description.filter(|d| *d != chat.description) {
+            changes.push(if description.is_empty() {
+                "cleared the description".to_string()
+            } else {
+                "updated the description".to_string()
+            });
+            chat.description = description;
+        }
+        if let Some(icon) = self.icon {
+            let current = chat.icon.as_ref().map(|icon| &icon.id);
+            if icon.as_ref().map(|icon| &icon.id) != current {
+                changes.push(if icon.is_some() {
+                    "changed the chat icon".to_string()
+                } else {
+                    "removed the chat icon".to_string()
+                });
+                chat.icon = icon;
+            }
+        }
+        changes
+    }
+}
+
+/// How long messages of a chat are kept
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum Retention {
+    #[default]
+    Forever,
+    /// Messages are deleted once they are this many days old
+    Days { days: u32 },
+    /// Messages are deleted this many seconds after another member read them
+    Ephemeral { seconds: u32 },
+}
+
+impl Retention {
+    /// Time `message` has to go at, if it is already known
+    pub fn expires_at(&self, message: &Message) -> Option<u64> {
+        match self {
+            Retention::Forever => None,
+            Retention::Days { days } => Some(
+                message
+                    .timestamp
+                    .saturating_add(*days as u64 * 24 * 60 * 60 * 1000),
+            ),
+            Retention::Ephemeral { .. } => message.expires_at,
+        }
+    }
+
+    /// Whether `message` has to go at `now`
+    pub fn is_expired(&self, message: &Message, now: u64) -> bool {
+        self.expires_at(message).is_some_and(|at| at <= now)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Pin {
+    pub message: u64,
+    pub by: String,
+    pub timestamp: u64,
 }
 
 impl Chat {
+    /// Checks the references of an incoming message and snapshots the quoted text
+    pub fn resolve_references(&self, message: &mut Message) -> Result<(), String> {
+        if let Some(root) = message.thread {
+            match self.messages.iter().find(|m| m.timestamp == root) {
+                None => return Err(format!("Thread root {root} doesn't exist")),
+                Some(m) if m.thread.is_some() => {
+                    return Err(format!("{root} is already part of a thread"))
+                }
+                Some(_) => {}
+            }
+        }
+        if let Some(quote) = &mut message.reply_to {
+            match self
+                .messages
+                .iter()
+                .find(|m| m.timestamp == quote.timestamp)
+            {
+                None => return Err(format!("Quoted message {} doesn't exist", quote.timestamp)),
+                Some(m) => {
+                    quote.author = m.author.clone();
+                    quote.message = m.message.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Earliest time one of the messages has to go at
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.messages
+            .iter()
+            .filter_map(|m| self.retention.expires_at(m))
+            .min()
+    }
+
+    /// Resolves the `@` mentions of an incoming message to members of the chat
+    pub fn resolve_mentions(&self, message: &mut Message) {
+        let names: BTreeSet<String> = message
+            .mentioned_names()
+            .into_iter()
+            .map(ToString::to_string)
+            .collect();
+        let everyone = names.contains(EVERYONE) && self.admins.contains(&message.author);
+        message.mentions = self
+            .members
+            .iter()
+            .filter(|member| **member != message.author)
+            .filter(|member| everyone || names.contains(*member))
+            .cloned()
+            .collect();
+    }
+
+    /// Marks the messages up to `timestamp` as read by `user`
+    ///
+    /// Only matters to ephemeral chats, where it starts the countdown of messages
+    /// written by others. Returns whether any message changed.
+    pub fn read(&mut self, user: &str, timestamp: u64, now: u64) -> bool {
+        let Retention::Ephemeral { seconds } = self.retention else {
+            return false;
+        };
+        let expires_at = now + seconds as u64 * 1000;
+        let unread: Vec<Message> = self
+            .messages
+            .range(..=Message::at(timestamp))
+            .filter(|m| m.author != user && m.expires_at.is_none())
+            .cloned()
+            .collect();
+        let changed = !unread.is_empty();
+        for mut message in unread {
+            message.expires_at = Some(expires_at);
+            self.messages.replace(message);
+        }
+        changed
+    }
+
+    /// Same as `into_view`, for when the chat is still needed
+    pub fn to_view(&self, id: String) -> ChatView {
+        ChatView {
+            id,
+            private: self.private,
+            name: self.name.clone(),
+            topic: self.topic.clone(),
+            description: self.description.clone(),
+            icon: self.icon.clone(),
+            members: self.members.clone(),
+            admins: self.admins.clone(),
+            retention: self.retention,
+        }
+    }
+
     pub fn into_view(self, id: String) -> ChatView {
         ChatView {
             id,
             private: self.private,
             name: self.name,
+            topic: self.topic,
+            description: self.description,
+            icon: self.icon,
             members: self.members,
+            admins: self.admins,
+            retention: self.retention,
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    /// Written by a member of the chat
+    #[default]
+    User,
+    /// Recorded by the server when something happens to the chat
+    System,
+    /// Posted through an incoming hook, `author` being its id and `nick` the name it goes by
+    Webhook,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
     pub timestamp: u64,
     pub author: String,
     pub message: String,
+    #[serde(default)]
+    pub kind: MessageKind,
+    /// Body parsed by the server, which clients render instead of the raw text
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rich: Vec<Node>,
+    /// Users who reacted to the message, grouped by emoji
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, BTreeSet<String>>,
+    /// Message this one is an inline reply to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<Quote>,
+    /// Root of the thread this message belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<u64>,
+    /// Members notified by the message, resolved by the server
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mentions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachmentRef>,
+    /// Name the author went by in the chat when sending the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nick: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<Poll>,
+    /// Time an ephemeral message will be deleted at, once it has been read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Time the author last changed the text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<u64>,
+}
+
+/// New text of a message
+///
+/// Mentions stay the ones of the original message, editing doesn't notify anyone.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageEdit {
+    pub message: String,
+}
+
+/// Snapshot of a quoted message, taken when the reply is sent
+///
+/// Clients only need to provide the timestamp, the rest is filled by the server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Quote {
+    pub timestamp: u64,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl Message {
+    pub fn system(timestamp: u64, text: String) -> Message {
+        Message {
+            author: SYSTEM_AUTHOR.to_string(),
+            message: text,
+            kind: MessageKind::System,
+            ..Message::at(timestamp)
+        }
+    }
+
+    pub fn hook(timestamp: u64, id: String, name: String, text: String) -> Message {
+        Message {
+            author: id,
+            message: text,
+            kind: MessageKind::Webhook,
+            nick: Some(name),
+            ..Message::at(timestamp)
+        }
+    }
+
+    /// Empty message standing for `timestamp` when looking messages up
+    pub fn at(timestamp: u64) -> Message {
+        Message {
+            timestamp,
+            author: String::new(),
+            message: String::new(),
+            kind: MessageKind::User,
+            rich: vec![],
+            reactions: Default::default(),
+            reply_to: None,
+            thread: None,
+            mentions: vec![],
+            attachments: vec![],
+            nick: None,
+            poll: None,
+            expires_at: None,
+            edited_at: None,
+        }
+    }
+
+    /// Names following an `@` in the body, ignoring things like email addresses
+    pub fn mentioned_names(&self) -> BTreeSet<&str> {
+        self.message
+            .match_indices('@')
+            .filter_map(|(i, _)| markdown::mention_at(&self.message, i))
+            .collect()
+    }
+
+    pub fn into_view(self, username: &str) -> MessageView {
+        MessageView {
+            timestamp: self.timestamp,
+            author: self.author,
+            message: self.message,
+            kind: self.kind,
+            rich: self.rich,
+            reactions: self
+                .reactions
+                .into_iter()
+                .map(|(emoji, users)| ReactionView {
+                    emoji,
+                    count: users.len(),
+                    me: users.contains(username),
+                })
+                .collect(),
+            reply_to: self.reply_to,
+            thread: self.thread,
+            replies: 0,
+            mentions: self.mentions,
+            attachments: self.attachments,
+            nick: self.nick,
+            poll: self.poll.map(|poll| poll.into_view(username, now())),
+            expires_at: self.expires_at,
+            edited_at: self.edited_at,
+        }
+    }
 }
 
 impl PartialEq<Self> for Message {
@@ -56,7 +463,7 @@ impl Eq for Message {}
 
 impl PartialOrd<Self> for Message {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.timestamp.partial_cmp(&other.timestamp)
+        Some(self.cmp(other))
     }
 }
 
@@ -66,10 +473,49 @@ impl Ord for Message {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReactionView {
+    pub emoji: String,
+    pub count: usize,
+    pub me: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessageView {
+    pub timestamp: u64,
+    pub author: String,
+    pub message: String,
+    pub kind: MessageKind,
+    pub rich: Vec<Node>,
+    pub reactions: Vec<ReactionView>,
+    pub reply_to: Option<Quote>,
+    pub thread: Option<u64>,
+    /// Number of messages in the thread started by this message
+    pub replies: usize,
+    pub mentions: Vec<String>,
+    pub attachments: Vec<AttachmentRef>,
+    pub nick: Option<String>,
+    pub poll: Option<PollView>,
+    pub expires_at: Option<u64>,
+    pub edited_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PinView {
+    pub pinned_by: String,
+    pub pinned_at: u64,
+    pub message: MessageView,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatView {
-    id: String,
+    pub id: String,
     pub private: bool,
     pub name: String,
+    pub topic: String,
+    pub description: String,
+    pub icon: Option<AttachmentRef>,
     pub members: Vec<String>,
+    pub admins: Vec<String>,
+    pub retention: Retention,
 }
@@ -1,14 +1,34 @@
 use crate::models::user::UserView;
+use crate::models::Redacted;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Debug, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Login {
     pub username: String,
     pub password: String,
 }
 
-#[derive(Debug, Serialize)]
+impl fmt::Debug for Login {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Login")
+            .field("username", &self.username)
+            .field("password", &Redacted)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Credentials {
     pub token: String,
     pub user: UserView,
 }
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("token", &Redacted)
+            .field("user", &self.user)
+            .finish()
+    }
+}
@@ -0,0 +1,87 @@
+use crate::models::Redacted;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Longest display name a hook can post under, in characters
+pub const MAX_HOOK_NAME_LEN: usize = 32;
+
+/// Checks a display name, returning it trimmed
+pub fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_HOOK_NAME_LEN {
+        return Err(format!(
+            "Names must be 1 to {MAX_HOOK_NAME_LEN} characters long"
+        ));
+    }
+    Ok(name.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HookInput {
+    pub name: String,
+}
+
+/// Token letting scripts post into a chat without an account
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IncomingHook {
+    pub chat: String,
+    /// Name messages are shown under unless the caller picks another
+    pub name: String,
+    pub token: String,
+    pub created_by: String,
+    pub timestamp: u64,
+}
+
+impl fmt::Debug for IncomingHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncomingHook")
+            .field("chat", &self.chat)
+            .field("name", &self.name)
+            .field("token", &Redacted)
+            .field("created_by", &self.created_by)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+impl IncomingHook {
+    pub fn into_view(self, id: String) -> HookView {
+        HookView {
+            id,
+            name: self.name,
+            token: self.token,
+            created_by: self.created_by,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct HookView {
+    pub id: String,
+    pub name: String,
+    pub token: String,
+    pub created_by: String,
+    pub timestamp: u64,
+}
+
+impl fmt::Debug for HookView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HookView")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("token", &Redacted)
+            .field("created_by", &self.created_by)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+/// Body of `POST /hooks/:token`
+#[derive(Debug, Deserialize)]
+pub struct HookMessage {
+    pub message: String,
+    /// Display name for this message only
+    #[serde(default)]
+    pub name: Option<String>,
+}
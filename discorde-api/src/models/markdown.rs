@@ -0,0 +1,523 @@
+use serde::{Deserialize, Serialize};
+
+/// Longest message body accepted, in characters
+pub const MAX_MESSAGE_LEN: usize = 4000;
+/// Deepest nesting of quotes and formatting accepted in a message
+pub const MAX_DEPTH: usize = 8;
+
+/// Link targets clients can safely open, anything else is left as text
+const LINK_SCHEMES: &[&str] = &["http://", "https://", "mailto:"];
+
+/// Rich text node of a parsed message body
+///
+/// Text is always plain, clients must never interpret it as markup of their own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Node {
+    Text {
+        text: String,
+    },
+    LineBreak,
+    Bold {
+        children: Vec<Node>,
+    },
+    Italic {
+        children: Vec<Node>,
+    },
+    Spoiler {
+        children: Vec<Node>,
+    },
+    Code {
+        text: String,
+    },
+    CodeBlock {
+        language: Option<String>,
+        text: String,
+    },
+    Link {
+        url: String,
+        children: Vec<Node>,
+    },
+    Quote {
+        children: Vec<Node>,
+    },
+    Mention {
+        user: String,
+    },
+}
+
+/// Parses a message body, refusing ones too long or too deeply nested
+pub fn parse(text: &str) -> Result<Vec<Node>, String> {
+    if text.chars().count() > MAX_MESSAGE_LEN {
+        return Err(format!(
+            "Messages can't be longer than {MAX_MESSAGE_LEN} characters"
+        ));
+    }
+    parse_blocks(text, 0)
+}
+
+/// Name mentioned by the `@` at byte `i` of `text`, ignoring things like email addresses
+pub fn mention_at(text: &str, i: usize) -> Option<&str> {
+    if follows_word(text, i) {
+        return None;
+    }
+    let rest = &text[i + 1..];
+    let end = rest
+        .find(|c: char| !(c.is_alphanumeric() || "_-.".contains(c)))
+        .unwrap_or(rest.len());
+    let name = rest[..end].trim_end_matches('.');
+    (!name.is_empty()).then_some(name)
+}
+
+fn follows_word(text: &str, i: usize) -> bool {
+    text[..i]
+        .chars()
+        .next_back()
+        .is_some_and(char::is_alphanumeric)
+}
+
+fn check_depth(depth: usize) -> Result<(), String> {
+    if depth > MAX_DEPTH {
+        return Err(format!(
+            "Formatting can't be nested more than {MAX_DEPTH} levels deep"
+        ));
+    }
+    Ok(())
+}
+
+/// Control and bidirectional override characters, which could disguise the text
+fn is_unsafe(c: char) -> bool {
+    (c.is_control() && c != '\n' && c != '\t')
+        || matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+fn sanitize(text: &str) -> String {
+    text.chars().filter(|c| !is_unsafe(*c)).collect()
+}
+
+/// Splits code blocks and quotes from the lines of inline text around them
+fn parse_blocks(text: &str, depth: usize) -> Result<Vec<Node>, String> {
+    check_depth(depth)?;
+    let mut nodes = vec![];
+    // Whether the last node ended a line of inline text, which needs a break before the next
+    let mut inline = false;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        if let Some((block, len)) = code_block(rest) {
+            nodes.push(block);
+            rest = rest[len..].strip_prefix('\n').unwrap_or(&rest[len..]);
+            inline = false;
+            continue;
+        }
+
+        let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+        if quote_line(line).is_some() {
+            let mut quoted = vec![];
+            let mut remaining = rest;
+            while let Some((line, next)) = remaining
+                .split_once('\n')
+                .or((!remaining.is_empty()).then_some((remaining, "")))
+            {
+                let Some(content) = quote_line(line) else {
+                    break;
+                };
+                quoted.push(content);
+                remaining = next;
+            }
+            nodes.push(Node::Quote {
+                children: parse_blocks(&quoted.join("\n"), depth + 1)?,
+            });
+            rest = remaining;
+            inline = false;
+            continue;
+        }
+
+        if inline {
+            nodes.push(Node::LineBreak);
+        }
+        nodes.extend(parse_inline(line, depth)?);
+        inline = true;
+        rest = next;
+    }
+    Ok(nodes)
+}
+
+fn quote_line(line: &str) -> Option<&str> {
+    if line == ">" {
+        return Some("");
+    }
+    line.strip_prefix("> ")
+}
+
+/// A fenced code block at the start of `text`, with the bytes it spans
+fn code_block(text: &str) -> Option<(Node, usize)> {
+    let inner = text.strip_prefix("```")?;
+    let end = inner.find("```")?;
+    let body = &inner[..end];
+
+    let (language, body) = match body.split_once('\n') {
+        Some((first, code))
+            if !first.is_empty()
+                && first
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || "+-#._".contains(c)) =>
+        {
+            (Some(first.to_lowercase()), code)
+        }
+        _ => (None, body.strip_prefix('\n').unwrap_or(body)),
+    };
+    let body = body.strip_suffix('\n').unwrap_or(body);
+    if body.is_empty() {
+        return None;
+    }
+    let block = Node::CodeBlock {
+        language,
+        text: sanitize(body),
+    };
+    Some((block, 3 + end + 3))
+}
+
+/// Parses formatting within a single line
+fn parse_inline(text: &str, depth: usize) -> Result<Vec<Node>, String> {
+    check_depth(depth)?;
+    let mut nodes = vec![];
+    let mut plain = String::new();
+    let mut i = 0;
+
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let parsed =
+            match c {
+                '\\' => {
+                    // Escaped punctuation is kept as is
+                    if let Some(next) = rest[1..].chars().next().filter(char::is_ascii_punctuation)
+                    {
+                        plain.push(next);
+                        i += 1 + next.len_utf8();
+                        continue;
+                    }
+                    None
+                }
+                '`' => code_span(rest),
+                '*' if rest.starts_with("**") => delimited(rest, "**", depth)?
+                    .map(|(children, len)| (Node::Bold { children }, len)),
+                '*' => delimited(rest, "*", depth)?
+                    .map(|(children, len)| (Node::Italic { children }, len)),
+                // Keeps snake_case identifiers intact
+                '_' if !follows_word(text, i) => delimited(rest, "_", depth)?
+                    .map(|(children, len)| (Node::Italic { children }, len)),
+                '|' if rest.starts_with("||") => delimited(rest, "||", depth)?
+                    .map(|(children, len)| (Node::Spoiler { children }, len)),
+                '[' => link(rest, depth)?,
+                '@' => mention_at(text, i).map(|user| {
+                    let node = Node::Mention {
+                        user: user.to_string(),
+                    };
+                    (node, 1 + user.len())
+                }),
+                'h' | 'H' if !follows_word(text, i) => autolink(rest),
+                _ => None,
+            };
+
+        match parsed {
+            Some((node, len)) => {
+                if !plain.is_empty() {
+                    nodes.push(Node::Text {
+                        text: std::mem::take(&mut plain),
+                    });
+                }
+                nodes.push(node);
+                i += len;
+            }
+            None => {
+                if !is_unsafe(c) {
+                    plain.push(c);
+                }
+                i += c.len_utf8();
+            }
+        }
+    }
+
+    if !plain.is_empty() {
+        nodes.push(Node::Text { text: plain });
+    }
+    Ok(nodes)
+}
+
+/// Text between a pair of `delim` at the start of `text`, with the bytes they span
+fn delimited(text: &str, delim: &str, depth: usize) -> Result<Option<(Vec<Node>, usize)>, String> {
+    let inner = &text[delim.len()..];
+    let Some(end) = inner.find(delim) else {
+        return Ok(None);
+    };
+    let content = &inner[..end];
+    if content.is_empty() || content.starts_with(char::is_whitespace) {
+        return Ok(None);
+    }
+    let children = parse_inline(content, depth + 1)?;
+    Ok(Some((children, delim.len() * 2 + end)))
+}
+
+/// Code span opened by a run of backticks, closed by a run of the same length
+fn code_span(text: &str) -> Option<(Node, usize)> {
+    let ticks = text.len() - text.trim_start_matches('`').len();
+    let fence = &text[..ticks];
+    let inner = &text[ticks..];
+    let end = inner.find(fence)?;
+    let code = &inner[..end];
+    if code.trim().is_empty() {
+        return None;
+    }
+    let node = Node::Code {
+        text: sanitize(code),
+    };
+    Some((node, ticks * 2 + end))
+}
+
+fn safe_url(url: &str) -> bool {
+    LINK_SCHEMES.iter().any(|scheme| {
+        url.len() > scheme.len()
+            && url
+                .get(..scheme.len())
+                .is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    }) && !url.chars().any(|c| c.is_whitespace() || is_unsafe(c))
+}
+
+/// `[label](url)` link, dropped back to text when the url isn't safe
+fn link(text: &str, depth: usize) -> Result<Option<(Node, usize)>, String> {
+    let Some(label_end) = text.find("](") else {
+        return Ok(None);
+    };
+    let label = &text[1..label_end];
+    let target = &text[label_end + 2..];
+    let Some(url_end) = target.find(')') else {
+        return Ok(None);
+    };
+    let url = target[..url_end].trim();
+    if !safe_url(url) {
+        return Ok(None);
+    }
+
+    let children = if label.trim().is_empty() {
+        vec![Node::Text {
+            text: url.to_string(),
+        }]
+    } else {
+        parse_inline(label, depth + 1)?
+    };
+    let node = Node::Link {
+        url: url.to_string(),
+        children,
+    };
+    Ok(Some((node, label_end + 2 + url_end + 1)))
+}
+
+/// Bare `http(s)://` url
+fn autolink(text: &str) -> Option<(Node, usize)> {
+    let end = text
+        .find(|c: char| c.is_whitespace() || "<>\"".contains(c))
+        .unwrap_or(text.len());
+    let url = text[..end].trim_end_matches(|c: char| ".,;:!?)'".contains(c));
+    if !url.contains("://") || !safe_url(url) {
+        return None;
+    }
+    let node = Node::Link {
+        url: url.to_string(),
+        children: vec![Node::Text {
+            text: url.to_string(),
+        }],
+    };
+    Some((node, url.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Node {
+        Node::Text {
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn refuses_messages_too_long() {
+        assert!(parse(&"a".repeat(MAX_MESSAGE_LEN)).is_ok());
+        // Counted in characters, not bytes
+        assert!(parse(&"é".repeat(MAX_MESSAGE_LEN)).is_ok());
+        assert!(parse(&"a".repeat(MAX_MESSAGE_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn refuses_nesting_too_deep() {
+        let quotes = |levels| format!("{}deep", "> ".repeat(levels));
+        assert!(parse(&quotes(MAX_DEPTH)).is_ok());
+        assert!(parse(&quotes(MAX_DEPTH + 1)).is_err());
+
+        // Formatting within quotes counts as well
+        let quoted = |levels, text| format!("{}{text}", "> ".repeat(levels));
+        assert!(parse(&quoted(MAX_DEPTH - 1, "**bold**")).is_ok());
+        assert!(parse(&quoted(MAX_DEPTH - 1, "**_italic_**")).is_err());
+        assert!(parse(&quoted(MAX_DEPTH - 2, "**_||spoiler||_**")).is_err());
+    }
+
+    #[test]
+    fn only_links_to_safe_schemes() {
+        assert!(safe_url("https://example.com"));
+        assert!(safe_url("HTTP://example.com"));
+        assert!(safe_url("mailto:alice@example.com"));
+        assert!(!safe_url("javascript:alert(1)"));
+        assert!(!safe_url("JavaScript:alert(1)"));
+        assert!(!safe_url("data:text/html,<script>alert(1)</script>"));
+        assert!(!safe_url("https://"));
+        assert!(!safe_url("https://example.com/\u{202e}gpj.exe"));
+
+        let message = "[click](javascript:alert(1))";
+        assert_eq!(parse(message).unwrap(), vec![text(message)]);
+        let message = "[click](data:text/html,hi)";
+        assert_eq!(parse(message).unwrap(), vec![text(message)]);
+    }
+
+    #[test]
+    fn parses_emphasis() {
+        assert_eq!(
+            parse("**bold *and italic* too**").unwrap(),
+            vec![Node::Bold {
+                children: vec![
+                    text("bold "),
+                    Node::Italic {
+                        children: vec![text("and italic")],
+                    },
+                    text(" too"),
+                ],
+            }]
+        );
+        assert_eq!(
+            parse("||_secret_||").unwrap(),
+            vec![Node::Spoiler {
+                children: vec![Node::Italic {
+                    children: vec![text("secret")],
+                }],
+            }]
+        );
+        assert_eq!(
+            parse("snake_case_name").unwrap(),
+            vec![text("snake_case_name")]
+        );
+        assert_eq!(
+            parse(r"\*not italic\*").unwrap(),
+            vec![text("*not italic*")]
+        );
+    }
+
+    #[test]
+    fn keeps_unclosed_markers_as_text() {
+        for message in [
+            "**open",
+            "*open",
+            "_open",
+            "||open",
+            "`open",
+            "[label](mailto:x",
+            "* spaced*",
+        ] {
+            assert_eq!(parse(message).unwrap(), vec![text(message)], "{message}");
+        }
+    }
+
+    #[test]
+    fn parses_code() {
+        assert_eq!(
+            parse("run `a *b* c` now").unwrap(),
+            vec![
+                text("run "),
+                Node::Code {
+                    text: "a *b* c".to_string(),
+                },
+                text(" now"),
+            ]
+        );
+        assert_eq!(
+            parse("``a ` b``").unwrap(),
+            vec![Node::Code {
+                text: "a ` b".to_string(),
+            }]
+        );
+        assert_eq!(
+            parse("before\n```rust\nfn main() {\n    **x**\n}\n```\nafter").unwrap(),
+            vec![
+                text("before"),
+                Node::CodeBlock {
+                    language: Some("rust".to_string()),
+                    text: "fn main() {\n    **x**\n}".to_string(),
+                },
+                text("after"),
+            ]
+        );
+        assert_eq!(
+            parse("```\nplain\n```").unwrap(),
+            vec![Node::CodeBlock {
+                language: None,
+                text: "plain".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_links() {
+        assert_eq!(
+            parse("see [the **docs**](https://example.com/docs)").unwrap(),
+            vec![
+                text("see "),
+                Node::Link {
+                    url: "https://example.com/docs".to_string(),
+                    children: vec![
+                        text("the "),
+                        Node::Bold {
+                            children: vec![text("docs")],
+                        },
+                    ],
+                },
+            ]
+        );
+        assert_eq!(
+            parse("go to https://example.com.").unwrap(),
+            vec![
+                text("go to "),
+                Node::Link {
+                    url: "https://example.com".to_string(),
+                    children: vec![text("https://example.com")],
+                },
+                text("."),
+            ]
+        );
+        assert_eq!(
+            parse("[](mailto:bob@example.com)").unwrap(),
+            vec![Node::Link {
+                url: "mailto:bob@example.com".to_string(),
+                children: vec![text("mailto:bob@example.com")],
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_quotes_and_lines() {
+        assert_eq!(
+            parse("> quoted\n>\n> more\nreply\nagain").unwrap(),
+            vec![
+                Node::Quote {
+                    children: vec![
+                        text("quoted"),
+                        Node::LineBreak,
+                        Node::LineBreak,
+                        text("more")
+                    ],
+                },
+                text("reply"),
+                Node::LineBreak,
+                text("again"),
+            ]
+        );
+    }
+}
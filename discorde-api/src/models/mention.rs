@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+/// Mention of a user in a chat message, as stored in their inbox
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Mention {
+    pub user: String,
+    pub chat: String,
+    pub message: u64,
+    pub author: String,
+    pub text: String,
+    #[serde(default)]
+    pub read: bool,
+}
+
+impl Mention {
+    pub fn into_view(self, id: String) -> MentionView {
+        MentionView {
+            id,
+            chat: self.chat,
+            message: self.message,
+            author: self.author,
+            text: self.text,
+            read: self.read,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MentionFilter {
+    #[serde(default)]
+    pub unread: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MentionView {
+    pub id: String,
+    pub chat: String,
+    pub message: u64,
+    pub author: String,
+    pub text: String,
+    pub read: bool,
+}
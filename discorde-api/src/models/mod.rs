@@ -1,3 +1,44 @@
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+
+pub mod attachment;
+pub mod bot;
 pub mod chat;
 pub mod creds;
+pub mod hook;
+pub mod markdown;
+pub mod mention;
+pub mod page;
+pub mod poll;
+pub mod scheduled;
+pub mod search;
+pub mod settings;
 pub mod user;
+pub mod webhook;
+pub mod ws;
+
+/// Stands in for passwords, tokens and keys when models are debug printed, as logs do
+pub struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// Current time in milliseconds since the epoch, as used by message timestamps
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Tells a `null` field apart from a missing one
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
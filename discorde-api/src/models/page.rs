@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+/// Query of paginated endpoints, walking backwards from the most recent entry
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PageQuery {
+    /// Only return entries strictly older than this timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+impl PageQuery {
+    /// Keeps the requested page out of `items`, which must be sorted oldest first
+    pub fn apply<T>(&self, items: Vec<T>, timestamp: impl Fn(&T) -> u64) -> Vec<T> {
+        let limit = self
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let mut items: Vec<T> = items
+            .into_iter()
+            .filter(|item| self.before.is_none_or(|before| timestamp(item) < before))
+            .collect();
+        let skip = items.len().saturating_sub(limit);
+        items.drain(..skip);
+        items
+    }
+}
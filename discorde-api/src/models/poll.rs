@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+pub const MAX_POLL_OPTIONS: usize = 10;
+/// Longest question, in characters
+const MAX_QUESTION_LEN: usize = 300;
+/// Longest option, in characters
+const MAX_OPTION_LEN: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Poll {
+    pub question: String,
+    pub options: Vec<PollOption>,
+    /// Whether voters may pick more than one option
+    #[serde(default)]
+    pub multiple: bool,
+    /// Time after which votes are refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closes_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PollOption {
+    pub text: String,
+    /// Users who picked this option, only ever filled by the server
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub votes: BTreeSet<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VoteInput {
+    /// Indices of the chosen options, empty to withdraw a vote
+    pub options: Vec<usize>,
+}
+
+impl Poll {
+    /// Checks a poll sent by a client, throwing away any vote it came with
+    pub fn validate(&mut self, now: u64) -> Result<(), String> {
+        self.question = self.question.trim().to_string();
+        if self.question.is_empty() || self.question.chars().count() > MAX_QUESTION_LEN {
+            return Err(format!(
+                "Poll questions must be 1 to {MAX_QUESTION_LEN} characters long"
+            ));
+        }
+        if !(2..=MAX_POLL_OPTIONS).contains(&self.options.len()) {
+            return Err(format!("Polls need 2 to {MAX_POLL_OPTIONS} options"));
+        }
+
+        let mut seen = BTreeSet::new();
+        for option in &mut self.options {
+            option.text = option.text.trim().to_string();
+            option.votes.clear();
+            if option.text.is_empty() || option.text.chars().count() > MAX_OPTION_LEN {
+                return Err(format!(
+                    "Poll options must be 1 to {MAX_OPTION_LEN} characters long"
+                ));
+            }
+            if !seen.insert(option.text.clone()) {
+                return Err(format!("Option {} appears twice", option.text));
+            }
+        }
+
+        if self.closes_at.is_some_and(|closes_at| closes_at <= now) {
+            return Err("Polls can't close in the past".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self, now: u64) -> bool {
+        self.closes_at.is_some_and(|closes_at| closes_at <= now)
+    }
+
+    /// Replaces the ballot of `user`, returns whether it changed
+    pub fn vote(&mut self, user: &str, choices: &[usize]) -> Result<bool, String> {
+        if !self.multiple && choices.len() > 1 {
+            return Err("Only one option can be picked in this poll".to_string());
+        }
+        let choices: BTreeSet<usize> = choices.iter().copied().collect();
+        if choices.iter().any(|i| *i >= self.options.len()) {
+            return Err("This poll has no such option".to_string());
+        }
+
+        let mut changed = false;
+        for (i, option) in self.options.iter_mut().enumerate() {
+            changed |= if choices.contains(&i) {
+                option.votes.insert(user.to_string())
+            } else {
+                option.votes.remove(user)
+            };
+        }
+        Ok(changed)
+    }
+
+    /// Number of votes for each option
+    pub fn tally(&self) -> Vec<usize> {
+        self.options.iter().map(|o| o.votes.len()).collect()
+    }
+
+    /// Number of users who voted at all
+    pub fn voters(&self) -> usize {
+        self.options
+            .iter()
+            .flat_map(|o| &o.votes)
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+    pub fn into_view(self, username: &str, now: u64) -> PollView {
+        PollView {
+            closed: self.is_closed(now),
+            voters: self.voters(),
+            question: self.question,
+            options: self
+                .options
+                .into_iter()
+                .map(|o| PollOptionView {
+                    votes: o.votes.len(),
+                    me: o.votes.contains(username),
+                    text: o.text,
+                })
+                .collect(),
+            multiple: self.multiple,
+            closes_at: self.closes_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PollOptionView {
+    pub text: String,
+    pub votes: usize,
+    pub me: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PollView {
+    pub question: String,
+    pub options: Vec<PollOptionView>,
+    pub multiple: bool,
+    pub closes_at: Option<u64>,
+    pub closed: bool,
+    pub voters: usize,
+}
@@ -0,0 +1,66 @@
+use crate::models::chat::Message;
+use serde::{Deserialize, Serialize};
+
+/// Most messages a user can have waiting in a chat
+pub const MAX_SCHEDULED: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduledInput {
+    /// Time the message should be sent at, in milliseconds since the epoch
+    pub send_at: u64,
+    pub message: String,
+    /// Root of the thread to post in
+    #[serde(default)]
+    pub thread: Option<u64>,
+}
+
+impl ScheduledInput {
+    pub fn into_scheduled(self, chat: String, author: String) -> Scheduled {
+        Scheduled {
+            chat,
+            author,
+            send_at: self.send_at,
+            message: self.message,
+            thread: self.thread,
+        }
+    }
+}
+
+/// Message waiting in the queue of the scheduler
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Scheduled {
+    pub chat: String,
+    pub author: String,
+    pub send_at: u64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<u64>,
+}
+
+impl Scheduled {
+    pub fn into_message(self, timestamp: u64) -> Message {
+        Message {
+            author: self.author,
+            message: self.message,
+            thread: self.thread,
+            ..Message::at(timestamp)
+        }
+    }
+
+    pub fn into_view(self, id: String) -> ScheduledView {
+        ScheduledView {
+            id,
+            send_at: self.send_at,
+            message: self.message,
+            thread: self.thread,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledView {
+    pub id: String,
+    pub send_at: u64,
+    pub message: String,
+    pub thread: Option<u64>,
+}
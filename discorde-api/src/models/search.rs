@@ -0,0 +1,28 @@
+use crate::models::chat::MessageView;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_HITS: usize = 20;
+const MAX_HITS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub chat: Option<String>,
+    pub author: Option<String>,
+    pub before: Option<u64>,
+    pub after: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+impl SearchQuery {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_HITS).clamp(1, MAX_HITS)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub chat: String,
+    pub score: f64,
+    pub message: MessageView,
+}
@@ -0,0 +1,90 @@
+use crate::models::chat::ChatView;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mute {
+    Forever,
+    Until(u64),
+}
+
+/// Settings a user chose for one of their chats
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ChatSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mute: Option<Mute>,
+    #[serde(default)]
+    pub favourite: bool,
+    #[serde(default)]
+    pub archived: bool,
+    /// Timestamp of the last message the user read
+    #[serde(default)]
+    pub last_read: u64,
+}
+
+impl ChatSettings {
+    pub fn is_muted(&self, now: u64) -> bool {
+        match self.mute {
+            None => false,
+            Some(Mute::Forever) => true,
+            Some(Mute::Until(until)) => now < until,
+        }
+    }
+
+    pub fn into_view(self, now: u64) -> SettingsView {
+        SettingsView {
+            muted: self.is_muted(now),
+            mute: self.mute,
+            favourite: self.favourite,
+            archived: self.archived,
+            last_read: self.last_read,
+        }
+    }
+}
+
+/// Changes to the settings of a chat, missing fields are left as they are
+#[derive(Debug, Deserialize)]
+pub struct SettingsPatch {
+    /// `null` unmutes the chat
+    #[serde(default, deserialize_with = "crate::models::nullable")]
+    pub mute: Option<Option<Mute>>,
+    pub favourite: Option<bool>,
+    pub archived: Option<bool>,
+}
+
+impl SettingsPatch {
+    pub fn apply(self, settings: &mut ChatSettings, now: u64) -> Result<(), String> {
+        if let Some(mute) = self.mute {
+            if matches!(mute, Some(Mute::Until(until)) if until <= now) {
+                return Err("Chats can't be muted until a time in the past".to_string());
+            }
+            settings.mute = mute;
+        }
+        if let Some(favourite) = self.favourite {
+            settings.favourite = favourite;
+        }
+        if let Some(archived) = self.archived {
+            settings.archived = archived;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SettingsView {
+    pub mute: Option<Mute>,
+    pub muted: bool,
+    pub favourite: bool,
+    pub archived: bool,
+    pub last_read: u64,
+}
+
+/// Chat as listed for one of its members
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserChatView {
+    #[serde(flatten)]
+    pub chat: ChatView,
+    pub settings: SettingsView,
+    /// Messages from others since the last one read
+    pub unread: usize,
+}
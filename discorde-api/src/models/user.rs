@@ -1,39 +1,104 @@
+use crate::models::bot::{Bot, BotView};
+use crate::models::settings::ChatSettings;
+use crate::models::Redacted;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 
-#[derive(Debug, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct UserInput {
     pub username: String,
     pub password: String,
 }
 
+impl fmt::Debug for UserInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserInput")
+            .field("username", &self.username)
+            .field("password", &Redacted)
+            .finish()
+    }
+}
+
 impl UserInput {
     pub fn into_user(self) -> User {
         User {
             username: self.username,
             password: self.password,
             chats: vec![],
+            settings: Default::default(),
+            bot: None,
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct User {
     pub username: String,
     pub password: String,
     pub chats: Vec<String>,
+    /// Settings of the user for each of their chats
+    #[serde(default)]
+    pub settings: BTreeMap<String, ChatSettings>,
+    /// Set on bot accounts, which authenticate with tokens instead of a password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot: Option<Bot>,
+}
+
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("username", &self.username)
+            .field("password", &Redacted)
+            .field("chats", &self.chats)
+            .field("settings", &self.settings)
+            .field("bot", &self.bot)
+            .finish()
+    }
 }
 
 impl User {
+    pub fn is_muted(&self, chat: &str, now: u64) -> bool {
+        self.settings.get(chat).is_some_and(|s| s.is_muted(now))
+    }
+
     pub fn into_view(self) -> UserView {
         UserView {
+            bot: self.bot.is_some(),
             username: self.username,
             chats: self.chats,
         }
     }
+
+    /// Bot account owned by `owner`
+    pub fn bot(username: String, owner: String, now: u64) -> User {
+        User {
+            username,
+            password: String::new(),
+            chats: vec![],
+            settings: Default::default(),
+            bot: Some(Bot {
+                owner,
+                created_at: now,
+            }),
+        }
+    }
+
+    /// View of a bot account, `None` for humans
+    pub fn into_bot_view(self) -> Option<BotView> {
+        let bot = self.bot?;
+        Some(BotView {
+            username: self.username,
+            owner: bot.owner,
+            created_at: bot.created_at,
+            chats: self.chats,
+        })
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserView {
     pub username: String,
     pub chats: Vec<String>,
+    pub bot: bool,
 }
@@ -0,0 +1,98 @@
+use crate::models::Redacted;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Events webhooks can subscribe to
+pub const WEBHOOK_EVENTS: &[&str] = &[
+    "message.create",
+    "message.edit",
+    "message.delete",
+    "member.add",
+    "member.remove",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookInput {
+    pub url: String,
+    /// Events to deliver, all of them when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Webhook {
+    pub chat: String,
+    pub url: String,
+    /// Key the payloads are signed with
+    pub secret: String,
+    pub events: Vec<String>,
+    pub created_by: String,
+    pub timestamp: u64,
+}
+
+impl fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhook")
+            .field("chat", &self.chat)
+            .field("url", &self.url)
+            .field("secret", &Redacted)
+            .field("events", &self.events)
+            .field("created_by", &self.created_by)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+impl Webhook {
+    pub fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+
+    pub fn into_view(self, id: String) -> WebhookView {
+        WebhookView {
+            id,
+            url: self.url,
+            secret: self.secret,
+            events: self.events,
+            created_by: self.created_by,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct WebhookView {
+    pub id: String,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub created_by: String,
+    pub timestamp: u64,
+}
+
+impl fmt::Debug for WebhookView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookView")
+            .field("id", &self.id)
+            .field("url", &self.url)
+            .field("secret", &Redacted)
+            .field("events", &self.events)
+            .field("created_by", &self.created_by)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+/// Delivery given up on after its last attempt failed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeadLetter {
+    pub webhook: String,
+    pub chat: String,
+    pub event: String,
+    /// Body that was sent
+    pub payload: String,
+    pub attempts: u32,
+    /// Why the last attempt failed
+    pub error: String,
+    pub timestamp: u64,
+}
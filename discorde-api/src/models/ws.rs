@@ -0,0 +1,86 @@
+use crate::models::chat::{ChatView, Message};
+use crate::models::markdown::Node;
+use serde::{Deserialize, Serialize};
+
+/// Everything that goes through a chat channel
+///
+/// Plain messages keep the `WsCommand` shape clients already send, events are
+/// tagged with a `type` field.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+// Messages are by far the most common, boxing them would only add an allocation
+#[allow(clippy::large_enum_variant)]
+pub enum WsMessage {
+    Command(WsCommand),
+    Event(WsEvent),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum WsEvent {
+    #[serde(rename = "reaction.add")]
+    ReactionAdd {
+        message: u64,
+        emoji: String,
+        user: String,
+    },
+    #[serde(rename = "reaction.remove")]
+    ReactionRemove {
+        message: u64,
+        emoji: String,
+        user: String,
+    },
+    #[serde(rename = "mention")]
+    Mention {
+        chat: String,
+        message: u64,
+        author: String,
+    },
+    /// Answer to a command, only sent to the connection that ran it
+    #[serde(rename = "ephemeral")]
+    Ephemeral { message: String },
+    /// New tally of a poll, in the order of its options
+    #[serde(rename = "poll.update")]
+    PollUpdate {
+        message: u64,
+        tally: Vec<usize>,
+        voters: usize,
+    },
+    /// Metadata of the chat changed
+    #[serde(rename = "chat.update")]
+    ChatUpdate { chat: ChatView },
+    /// The author changed the text of a message
+    #[serde(rename = "message.edit")]
+    MessageEdit {
+        message: u64,
+        text: String,
+        rich: Vec<Node>,
+        edited_at: u64,
+    },
+    #[serde(rename = "message.delete")]
+    MessageDelete { message: u64 },
+    #[serde(rename = "member.add")]
+    MemberAdd { user: String, by: String },
+    #[serde(rename = "member.remove")]
+    MemberRemove { user: String, by: String },
+    #[serde(rename = "pin")]
+    Pin {
+        message: u64,
+        user: String,
+        pinned: bool,
+    },
+}
+
+/// Actions clients can take over their websocket besides sending messages
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum WsRequest {
+    #[serde(rename = "poll.vote")]
+    Vote { message: u64, options: Vec<usize> },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WsCommand {
+    pub from: String,
+    pub message: Message,
+}
@@ -0,0 +1,64 @@
+use crate::chat::ChatSvc;
+use crate::db::Database;
+use crate::models::now;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, warn};
+
+/// How often the queue is checked for due messages
+const TICK: Duration = Duration::from_secs(1);
+
+/// Starts posting scheduled messages as they become due
+///
+/// The queue lives in the database, so messages due while the server was down
+/// are sent on the first tick after it starts again. Once `closing` is cancelled, the tick
+/// under way finishes and no other starts.
+pub fn spawn(db: Arc<Database>, chat: ChatSvc, closing: CancellationToken, workers: &TaskTracker) {
+    workers.spawn(worker(db, chat, closing));
+}
+
+async fn worker(db: Arc<Database>, chat: ChatSvc, closing: CancellationToken) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        tokio::select! {
+            () = closing.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+
+        let mut due = match db.get_scheduled().await {
+            Ok(scheduled) => scheduled
+                .into_iter()
+                .filter(|(_, s)| s.send_at <= now())
+                .collect::<Vec<_>>(),
+            Err(error) => {
+                error!(?error);
+                continue;
+            }
+        };
+        due.sort_by_key(|(_, s)| s.send_at);
+
+        // Messages are keyed by timestamp, those due together still need their own
+        let mut timestamp = now();
+        for (id, scheduled) in due {
+            let posted = loop {
+                let message = scheduled.clone().into_message(timestamp);
+                match chat.post(scheduled.chat.clone(), message).await {
+                    // Taken by a message posted meanwhile
+                    Err(error) if error.kind() == ErrorKind::AlreadyExists => timestamp += 1,
+                    posted => break posted,
+                }
+            };
+            timestamp += 1;
+            if let Err(error) = posted {
+                // Most likely the author left the chat since
+                warn!(?error, id, "Dropping scheduled message");
+            }
+            if let Err(error) = db.remove_scheduled(id).await {
+                error!(?error);
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::chat::ChatSvc;
+use crate::db::Database;
+use crate::models::now;
+use crate::models::ws::{WsEvent, WsMessage};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::error;
+
+/// How long to wait before trying again when deleting expired messages failed
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Starts deleting messages once the retention policy of their chat says so
+///
+/// The database tells when the next message is due, nothing is scanned in between. Stops
+/// once `closing` is cancelled, a sweep under way aside.
+pub fn spawn(db: Arc<Database>, chat: ChatSvc, closing: CancellationToken, workers: &TaskTracker) {
+    workers.spawn(worker(db, chat, closing));
+}
+
+async fn worker(db: Arc<Database>, chat: ChatSvc, closing: CancellationToken) {
+    let mut next_expiry = db.next_expiry();
+    loop {
+        let due = *next_expiry.borrow_and_update();
+        let wait = async {
+            match due {
+                Some(at) => {
+                    tokio::time::sleep(Duration::from_millis(at.saturating_sub(now()))).await
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            () = closing.cancelled() => return,
+            changed = next_expiry.changed() => {
+                if changed.is_err() {
+                    // The database is gone
+                    return;
+                }
+                continue;
+            }
+            () = wait => {}
+        }
+
+        match db.expire_messages(now()).await {
+            Ok(expired) => {
+                for expired in expired {
+                    for message in expired.messages {
+                        let event = WsEvent::MessageDelete { message };
+                        chat.publish(expired.chat.clone(), WsMessage::Event(event));
+                    }
+                }
+            }
+            Err(error) => {
+                error!(?error);
+                tokio::select! {
+                    () = closing.cancelled() => return,
+                    () = tokio::time::sleep(RETRY_DELAY) => {}
+                }
+            }
+        }
+    }
+}
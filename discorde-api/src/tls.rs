@@ -0,0 +1,75 @@
+use axum_server::tls_rustls::RustlsConfig;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info};
+
+/// How often the certificate files are checked for changes
+const TICK: Duration = Duration::from_secs(5);
+
+/// Reads the certificate chain and private key, both PEM encoded
+///
+/// Clients are offered HTTP/2 and HTTP/1.1 through ALPN.
+pub async fn load(cert: &Path, key: &Path) -> Result<RustlsConfig, Error> {
+    RustlsConfig::from_pem_file(cert, key)
+        .await
+        .map_err(|error| {
+            Error::new(
+                error.kind(),
+                format!("{} or {}: {error}", cert.display(), key.display()),
+            )
+        })
+}
+
+/// Starts reloading the certificate when its files change or on SIGHUP
+///
+/// New connections get the new certificate, open ones keep theirs. When the files can't be
+/// read, as when they are halfway replaced, the current certificate is kept. Stops once
+/// `closing` is cancelled.
+pub fn spawn(
+    config: RustlsConfig,
+    cert: PathBuf,
+    key: PathBuf,
+    closing: CancellationToken,
+    workers: &TaskTracker,
+) {
+    workers.spawn(worker(config, cert, key, closing));
+}
+
+async fn worker(config: RustlsConfig, cert: PathBuf, key: PathBuf, closing: CancellationToken) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(error) => {
+            error!(?error, "Certificates won't be reloaded on SIGHUP");
+            None
+        }
+    };
+    let mut interval = tokio::time::interval(TICK);
+    let mut loaded = modified(&cert, &key);
+    loop {
+        tokio::select! {
+            () = closing.cancelled() => return,
+            Some(()) = async { hangup.as_mut()?.recv().await } => {}
+            _ = interval.tick() => {
+                if modified(&cert, &key) == loaded {
+                    continue;
+                }
+            }
+        }
+
+        loaded = modified(&cert, &key);
+        match config.reload_from_pem_file(&cert, &key).await {
+            Ok(()) => info!("Reloaded the TLS certificate"),
+            Err(error) => error!(?error, "Keeping the current TLS certificate"),
+        }
+    }
+}
+
+/// Last modification times of the certificate and key
+fn modified(cert: &Path, key: &Path) -> Option<(SystemTime, SystemTime)> {
+    let time = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    Some((time(cert)?, time(key)?))
+}
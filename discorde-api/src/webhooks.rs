@@ -0,0 +1,333 @@
+use crate::db::Database;
+use crate::models::now;
+use crate::models::webhook::{DeadLetter, Webhook};
+use crate::models::ws::{WsEvent, WsMessage};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, warn};
+
+/// Attempts made before a delivery goes to the dead-letter log
+const MAX_ATTEMPTS: u32 = 5;
+/// Wait before the first retry, doubled after every failure
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+/// How long a receiver gets to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// `sha256=` followed by the hex HMAC of the body, keyed with the secret of the webhook
+pub const SIGNATURE_HEADER: &str = "X-Discorde-Signature";
+pub const EVENT_HEADER: &str = "X-Discorde-Event";
+
+struct Notification {
+    chat: String,
+    event: &'static str,
+    data: Value,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    id: String,
+    event: &'a str,
+    chat: &'a str,
+    timestamp: u64,
+    data: &'a Value,
+}
+
+/// Delivers chat events to the webhooks registered by chat admins
+#[derive(Clone)]
+pub struct Webhooks {
+    tx: UnboundedSender<Notification>,
+    /// Whether webhooks may target loopback, link-local and private addresses
+    allow_private: bool,
+    stop: CancellationToken,
+    /// The worker and the deliveries it started
+    tasks: TaskTracker,
+}
+
+impl Webhooks {
+    pub fn new(db: Arc<Database>, allow_private: bool) -> Self {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let stop = CancellationToken::new();
+        let tasks = TaskTracker::new();
+        tasks.spawn(Self::worker(
+            rx,
+            db,
+            allow_private,
+            stop.clone(),
+            tasks.clone(),
+        ));
+
+        Self {
+            tx,
+            allow_private,
+            stop,
+            tasks,
+        }
+    }
+
+    /// Delivers what was queued, filing deliveries waiting to be retried as dead letters
+    ///
+    /// Notifications queued later are dropped, the db must outlive this.
+    pub async fn shutdown(&self) {
+        self.stop.cancel();
+        self.tasks.close();
+        self.tasks.wait().await;
+    }
+
+    /// Checks `url` can be delivered to, giving the reason when it can't
+    ///
+    /// Hosts given by name are checked again each time they are resolved.
+    pub fn check_url(&self, url: &str) -> Result<(), String> {
+        check_url(url, self.allow_private)
+    }
+
+    async fn worker(
+        mut rx: UnboundedReceiver<Notification>,
+        db: Arc<Database>,
+        allow_private: bool,
+        stop: CancellationToken,
+        tasks: TaskTracker,
+    ) {
+        // Receivers could otherwise bounce deliveries to addresses they couldn't be registered at
+        let builder = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none());
+        let builder = if allow_private {
+            builder
+        } else {
+            builder.dns_resolver(Arc::new(PublicResolver))
+        };
+        let client = builder.build().map_err(|error| error!(?error)).unwrap();
+
+        loop {
+            let notification = tokio::select! {
+                // What was queued before stopping still goes out
+                biased;
+                Some(notification) = rx.recv() => notification,
+                () = stop.cancelled() => match rx.try_recv() {
+                    Ok(notification) => notification,
+                    Err(_) => return,
+                },
+            };
+            let webhooks = match db.get_webhooks(notification.chat.clone()).await {
+                Ok(webhooks) => webhooks,
+                Err(error) => {
+                    error!(?error);
+                    continue;
+                }
+            };
+            for (id, webhook) in webhooks {
+                if !webhook.wants(notification.event) {
+                    continue;
+                }
+                let body = serde_json::to_string(&Payload {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    event: notification.event,
+                    chat: &notification.chat,
+                    timestamp: now(),
+                    data: &notification.data,
+                })
+                .unwrap();
+                // Retries must not hold back other deliveries
+                tasks.spawn(deliver(
+                    client.clone(),
+                    db.clone(),
+                    id,
+                    webhook,
+                    notification.event,
+                    body,
+                    allow_private,
+                    stop.clone(),
+                ));
+            }
+        }
+    }
+
+    /// Queues `msg` for the webhooks of `chat`, if it is something they are told about
+    pub fn notify(&self, chat: String, msg: &WsMessage) {
+        let (event, data) = match msg {
+            WsMessage::Command(cmd) => ("message.create", json!(cmd.message)),
+            WsMessage::Event(WsEvent::MessageEdit {
+                message,
+                text,
+                edited_at,
+                ..
+            }) => (
+                "message.edit",
+                json!({ "message": message, "text": text, "edited_at": edited_at }),
+            ),
+            WsMessage::Event(WsEvent::MessageDelete { message }) => {
+                ("message.delete", json!({ "message": message }))
+            }
+            WsMessage::Event(WsEvent::MemberAdd { user, by }) => {
+                ("member.add", json!({ "user": user, "by": by }))
+            }
+            WsMessage::Event(WsEvent::MemberRemove { user, by }) => {
+                ("member.remove", json!({ "user": user, "by": by }))
+            }
+            _ => return,
+        };
+        _ = self.tx.send(Notification { chat, event, data });
+    }
+}
+
+/// Whether `ip` is reachable from the internet at large
+///
+/// Loopback, link-local, private, shared and multicast addresses could reach the server itself
+/// or its neighbours, which chat admins have no business with.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0
+        // Shared address space of carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b)))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local
+        || first & 0xfe00 == 0xfc00
+        // Link-local
+        || first & 0xffc0 == 0xfe80)
+}
+
+fn check_url(url: &str, allow_private: bool) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|error| error.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("{} urls can't be delivered to", url.scheme()));
+    }
+    let Some(host) = url.host_str() else {
+        return Err("The url has no host".to_string());
+    };
+    // Names are checked once resolved
+    let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() else {
+        return Ok(());
+    };
+    if !allow_private && !is_public(ip) {
+        return Err(format!("{ip} isn't a public address"));
+    }
+    Ok(())
+}
+
+/// Resolves host names to their public addresses only
+///
+/// Checking at connection time leaves no window for a name to change between a check and
+/// the delivery.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} has no public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Posts `body` until the receiver accepts it, or files it in the dead-letter log
+///
+/// Retries are given up once `stop` is cancelled.
+#[allow(clippy::too_many_arguments)]
+async fn deliver(
+    client: reqwest::Client,
+    db: Arc<Database>,
+    id: String,
+    webhook: Webhook,
+    event: &'static str,
+    body: String,
+    allow_private: bool,
+    stop: CancellationToken,
+) {
+    let signature = sign(&webhook.secret, &body);
+    let mut delay = FIRST_RETRY;
+    let mut attempts = 0;
+
+    let error = match check_url(&webhook.url, allow_private) {
+        // Registered before the server was told to refuse such urls
+        Err(error) => error,
+        Ok(()) => loop {
+            attempts += 1;
+            let res = client
+                .post(&webhook.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event)
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+            let error = match res {
+                Ok(res) if res.status().is_success() => return,
+                Ok(res) => format!("Answered with {}", res.status()),
+                Err(e) => e.to_string(),
+            };
+            if attempts == MAX_ATTEMPTS {
+                break error;
+            }
+            warn!(
+                webhook = id,
+                attempt = attempts,
+                error,
+                "Webhook delivery failed, retrying"
+            );
+            tokio::select! {
+                () = stop.cancelled() => break format!("{error}, the server shut down before retrying"),
+                () = tokio::time::sleep(delay) => {}
+            }
+            delay *= 2;
+        },
+    };
+
+    warn!(webhook = id, error, "Webhook delivery abandoned");
+    let letter = DeadLetter {
+        webhook: id,
+        chat: webhook.chat,
+        event: event.to_string(),
+        payload: body,
+        attempts,
+        error,
+        timestamp: now(),
+    };
+    if let Err(error) = db.insert_dead_letter(letter).await {
+        error!(?error);
+    }
+}
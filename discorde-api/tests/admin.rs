@@ -0,0 +1,86 @@
+mod common;
+
+use common::Server;
+use discorde_api::admin::Admin;
+use discorde_client::models::attachment::AttachmentRef;
+use discorde_client::models::chat::ChatInput;
+use std::io::ErrorKind;
+
+#[tokio::test]
+async fn refuses_dbs_in_use() {
+    let mut server = Server::start().await;
+    server.user("alice").await;
+
+    let error = Admin::open(server.database().to_path_buf())
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::ResourceBusy);
+    assert!(error.to_string().contains("in use"));
+    // The server's lock is left alone
+    assert!(server.is_locked());
+
+    server.stop().await.unwrap();
+    let admin = Admin::open(server.database().to_path_buf()).await.unwrap();
+    assert_eq!(admin.users().await.unwrap()[0].username, "alice");
+    // One admin at a time
+    let error = Admin::open(server.database().to_path_buf())
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::ResourceBusy);
+    admin.close().await.unwrap();
+    assert!(!server.is_locked());
+}
+
+#[tokio::test]
+async fn refuses_locks_of_dead_processes() {
+    let path = std::env::temp_dir().join(format!("discorde-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&path).unwrap();
+
+    // Not pids of processes, let alone live ones
+    for pid in ["0", "-1", "999999999"] {
+        std::fs::write(path.join("lock"), pid).unwrap();
+        let error = Admin::open(path.clone()).await.err().unwrap();
+        assert!(error.to_string().contains("dead process"), "{pid}: {error}");
+    }
+    _ = std::fs::remove_dir_all(&path);
+}
+
+#[tokio::test]
+async fn checks_references() {
+    let mut server = Server::start().await;
+    let alice = server.user("alice").await;
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec![],
+    };
+    let id = alice.create_chat(&input).await.unwrap().id;
+    let body = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        notes\r\n\
+        --boundary--\r\n";
+    let attachment: AttachmentRef = reqwest::Client::new()
+        .post(format!("{}/chats/{id}/attachments", server.url()))
+        .bearer_auth(&alice.session().unwrap().token)
+        .header("content-type", "multipart/form-data; boundary=boundary")
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    server.stop().await.unwrap();
+
+    let admin = Admin::open(server.database().to_path_buf()).await.unwrap();
+    assert_eq!(admin.check().await.unwrap(), Vec::<String>::new());
+
+    std::fs::remove_dir_all(server.database().join("blobs")).unwrap();
+    let problems = admin.check().await.unwrap();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains(&attachment.id), "{problems:?}");
+    admin.close().await.unwrap();
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use common::Server;
+use discorde_api::config::Config;
+use discorde_client::models::attachment::AttachmentRef;
+use discorde_client::models::chat::ChatInput;
+use discorde_client::Client;
+use image::{ImageFormat, RgbImage};
+use reqwest::StatusCode;
+use std::io::Cursor;
+
+async fn general(alice: &Client) -> String {
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["bob".to_string()],
+    };
+    alice.create_chat(&input).await.unwrap().id
+}
+
+async fn upload(
+    server: &Server,
+    client: &Client,
+    chat: &str,
+    name: &str,
+    mime: &str,
+    data: &[u8],
+) -> reqwest::Response {
+    let mut body = format!(
+        "--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+        Content-Type: {mime}\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(b"\r\n--boundary--\r\n");
+    reqwest::Client::new()
+        .post(format!("{}/chats/{chat}/attachments", server.url()))
+        .bearer_auth(&client.session().unwrap().token)
+        .header("content-type", "multipart/form-data; boundary=boundary")
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn download(server: &Server, client: &Client, path: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .get(format!("{}/attachments/{path}", server.url()))
+        .bearer_auth(&client.session().unwrap().token)
+}
+
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut out = Cursor::new(vec![]);
+    RgbImage::new(width, height)
+        .write_to(&mut out, ImageFormat::Png)
+        .unwrap();
+    out.into_inner()
+}
+
+#[tokio::test]
+async fn serves_ranges_of_attachments() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let carol = server.user("carol").await;
+    let id = general(&alice).await;
+
+    let res = upload(
+        &server,
+        &alice,
+        &id,
+        "hello.txt",
+        "text/plain",
+        b"hello world",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let attachment: AttachmentRef = res.json().await.unwrap();
+    assert_eq!(attachment.size, 11);
+    assert!(!attachment.thumbnail);
+
+    let res = download(&server, &bob, &attachment.id)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "text/plain");
+    assert_eq!(
+        res.headers()["content-disposition"],
+        "attachment; filename=\"hello.txt\""
+    );
+    assert_eq!(res.text().await.unwrap(), "hello world");
+
+    let res = download(&server, &bob, &attachment.id)
+        .header("range", "bytes=6-")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.headers()["content-range"], "bytes 6-10/11");
+    assert_eq!(res.text().await.unwrap(), "world");
+
+    // Files without a thumbnail, or out of reach, are nowhere to be found
+    let thumbnail = format!("{}/thumbnail", attachment.id);
+    let res = download(&server, &bob, &thumbnail).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = download(&server, &carol, &attachment.id)
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_client_error());
+    let res = upload(&server, &carol, &id, "hello.txt", "text/plain", b"hi").await;
+    assert!(res.status().is_client_error());
+}
+
+#[tokio::test]
+async fn makes_thumbnails_of_images() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let id = general(&alice).await;
+
+    let res = upload(
+        &server,
+        &alice,
+        &id,
+        "wide.png",
+        "image/png",
+        &png(600, 300),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let attachment: AttachmentRef = res.json().await.unwrap();
+    assert!(attachment.thumbnail);
+
+    let thumbnail = format!("{}/thumbnail", attachment.id);
+    let res = download(&server, &alice, &thumbnail).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    let data = res.bytes().await.unwrap();
+    let image = image::load_from_memory_with_format(&data, ImageFormat::Png).unwrap();
+    assert_eq!((image.width(), image.height()), (256, 128));
+}
+
+#[tokio::test]
+async fn refuses_unexpected_files() {
+    let server = Server::start_with(Config {
+        upload_limit: 100,
+        ..Config::default()
+    })
+    .await;
+    let alice = server.user("alice").await;
+    let id = general(&alice).await;
+
+    let res = upload(&server, &alice, &id, "a.txt", "text/plain", &[b'a'; 100]).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let res = upload(&server, &alice, &id, "a.txt", "text/plain", &[b'a'; 101]).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let res = upload(
+        &server,
+        &alice,
+        &id,
+        "run.exe",
+        "application/x-msdownload",
+        b"MZ",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    // Images must be what they claim to be
+    let res = upload(
+        &server,
+        &alice,
+        &id,
+        "fake.png",
+        "image/png",
+        b"not an image",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let res = upload(&server, &alice, &id, "a.jpg", "image/jpeg", &png(1, 1)).await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(server.blobs(), 1);
+}
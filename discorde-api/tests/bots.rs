@@ -0,0 +1,150 @@
+mod common;
+
+use common::{connected, Server};
+use discorde_client::models::chat::ChatInput;
+use discorde_client::{Client, Session};
+use serde_json::{json, Value};
+use std::io::ErrorKind;
+
+/// Bot of `owner` called `name`, with a client authenticated by a fresh token
+async fn bot(server: &Server, owner: &Client, name: &str) -> (Client, String) {
+    let http = reqwest::Client::new();
+    let token = &owner.session().unwrap().token;
+    let res = http
+        .post(format!("{}/bots", server.url()))
+        .bearer_auth(token)
+        .json(&json!({ "username": name }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+
+    let issued: Value = http
+        .post(format!("{}/bots/{name}/tokens", server.url()))
+        .bearer_auth(token)
+        .json(&json!({ "name": "tests" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let session = Session {
+        username: name.to_string(),
+        token: issued["token"].as_str().unwrap().to_string(),
+    };
+    let id = issued["id"].as_str().unwrap().to_string();
+    (server.client().with_session(session), id)
+}
+
+#[tokio::test]
+async fn authenticates_bots_with_tokens() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let (helper, token) = bot(&server, &alice, "helper").await;
+    assert!(helper.session().unwrap().token.starts_with("bot."));
+
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["helper".to_string()],
+    };
+    let chat = alice.create_chat(&input).await.unwrap();
+    let chats = helper.chats().await.unwrap();
+    assert_eq!(chats.len(), 1);
+    assert_eq!(chats[0].chat.id, chat.id);
+
+    // Revoked tokens stop working right away
+    let res = reqwest::Client::new()
+        .delete(format!("{}/bots/helper/tokens/{token}", server.url()))
+        .bearer_auth(&alice.session().unwrap().token)
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    let refused = helper.chats().await.unwrap_err();
+    assert_eq!(refused.kind(), ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn refuses_password_logins_for_bots() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    bot(&server, &alice, "helper").await;
+
+    for password in ["", "password"] {
+        assert!(server.client().login("helper", password).await.is_err());
+    }
+    // Nor can the name of a bot be used as a session
+    let session = Session {
+        username: "helper".to_string(),
+        token: "helper".to_string(),
+    };
+    let refused = server.client().with_session(session).users().await;
+    assert_eq!(refused.unwrap_err().kind(), ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn refuses_names_looking_like_bot_tokens() {
+    let server = Server::start().await;
+    let refused = server.client().create_user("bot.alice", "password").await;
+    assert_eq!(refused.unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    let alice = server.user("alice").await;
+    let res = reqwest::Client::new()
+        .post(format!("{}/bots", server.url()))
+        .bearer_auth(&alice.session().unwrap().token)
+        .json(&json!({ "username": "bot.helper" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn rate_limits_bots() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let (helper, _) = bot(&server, &alice, "helper").await;
+
+    // The bucket holds 20 requests and refills by 2 a second
+    let mut served = 0;
+    let error = loop {
+        match helper.chats().await {
+            Ok(_) => served += 1,
+            Err(error) => break error,
+        }
+        assert!(served < 30, "the bot was never limited");
+    };
+    assert!(served >= 20, "limited after {served} requests");
+    assert!(error.to_string().contains("429"), "{error}");
+
+    // Websockets wait until they are let in again
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["helper".to_string()],
+    };
+    let id = alice.create_chat(&input).await.unwrap().id;
+    let mut events = helper.connect(&id).unwrap();
+    connected(&mut events).await;
+
+    // People aren't limited
+    for _ in 0..30 {
+        alice.chats().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn flags_bots_in_user_views() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    bot(&server, &alice, "helper").await;
+
+    assert!(alice.user("helper").await.unwrap().bot);
+    assert!(!alice.user("alice").await.unwrap().bot);
+    let users = alice.users().await.unwrap();
+    let flags: Vec<_> = users.iter().map(|u| (u.username.as_str(), u.bot)).collect();
+    assert!(flags.contains(&("helper", true)));
+    assert!(flags.contains(&("alice", false)));
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::{connected, Server, TIMEOUT};
+use discorde_client::models::chat::{ChatInput, ChatPatch};
+use discorde_client::models::page::PageQuery;
+use discorde_client::models::ws::{WsEvent, WsMessage};
+use discorde_client::{Event, Events};
+use std::io::ErrorKind;
+
+fn chat(name: &str, members: &[&str]) -> ChatInput {
+    ChatInput {
+        private: false,
+        name: name.to_string(),
+        members: members.iter().map(ToString::to_string).collect(),
+    }
+}
+
+/// Follows `events` until the server ends them, which it must do after telling of the removal
+async fn cut_off(events: &mut Events, user: &str) {
+    let mut removed = false;
+    loop {
+        let event = tokio::time::timeout(TIMEOUT, events.next())
+            .await
+            .expect("the websocket is still open");
+        match event {
+            None => break,
+            Some(Event::Message(WsMessage::Event(WsEvent::MemberRemove { user: u, .. })))
+                if u == user =>
+            {
+                removed = true
+            }
+            Some(Event::Message(WsMessage::Command(cmd))) => {
+                assert_ne!(cmd.message.message, "secret", "{user} still reads the chat")
+            }
+            Some(_) => {}
+        }
+    }
+    assert!(removed);
+}
+
+#[tokio::test]
+async fn creates_and_lists_chats() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+
+    let created = alice.create_chat(&chat("general", &["bob"])).await.unwrap();
+    assert_eq!(created.name, "general");
+    assert_eq!(created.admins, ["alice"]);
+    assert!(created.members.contains(&"bob".to_string()));
+
+    let chats = bob.chats().await.unwrap();
+    assert_eq!(chats.len(), 1);
+    assert_eq!(chats[0].chat.id, created.id);
+    // The creation is recorded in the history
+    assert_eq!(chats[0].unread, 1);
+}
+
+#[tokio::test]
+async fn only_admins_edit_chats() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = alice
+        .create_chat(&chat("general", &["bob"]))
+        .await
+        .unwrap()
+        .id;
+
+    let patch = ChatPatch {
+        topic: Some("Anything goes".to_string()),
+        ..Default::default()
+    };
+    let edited = alice.edit_chat(&id, &patch).await.unwrap();
+    assert_eq!(edited.topic, "Anything goes");
+    let error = bob.edit_chat(&id, &patch).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn manages_members() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = alice.create_chat(&chat("general", &[])).await.unwrap().id;
+
+    let error = bob.messages(&id, &PageQuery::default()).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+
+    bob.add_member(&id, "bob").await.unwrap();
+    let history = bob.messages(&id, &PageQuery::default()).await.unwrap();
+    assert_eq!(history.last().unwrap().message, "bob joined the chat");
+
+    alice.remove_member(&id, "bob").await.unwrap();
+    assert!(bob.chats().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn disconnects_removed_members() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = alice
+        .create_chat(&chat("general", &["bob"]))
+        .await
+        .unwrap()
+        .id;
+    let alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut bob_events).await;
+
+    alice.remove_member(&id, "bob").await.unwrap();
+    alice_events.send_text("secret").unwrap();
+    cut_off(&mut bob_events, "bob").await;
+}
+
+#[tokio::test]
+async fn disconnects_kicked_members() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = alice
+        .create_chat(&chat("general", &["bob"]))
+        .await
+        .unwrap()
+        .id;
+    let alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut bob_events).await;
+
+    alice_events.send_text("/kick bob").unwrap();
+    alice_events.send_text("secret").unwrap();
+    cut_off(&mut bob_events, "bob").await;
+    let history = alice.messages(&id, &PageQuery::default()).await.unwrap();
+    assert!(history.iter().any(|m| m.message == "alice removed bob"));
+}
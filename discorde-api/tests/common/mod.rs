@@ -0,0 +1,193 @@
+//! Server and helpers shared by the integration tests
+
+#![allow(dead_code)]
+
+use discorde_api::config::Config;
+use discorde_client::models::chat::Message;
+use discorde_client::models::ws::WsMessage;
+use discorde_client::{Client, Event, Events};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// How long to wait for something the server should do right away
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Server running in the test, with a database of its own
+pub struct Server {
+    pub addr: SocketAddr,
+    dir: PathBuf,
+    config: Config,
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl Server {
+    pub async fn start() -> Self {
+        Self::start_with(Config::default()).await
+    }
+
+    /// Server configured by `config`, with its own database all the same
+    pub async fn start_with(config: Config) -> Self {
+        let dir = std::env::temp_dir().join(format!("discorde-test-{}", uuid::Uuid::new_v4()));
+        let config = Config {
+            database: dir.clone(),
+            ..config
+        };
+        let mut server = Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            dir,
+            config,
+            stop: None,
+            task: None,
+        };
+        server.spawn().await;
+        server
+    }
+
+    /// Starts the server again on the same database, once stopped
+    ///
+    /// It listens on another port, clients must be made anew.
+    pub async fn restart(&mut self) {
+        assert!(self.task.is_none(), "the server is still running");
+        self.spawn().await;
+    }
+
+    async fn spawn(&mut self) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        self.addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(discorde_api::serve(listener, self.config.clone(), async {
+            _ = stopped.await;
+        }));
+        self.stop = Some(stop);
+        self.task = Some(task);
+    }
+
+    /// Shuts the server down as a signal would, returning how it went
+    pub async fn stop(&mut self) -> std::io::Result<()> {
+        _ = self.stop.take().unwrap().send(());
+        tokio::time::timeout(TIMEOUT, self.task.take().unwrap())
+            .await
+            .expect("the server didn't stop")
+            .unwrap()
+    }
+
+    /// Directory of the database
+    pub fn database(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether the database is still locked by the server
+    pub fn is_locked(&self) -> bool {
+        self.dir.join("lock").exists()
+    }
+
+    /// Number of files in the blob store of the database
+    pub fn blobs(&self) -> usize {
+        std::fs::read_dir(self.dir.join("blobs")).map_or(0, |dir| dir.count())
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn client(&self) -> Client {
+        Client::new(&self.url()).unwrap()
+    }
+
+    /// Client logged in as a new user
+    pub async fn user(&self, username: &str) -> Client {
+        let mut client = self.client();
+        client.create_user(username, "password").await.unwrap();
+        client.login(username, "password").await.unwrap();
+        client
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Forwards connections to a server, and can cut them to test reconnections
+pub struct Proxy {
+    pub addr: SocketAddr,
+    connections: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Proxy {
+    pub async fn start(target: SocketAddr) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(Mutex::new(vec![]));
+        let handles = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                let handle = tokio::spawn(async move {
+                    if let Ok(mut outbound) = TcpStream::connect(target).await {
+                        _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                    }
+                });
+                handles.lock().unwrap().push(handle);
+            }
+        });
+        Self { addr, connections }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Drops every connection open through the proxy
+    pub fn cut(&self) {
+        for handle in self.connections.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+pub async fn next_event(events: &mut Events) -> Event {
+    tokio::time::timeout(TIMEOUT, events.next())
+        .await
+        .expect("no event came")
+        .expect("the connection was refused")
+}
+
+/// Next message or event sent through the chat, skipping connection changes
+pub async fn next_message(events: &mut Events) -> WsMessage {
+    loop {
+        if let Event::Message(msg) = next_event(events).await {
+            return msg;
+        }
+    }
+}
+
+/// Waits for the connection to be up, so that nothing sent next is missed
+pub async fn connected(events: &mut Events) {
+    loop {
+        if let Event::Connected = next_event(events).await {
+            return;
+        }
+    }
+}
+
+/// Next message posted to the chat, skipping events
+pub async fn next_command(events: &mut Events) -> Message {
+    loop {
+        if let WsMessage::Command(cmd) = next_message(events).await {
+            return cmd.message;
+        }
+    }
+}
+
+/// Sends `message` through `sender`, returning it as `listener` got it from the same chat
+pub async fn post(sender: &Events, listener: &mut Events, message: Message) -> Message {
+    sender.send(message).unwrap();
+    next_command(listener).await
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use common::{connected, next_message, Server};
+use discorde_client::models::chat::{ChatInput, Message};
+use discorde_client::models::now;
+use discorde_client::models::page::PageQuery;
+use discorde_client::models::settings::UserChatView;
+use discorde_client::models::ws::{WsEvent, WsMessage};
+use discorde_client::{Client, Events};
+use std::io::ErrorKind;
+
+async fn general(alice: &Client) -> String {
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["bob".to_string()],
+    };
+    alice.create_chat(&input).await.unwrap().id
+}
+
+/// Sends `text` as alice, returning the message and the mentions bob was notified of meanwhile
+///
+/// Waits for bob to be notified of it too when `notified`.
+async fn post(alice: &Events, bob: &mut Events, text: &str, notified: bool) -> (Message, Vec<u64>) {
+    let message = Message {
+        message: text.to_string(),
+        ..Message::at(now())
+    };
+    alice.send(message).unwrap();
+    let mut posted: Option<Message> = None;
+    let mut mentioned = vec![];
+    // The mention and the message come through different channels, in any order
+    loop {
+        if let Some(message) = &posted {
+            if !notified || mentioned.contains(&message.timestamp) {
+                return (posted.unwrap(), mentioned);
+            }
+        }
+        match next_message(bob).await {
+            WsMessage::Command(cmd) => posted = Some(cmd.message),
+            WsMessage::Event(WsEvent::Mention {
+                chat: _,
+                message,
+                author,
+            }) => {
+                assert_eq!(author, "alice");
+                mentioned.push(message);
+            }
+            WsMessage::Event(_) => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn lists_mentions() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+    let alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut bob_events).await;
+
+    let mut mentioning = vec![];
+    for text in ["@bob hi", "Anyone there?", "@bob ping", "@bob **ping**"] {
+        let (message, mentioned) =
+            post(&alice_events, &mut bob_events, text, text.contains("@bob")).await;
+        if text.contains("@bob") {
+            assert_eq!(mentioned, [message.timestamp]);
+            mentioning.push(message.timestamp);
+        } else {
+            assert!(mentioned.is_empty());
+        }
+    }
+    assert!(alice
+        .mentions(false, &PageQuery::default())
+        .await
+        .unwrap()
+        .is_empty());
+
+    let all = bob.mentions(false, &PageQuery::default()).await.unwrap();
+    let timestamps: Vec<u64> = all.iter().map(|m| m.message).collect();
+    assert_eq!(timestamps, mentioning);
+    assert!(all
+        .iter()
+        .all(|m| m.chat == id && m.author == "alice" && !m.read));
+    assert_eq!(all[1].text, "@bob ping");
+
+    // Pages go back from the latest mention
+    let page = PageQuery {
+        before: None,
+        limit: Some(2),
+    };
+    let latest = bob.mentions(false, &page).await.unwrap();
+    assert_eq!(latest.len(), 2);
+    assert_eq!(latest[0].id, all[1].id);
+    let page = PageQuery {
+        before: Some(latest[0].message),
+        limit: Some(2),
+    };
+    let older = bob.mentions(false, &page).await.unwrap();
+    assert_eq!(older.len(), 1);
+    assert_eq!(older[0].id, all[0].id);
+
+    bob.read_mention(&all[0].id).await.unwrap();
+    bob.read_mention(&all[0].id).await.unwrap();
+    let unread = bob.mentions(true, &PageQuery::default()).await.unwrap();
+    let ids: Vec<&str> = unread.iter().map(|m| m.id.as_str()).collect();
+    assert_eq!(ids, [all[1].id.as_str(), all[2].id.as_str()]);
+    let all = bob.mentions(false, &PageQuery::default()).await.unwrap();
+    assert!(all[0].read);
+
+    // Nobody reads the mentions of someone else
+    let error = alice.read_mention(&all[1].id).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = bob.read_mention("nothing").await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn keeps_muted_chats_quiet() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+    let alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut bob_events).await;
+    let mute = |mute: serde_json::Value| {
+        reqwest::Client::new()
+            .patch(format!("{}/chats/{id}/settings", server.url()))
+            .bearer_auth(&bob.session().unwrap().token)
+            .json(&serde_json::json!({ "mute": mute }))
+            .send()
+    };
+
+    let listed = |chats: Vec<UserChatView>| chats.into_iter().find(|c| c.chat.id == id).unwrap();
+    let before = listed(bob.chats().await.unwrap()).unread;
+
+    let res = mute(serde_json::json!({ "until": now() - 1000 }))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    let res = mute(serde_json::json!("forever")).await.unwrap();
+    assert!(res.status().is_success());
+    let (muted, mentioned) =
+        post(&alice_events, &mut bob_events, "@bob are you there?", false).await;
+    assert_eq!(muted.mentions, ["bob"]);
+    assert!(mentioned.is_empty());
+
+    let res = mute(serde_json::Value::Null).await.unwrap();
+    assert!(res.status().is_success());
+    // Mentions reach bob in order, the muted one would come first
+    let (unmuted, mentioned) = post(&alice_events, &mut bob_events, "@bob hello?", true).await;
+    assert_eq!(mentioned, [unmuted.timestamp]);
+
+    // Muting only silences notifications, the rest is kept
+    let mentions = bob.mentions(true, &PageQuery::default()).await.unwrap();
+    let timestamps: Vec<u64> = mentions.iter().map(|m| m.message).collect();
+    assert_eq!(timestamps, [muted.timestamp, unmuted.timestamp]);
+    let chat = listed(bob.chats().await.unwrap());
+    assert_eq!(chat.unread, before + 2);
+    assert!(!chat.settings.muted);
+}
@@ -0,0 +1,486 @@
+mod common;
+
+use common::{connected, next_command, next_event, next_message, Proxy, Server, TIMEOUT};
+use discorde_client::models::attachment::AttachmentRef;
+use discorde_client::models::chat::{ChatInput, Message, MessageKind, Quote, Retention};
+use discorde_client::models::now;
+use discorde_client::models::page::PageQuery;
+use discorde_client::models::ws::{WsEvent, WsMessage};
+use discorde_client::{Client, Event};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn general(alice: &Client) -> String {
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["bob".to_string()],
+    };
+    alice.create_chat(&input).await.unwrap().id
+}
+
+#[tokio::test]
+async fn sends_messages_over_the_websocket() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+
+    let mut alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut alice_events).await;
+    connected(&mut bob_events).await;
+
+    bob_events.send_text("Hi **alice**").unwrap();
+    let WsMessage::Command(cmd) = next_message(&mut alice_events).await else {
+        panic!("expected a message");
+    };
+    assert_eq!(cmd.from, "bob");
+    assert_eq!(cmd.message.message, "Hi **alice**");
+    assert!(!cmd.message.rich.is_empty());
+
+    let history = alice.messages(&id, &PageQuery::default()).await.unwrap();
+    let last = history.last().unwrap();
+    assert_eq!(last.author, "bob");
+    assert_eq!(last.kind, MessageKind::User);
+    assert_eq!(last.timestamp, cmd.message.timestamp);
+}
+
+#[tokio::test]
+async fn answers_commands_privately() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let id = general(&alice).await;
+
+    let mut events = alice.connect(&id).unwrap();
+    connected(&mut events).await;
+    events.send_text("/nope").unwrap();
+    let msg = next_message(&mut events).await;
+    assert!(matches!(msg, WsMessage::Event(WsEvent::Ephemeral { .. })));
+}
+
+#[tokio::test]
+async fn refuses_forged_timestamps() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let id = general(&alice).await;
+    let res = reqwest::Client::new()
+        .put(format!("{}/chats/{id}/retention", server.url()))
+        .bearer_auth(&alice.session().unwrap().token)
+        .json(&Retention::Days { days: 1 })
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+
+    let mut events = alice.connect(&id).unwrap();
+    connected(&mut events).await;
+    // Kept forever, or swept right away
+    for timestamp in [u64::MAX, 0, now() + 10 * 60 * 1000] {
+        let message = Message {
+            message: "forged".to_string(),
+            ..Message::at(timestamp)
+        };
+        events.send(message).unwrap();
+        let msg = next_message(&mut events).await;
+        assert!(matches!(msg, WsMessage::Event(WsEvent::Ephemeral { .. })));
+    }
+    events.send_text("on time").unwrap();
+
+    let start = Instant::now();
+    loop {
+        let history = alice.messages(&id, &PageQuery::default()).await.unwrap();
+        if history.iter().any(|m| m.message == "on time") {
+            assert!(history.iter().all(|m| m.message != "forged"));
+            break;
+        }
+        assert!(start.elapsed() < TIMEOUT, "the message wasn't stored");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn refuses_messages_taking_the_time_of_another() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+
+    let mut alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut alice_events).await;
+    connected(&mut bob_events).await;
+    let at = now();
+    let message = |text: &str| Message {
+        message: text.to_string(),
+        ..Message::at(at)
+    };
+    alice_events.send(message("first")).unwrap();
+    let WsMessage::Command(cmd) = next_message(&mut bob_events).await else {
+        panic!("expected a message");
+    };
+    assert_eq!(cmd.message.message, "first");
+
+    bob_events.send(message("second")).unwrap();
+    let msg = next_message(&mut bob_events).await;
+    assert!(matches!(msg, WsMessage::Event(WsEvent::Ephemeral { .. })));
+    let history = alice.messages(&id, &PageQuery::default()).await.unwrap();
+    let stored = history.iter().find(|m| m.timestamp == at).unwrap();
+    assert_eq!(stored.message, "first");
+}
+
+#[tokio::test]
+async fn moves_scheduled_messages_past_posted_ones() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+    let http = reqwest::Client::new();
+    let schedule = |message: &str, send_at: u64| {
+        http.post(format!("{}/chats/{id}/scheduled", server.url()))
+            .bearer_auth(&alice.session().unwrap().token)
+            .json(&serde_json::json!({ "message": message, "send_at": send_at }))
+            .send()
+    };
+    let mut alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut alice_events).await;
+    connected(&mut bob_events).await;
+
+    // The scheduler ticks every second, the first message tells when
+    let res = schedule("probe", now() + 100).await.unwrap();
+    assert!(res.status().is_success());
+    let probe = next_command(&mut bob_events).await;
+    assert_eq!(probe.message, "probe");
+    let tick = probe.timestamp + 3000;
+    let res = schedule("scheduled", tick - 500).await.unwrap();
+    assert!(res.status().is_success());
+
+    // Taking every time around the tick posting it
+    let taken = tick - 15..tick + 25;
+    for timestamp in taken.clone() {
+        let message = Message {
+            message: "posted".to_string(),
+            ..Message::at(timestamp)
+        };
+        alice_events.send(message).unwrap();
+    }
+    for _ in taken.clone() {
+        assert_eq!(next_command(&mut bob_events).await.message, "posted");
+    }
+    assert!(now() < tick, "posting took too long");
+
+    let scheduled = next_command(&mut bob_events).await;
+    assert_eq!(scheduled.message, "scheduled");
+    assert_eq!(scheduled.timestamp, taken.end);
+    // Both are kept
+    let history = alice.messages(&id, &PageQuery::default()).await.unwrap();
+    let stored = history.iter().find(|m| m.timestamp == taken.end).unwrap();
+    assert_eq!(stored.message, "scheduled");
+    let posted = history.iter().filter(|m| m.message == "posted").count();
+    assert_eq!(posted, taken.count());
+}
+
+#[tokio::test]
+async fn streams_reactions_and_pins() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+    let message = alice.messages(&id, &PageQuery::default()).await.unwrap()[0].timestamp;
+
+    let mut events = alice.connect(&id).unwrap();
+    connected(&mut events).await;
+
+    bob.react(&id, message, "🎉").await.unwrap();
+    match next_message(&mut events).await {
+        WsMessage::Event(WsEvent::ReactionAdd { emoji, user, .. }) => {
+            assert_eq!(emoji, "🎉");
+            assert_eq!(user, "bob");
+        }
+        msg => panic!("expected a reaction, got {msg:?}"),
+    }
+
+    bob.pin(&id, message).await.unwrap();
+    assert!(matches!(
+        next_message(&mut events).await,
+        WsMessage::Event(WsEvent::Pin { pinned: true, .. })
+    ));
+    assert_eq!(alice.pins(&id).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn reconnects_after_a_drop() {
+    let server = Server::start().await;
+    let proxy = Proxy::start(server.addr).await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+
+    let through_proxy = Client::new(&proxy.url())
+        .unwrap()
+        .with_session(alice.session().unwrap().clone());
+    let mut events = through_proxy.connect(&id).unwrap();
+    connected(&mut events).await;
+
+    proxy.cut();
+    assert!(matches!(next_event(&mut events).await, Event::Disconnected));
+    connected(&mut events).await;
+
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut bob_events).await;
+    bob_events.send_text("Still there?").unwrap();
+    let WsMessage::Command(cmd) = next_message(&mut events).await else {
+        panic!("expected a message");
+    };
+    assert_eq!(cmd.message.message, "Still there?");
+}
+
+/// Open chat websockets, as the metrics tell
+async fn websockets(server: &Server) -> String {
+    let metrics = reqwest::get(server.url() + "/metrics")
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix("discorde_websockets "))
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+async fn forgets_websockets_dropped_without_closing() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let id = general(&alice).await;
+    let token = &alice.session().unwrap().token;
+
+    // A bare client going away without a Close frame, as when its network drops
+    let mut socket = TcpStream::connect(server.addr).await.unwrap();
+    let handshake = format!(
+        "GET /chats/{id} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Protocol: realProtocol, {token}\r\n\r\n",
+        server.addr
+    );
+    socket.write_all(handshake.as_bytes()).await.unwrap();
+    let mut response = vec![0; 1024];
+    let read = socket.read(&mut response).await.unwrap();
+    assert!(response[..read].starts_with(b"HTTP/1.1 101"));
+    // Answers the server's ping with an empty masked pong
+    socket.write_all(&[0x8a, 0x80, 0, 0, 0, 0]).await.unwrap();
+
+    let start = Instant::now();
+    while websockets(&server).await != "1" {
+        assert!(start.elapsed() < TIMEOUT, "the websocket wasn't counted");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    drop(socket);
+    while websockets(&server).await != "0" {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "the dropped websocket is still counted"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn refused_connections_end_the_stream() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let carol = server.user("carol").await;
+    let id = general(&alice).await;
+
+    let mut events = carol.connect(&id).unwrap();
+    let end = tokio::time::timeout(common::TIMEOUT, events.next()).await;
+    assert!(end.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn leaves_nothing_of_expired_messages() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+    let http = reqwest::Client::new();
+    let token = |client: &Client| client.session().unwrap().token.clone();
+
+    let res = http
+        .put(format!("{}/chats/{id}/retention", server.url()))
+        .bearer_auth(token(&alice))
+        .json(&Retention::Ephemeral { seconds: 1 })
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+
+    let body = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        secret notes\r\n\
+        --boundary--\r\n";
+    let attachment: AttachmentRef = http
+        .post(format!("{}/chats/{id}/attachments", server.url()))
+        .bearer_auth(token(&alice))
+        .header("content-type", "multipart/form-data; boundary=boundary")
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(server.blobs(), 1);
+
+    let mut alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut alice_events).await;
+    connected(&mut bob_events).await;
+
+    alice_events
+        .send(Message {
+            message: "the secret".to_string(),
+            attachments: vec![attachment.clone()],
+            ..Message::at(now())
+        })
+        .unwrap();
+    let WsMessage::Command(secret) = next_message(&mut bob_events).await else {
+        panic!("expected a message");
+    };
+    let secret = secret.message.timestamp;
+    bob_events
+        .send(Message {
+            message: "got it".to_string(),
+            reply_to: Some(Quote {
+                timestamp: secret,
+                author: String::new(),
+                message: String::new(),
+            }),
+            ..Message::at(now())
+        })
+        .unwrap();
+    next_message(&mut alice_events).await;
+
+    // Bob reading the secret starts its countdown, his reply stays unread
+    bob.read(&id, secret).await.unwrap();
+    loop {
+        match next_message(&mut alice_events).await {
+            WsMessage::Event(WsEvent::MessageDelete { message }) if message == secret => break,
+            _ => {}
+        }
+    }
+
+    let history = alice.messages(&id, &PageQuery::default()).await.unwrap();
+    assert!(history.iter().all(|m| m.timestamp != secret));
+    let reply = history.iter().find(|m| m.message == "got it").unwrap();
+    let quote = reply.reply_to.as_ref().unwrap();
+    assert_eq!(quote.timestamp, secret);
+    assert!(quote.author.is_empty() && quote.message.is_empty());
+
+    let res = http
+        .get(format!("{}/attachments/{}", server.url(), attachment.id))
+        .bearer_auth(token(&alice))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+    assert_eq!(server.blobs(), 0);
+}
+
+#[tokio::test]
+async fn posts_through_incoming_hooks() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let id = general(&alice).await;
+    let http = reqwest::Client::new();
+
+    let hook: serde_json::Value = http
+        .post(format!("{}/chats/{id}/hooks", server.url()))
+        .bearer_auth(&alice.session().unwrap().token)
+        .json(&serde_json::json!({ "name": "CI" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let hook_id = hook["id"].as_str().unwrap();
+    let post = |token: &str, body: serde_json::Value| {
+        http.post(format!("{}/hooks/{token}", server.url()))
+            .json(&body)
+            .send()
+    };
+
+    let mut events = bob.connect(&id).unwrap();
+    connected(&mut events).await;
+
+    // The token is the only credential
+    let token = hook["token"].as_str().unwrap();
+    let res = post(
+        token,
+        serde_json::json!({ "message": "Build **passed** @bob" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+    let posted: Message = res.json().await.unwrap();
+
+    // Bob is told about the mention too
+    let cmd = loop {
+        if let WsMessage::Command(cmd) = next_message(&mut events).await {
+            break cmd;
+        }
+    };
+    assert_eq!(cmd.from, hook_id);
+    assert_eq!(cmd.message.timestamp, posted.timestamp);
+    assert_eq!(cmd.message.kind, MessageKind::Webhook);
+    assert_eq!(cmd.message.nick.as_deref(), Some("CI"));
+    assert_eq!(cmd.message.message, "Build **passed** @bob");
+    assert!(!cmd.message.rich.is_empty());
+    assert_eq!(cmd.message.mentions, ["bob"]);
+
+    let history = bob.messages(&id, &PageQuery::default()).await.unwrap();
+    let stored = history.last().unwrap();
+    assert_eq!(stored.timestamp, posted.timestamp);
+    assert_eq!(stored.kind, MessageKind::Webhook);
+    assert_eq!(
+        bob.mentions(false, &PageQuery::default())
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // A name given with the message only applies to it
+    let res = post(
+        token,
+        serde_json::json!({ "message": "Deployed", "name": "CD" }),
+    )
+    .await
+    .unwrap();
+    let cmd = loop {
+        if let WsMessage::Command(cmd) = next_message(&mut events).await {
+            break cmd;
+        }
+    };
+    assert_eq!(cmd.message.nick.as_deref(), Some("CD"));
+    assert_eq!(
+        res.json::<Message>().await.unwrap().nick.as_deref(),
+        Some("CD")
+    );
+
+    let res = post("nope", serde_json::json!({ "message": "Hi" }))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+}
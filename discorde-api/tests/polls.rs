@@ -0,0 +1,148 @@
+mod common;
+
+use common::{connected, next_message, post, Server};
+use discorde_client::models::chat::{ChatInput, Message};
+use discorde_client::models::now;
+use discorde_client::models::page::PageQuery;
+use discorde_client::models::poll::{Poll, PollOption};
+use discorde_client::models::ws::{WsEvent, WsMessage};
+use discorde_client::Events;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+fn poll(options: &[&str], multiple: bool, closes_at: Option<u64>) -> Message {
+    Message {
+        poll: Some(Poll {
+            question: "Where do we eat?".to_string(),
+            options: options
+                .iter()
+                .map(|text| PollOption {
+                    text: text.to_string(),
+                    votes: ["mallory".to_string()].into(),
+                })
+                .collect(),
+            multiple,
+            closes_at,
+        }),
+        ..Message::at(now())
+    }
+}
+
+/// Next tally of a poll, as `(message, tally, voters)`
+async fn next_tally(events: &mut Events) -> (u64, Vec<usize>, usize) {
+    loop {
+        if let WsMessage::Event(WsEvent::PollUpdate {
+            message,
+            tally,
+            voters,
+        }) = next_message(events).await
+        {
+            return (message, tally, voters);
+        }
+    }
+}
+
+#[tokio::test]
+async fn tallies_votes_until_the_poll_closes() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let carol = server.user("carol").await;
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["bob".to_string(), "carol".to_string()],
+    };
+    let id = alice.create_chat(&input).await.unwrap().id;
+    let alice_events = alice.connect(&id).unwrap();
+    let carol_events = carol.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut bob_events).await;
+
+    let closes_at = now() + 2000;
+    let message = poll(&["Pizza", "Sushi", "Tacos"], false, Some(closes_at));
+    let posted = post(&alice_events, &mut bob_events, message).await;
+    let ts = posted.timestamp;
+    // Votes sent along with the poll are thrown away
+    let view = bob.vote(&id, ts, vec![]).await.unwrap();
+    assert_eq!(view.voters, 0);
+    assert!(!view.closed);
+    assert_eq!(next_tally(&mut bob_events).await, (ts, vec![0, 0, 0], 0));
+
+    let view = bob.vote(&id, ts, vec![1]).await.unwrap();
+    assert!(view.options[1].me);
+    assert_eq!(next_tally(&mut bob_events).await, (ts, vec![0, 1, 0], 1));
+    carol_events.vote(ts, vec![1]).unwrap();
+    assert_eq!(next_tally(&mut bob_events).await, (ts, vec![0, 2, 0], 2));
+    // A new ballot replaces the previous one
+    bob.vote(&id, ts, vec![2]).await.unwrap();
+    assert_eq!(next_tally(&mut bob_events).await, (ts, vec![0, 1, 1], 2));
+
+    let error = bob.vote(&id, ts, vec![0, 1]).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = bob.vote(&id, ts, vec![3]).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = bob.vote(&id, ts + 1, vec![0]).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+
+    let history = alice.messages(&id, &PageQuery::default()).await.unwrap();
+    let view = history
+        .iter()
+        .find(|m| m.timestamp == ts)
+        .and_then(|m| m.poll.clone())
+        .unwrap();
+    let votes: Vec<usize> = view.options.iter().map(|o| o.votes).collect();
+    assert_eq!(votes, [0, 1, 1]);
+    assert!(view.options.iter().all(|o| !o.me));
+
+    tokio::time::sleep(Duration::from_millis(closes_at.saturating_sub(now()) + 10)).await;
+    let error = bob.vote(&id, ts, vec![0]).await.unwrap_err();
+    assert!(error.to_string().contains("409"), "{error}");
+    let history = bob.messages(&id, &PageQuery::default()).await.unwrap();
+    let view = history.iter().find(|m| m.timestamp == ts).unwrap();
+    let view = view.poll.as_ref().unwrap();
+    assert!(view.closed);
+    assert!(view.options[2].me);
+}
+
+#[tokio::test]
+async fn takes_several_choices_when_allowed() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["bob".to_string()],
+    };
+    let id = alice.create_chat(&input).await.unwrap().id;
+    let mut alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut alice_events).await;
+    connected(&mut bob_events).await;
+
+    let message = poll(&["Pizza", "Sushi", "Tacos"], true, None);
+    let ts = post(&alice_events, &mut bob_events, message)
+        .await
+        .timestamp;
+    let view = bob.vote(&id, ts, vec![0, 2]).await.unwrap();
+    assert_eq!(view.voters, 1);
+    let votes: Vec<usize> = view.options.iter().map(|o| o.votes).collect();
+    assert_eq!(votes, [1, 0, 1]);
+    // An empty ballot withdraws the vote
+    let view = bob.vote(&id, ts, vec![]).await.unwrap();
+    assert_eq!(view.voters, 0);
+
+    // Polls closing in the past are refused to their author
+    alice_events
+        .send(poll(&["Now", "Never"], false, Some(now() - 1000)))
+        .unwrap();
+    loop {
+        if let WsMessage::Event(WsEvent::Ephemeral { message }) =
+            next_message(&mut alice_events).await
+        {
+            assert!(message.contains("in the past"), "{message}");
+            break;
+        }
+    }
+}
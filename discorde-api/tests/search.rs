@@ -0,0 +1,147 @@
+mod common;
+
+use common::{connected, post, Server};
+use discorde_api::Database;
+use discorde_client::models::chat::{ChatInput, Message};
+use discorde_client::models::now;
+use discorde_client::{Client, Events};
+use serde_json::Value;
+
+fn message(text: &str) -> Message {
+    Message {
+        message: text.to_string(),
+        ..Message::at(now())
+    }
+}
+
+async fn chat(owner: &Client, name: &str, members: &[&str]) -> String {
+    let input = ChatInput {
+        private: false,
+        name: name.to_string(),
+        members: members.iter().map(ToString::to_string).collect(),
+    };
+    owner.create_chat(&input).await.unwrap().id
+}
+
+async fn login(server: &Server, username: &str) -> Client {
+    let mut client = server.client();
+    client.login(username, "password").await.unwrap();
+    client
+}
+
+/// Texts of the hits of `query`, best first
+async fn search(server: &Server, client: &Client, query: &[(&str, &str)]) -> Vec<(String, String)> {
+    let hits: Vec<Value> = reqwest::Client::new()
+        .get(format!("{}/search", server.url()))
+        .bearer_auth(&client.session().unwrap().token)
+        .query(query)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let scores: Vec<f64> = hits.iter().map(|h| h["score"].as_f64().unwrap()).collect();
+    assert!(scores.is_sorted_by(|a, b| a >= b), "{scores:?}");
+    hits.iter()
+        .map(|hit| {
+            let chat = hit["chat"].as_str().unwrap().to_string();
+            (
+                chat,
+                hit["message"]["message"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+async fn post_all(sender: &Events, listener: &mut Events, texts: &[&str]) {
+    for text in texts {
+        post(sender, listener, message(text)).await;
+    }
+}
+
+#[tokio::test]
+async fn ranks_hits_in_the_chats_of_the_user() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let carol = server.user("carol").await;
+    let general = chat(&alice, "general", &["bob"]).await;
+    let secret = chat(&carol, "secret", &["bob"]).await;
+    let alice_events = alice.connect(&general).unwrap();
+    let carol_events = carol.connect(&secret).unwrap();
+    let mut bob_general = bob.connect(&general).unwrap();
+    let mut bob_secret = bob.connect(&secret).unwrap();
+    connected(&mut bob_general).await;
+    connected(&mut bob_secret).await;
+
+    post_all(
+        &alice_events,
+        &mut bob_general,
+        &[
+            "Who brings the cake on Friday?",
+            "Cake, cake and more cake",
+            "The meeting moved to Monday",
+        ],
+    )
+    .await;
+    post_all(&carol_events, &mut bob_secret, &["Surprise cake for alice"]).await;
+
+    let hits = search(&server, &alice, &[("q", "CAKE")]).await;
+    let texts: Vec<&str> = hits.iter().map(|(_, text)| text.as_str()).collect();
+    assert_eq!(
+        texts,
+        ["Cake, cake and more cake", "Who brings the cake on Friday?"]
+    );
+    assert!(hits.iter().all(|(chat, _)| *chat == general));
+
+    // Every term must match
+    let hits = search(&server, &alice, &[("q", "cake friday")]).await;
+    assert_eq!(hits.len(), 1);
+    assert!(search(&server, &alice, &[("q", "cake pie")])
+        .await
+        .is_empty());
+
+    // Bob is in both chats, and can narrow it down to one
+    assert_eq!(search(&server, &bob, &[("q", "cake")]).await.len(), 3);
+    let hits = search(&server, &bob, &[("q", "cake"), ("chat", &secret)]).await;
+    assert_eq!(
+        hits,
+        [(secret.clone(), "Surprise cake for alice".to_string())]
+    );
+    let hits = search(&server, &carol, &[("q", "cake"), ("chat", &general)]).await;
+    assert!(hits.is_empty());
+}
+
+#[tokio::test]
+async fn reindexes_stored_messages() {
+    let mut server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let general = chat(&alice, "general", &["bob"]).await;
+    let alice_events = alice.connect(&general).unwrap();
+    let mut bob_events = bob.connect(&general).unwrap();
+    connected(&mut bob_events).await;
+    post_all(
+        &alice_events,
+        &mut bob_events,
+        &["Lunch at noon", "Noon it is"],
+    )
+    .await;
+
+    // Losing the index loses every hit
+    server.stop().await.unwrap();
+    std::fs::remove_file(server.database().join("search.json")).unwrap();
+    server.restart().await;
+    let alice = login(&server, "alice").await;
+    assert!(search(&server, &alice, &[("q", "noon")]).await.is_empty());
+
+    server.stop().await.unwrap();
+    let count = Database::reindex(server.database().to_path_buf())
+        .await
+        .unwrap();
+    assert!(count >= 2, "{count}");
+    server.restart().await;
+    let alice = login(&server, "alice").await;
+    assert_eq!(search(&server, &alice, &[("q", "noon")]).await.len(), 2);
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use common::{connected, next_event, next_message, Server};
+use discorde_api::config::Config;
+use discorde_client::models::chat::ChatInput;
+use discorde_client::models::now;
+use discorde_client::models::page::PageQuery;
+use discorde_client::models::ws::WsMessage;
+use discorde_client::Event;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+#[tokio::test]
+async fn shuts_down_gracefully() {
+    let mut server = Server::start().await;
+    let alice = server.user("alice").await;
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec![],
+    };
+    let id = alice.create_chat(&input).await.unwrap().id;
+    let mut events = alice.connect(&id).unwrap();
+    connected(&mut events).await;
+    assert!(server.is_locked());
+
+    server.stop().await.unwrap();
+    // Websockets are closed rather than left hanging
+    assert!(matches!(next_event(&mut events).await, Event::Disconnected));
+    assert!(!server.is_locked());
+    assert!(alice.chats().await.is_err());
+}
+
+#[tokio::test]
+async fn keeps_scheduled_messages_across_shutdowns() {
+    let mut server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["bob".to_string()],
+    };
+    let id = alice.create_chat(&input).await.unwrap().id;
+    let http = reqwest::Client::new();
+    let schedule = |message: &str, send_at: u64| {
+        http.post(format!("{}/chats/{id}/scheduled", server.url()))
+            .bearer_auth(&alice.session().unwrap().token)
+            .json(&json!({ "message": message, "send_at": send_at }))
+            .send()
+    };
+    assert!(schedule("soon", now() + 1000)
+        .await
+        .unwrap()
+        .status()
+        .is_success());
+    assert!(schedule("later", now() + 3_600_000)
+        .await
+        .unwrap()
+        .status()
+        .is_success());
+
+    // Stopping right after a tick posted the first one
+    let mut events = bob.connect(&id).unwrap();
+    connected(&mut events).await;
+    loop {
+        if let WsMessage::Command(cmd) = next_message(&mut events).await {
+            assert_eq!(cmd.message.message, "soon");
+            break;
+        }
+    }
+    server.stop().await.unwrap();
+    assert!(!server.is_locked());
+
+    server.restart().await;
+    let mut alice = server.client();
+    alice.login("alice", "password").await.unwrap();
+    let scheduled: Vec<Value> = reqwest::Client::new()
+        .get(format!("{}/chats/{id}/scheduled", server.url()))
+        .bearer_auth(&alice.session().unwrap().token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(scheduled.len(), 1);
+    assert_eq!(scheduled[0]["message"], "later");
+    // Posted once, before the shutdown
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let messages = alice.messages(&id, &PageQuery::default()).await.unwrap();
+    assert_eq!(messages.iter().filter(|m| m.message == "soon").count(), 1);
+    server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn serves_http2_over_tls() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let server = Server::start_with(Config {
+        tls_cert: Some(fixtures.join("cert.pem")),
+        tls_key: Some(fixtures.join("key.pem")),
+        ..Config::default()
+    })
+    .await;
+    let url = format!("https://{}/users", server.addr);
+
+    // The certificate is self-signed
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.version(), reqwest::Version::HTTP_2);
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    // Clients without HTTP/2 fall back to HTTP/1.1
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .http1_only()
+        .build()
+        .unwrap();
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.version(), reqwest::Version::HTTP_11);
+
+    // Plain HTTP isn't answered
+    assert!(reqwest::get(server.url() + "/users").await.is_err());
+}
+
+#[tokio::test]
+async fn reports_health_and_metrics() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec![],
+    };
+    let id = alice.create_chat(&input).await.unwrap().id;
+    let mut events = alice.connect(&id).unwrap();
+    connected(&mut events).await;
+
+    // Marking messages read goes through the db queue like any other request
+    let messages = alice.messages(&id, &PageQuery::default()).await.unwrap();
+    alice.read(&id, messages[0].timestamp).await.unwrap();
+
+    let get = |path: &str| reqwest::get(server.url() + path);
+    assert!(get("/healthz").await.unwrap().status().is_success());
+    assert!(get("/readyz").await.unwrap().status().is_success());
+
+    let metrics = get("/metrics").await.unwrap().text().await.unwrap();
+    let lines = metrics.lines().collect::<Vec<_>>();
+    assert!(lines
+        .contains(&r#"discorde_http_requests_total{method="POST",route="/chats",status="201"} 1"#));
+    assert!(lines.contains(&"discorde_websockets 1"));
+    assert!(lines.contains(&"discorde_chat_channels 1"));
+    // The creation of the chat is recorded as a message
+    assert!(lines.contains(&"discorde_messages_sent_total 1"));
+    assert!(
+        lines.contains(&r#"discorde_db_request_duration_seconds_count{request="insert_chat"} 1"#)
+    );
+    assert!(
+        lines.contains(&r#"discorde_db_request_duration_seconds_count{request="read_messages"} 1"#)
+    );
+    assert!(lines.contains(&"discorde_db_queue_depth 0"));
+}
+
+#[tokio::test]
+async fn tags_responses_with_request_ids() {
+    let server = Server::start().await;
+    let client = reqwest::Client::new();
+    let url = server.url() + "/healthz";
+
+    let res = client.get(&url).send().await.unwrap();
+    assert!(res.headers().contains_key("x-request-id"));
+    // IDs set by a proxy in front are kept
+    let res = client
+        .get(&url)
+        .header("x-request-id", "from-proxy")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()["x-request-id"], "from-proxy");
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::{connected, next_message, post, Server};
+use discorde_client::models::chat::{ChatInput, Message, Quote};
+use discorde_client::models::now;
+use discorde_client::models::page::PageQuery;
+use discorde_client::models::ws::{WsEvent, WsMessage};
+use std::io::ErrorKind;
+
+fn message(text: &str) -> Message {
+    Message {
+        message: text.to_string(),
+        ..Message::at(now())
+    }
+}
+
+#[tokio::test]
+async fn keeps_threads_out_of_the_history() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["bob".to_string()],
+    };
+    let id = alice.create_chat(&input).await.unwrap().id;
+    let mut alice_events = alice.connect(&id).unwrap();
+    let mut bob_events = bob.connect(&id).unwrap();
+    connected(&mut bob_events).await;
+
+    let root = post(&alice_events, &mut bob_events, message("Lunch?")).await;
+    let mut replies = vec![];
+    for text in ["Pizza", "Sushi", "Tacos", "Salad"] {
+        let reply = Message {
+            thread: Some(root.timestamp),
+            ..message(text)
+        };
+        replies.push(post(&alice_events, &mut bob_events, reply).await);
+    }
+    // A quote stays in the history, carrying what it quotes
+    let quote = Message {
+        reply_to: Some(Quote {
+            timestamp: root.timestamp,
+            author: String::new(),
+            message: String::new(),
+        }),
+        ..message("Anything but tacos")
+    };
+    let quote = post(&alice_events, &mut bob_events, quote).await;
+    let quoted = quote.reply_to.unwrap();
+    assert_eq!(quoted.author, "alice");
+    assert_eq!(quoted.message, "Lunch?");
+
+    let history = bob.messages(&id, &PageQuery::default()).await.unwrap();
+    assert!(history.iter().all(|m| m.thread.is_none()));
+    let listed = history
+        .iter()
+        .find(|m| m.timestamp == root.timestamp)
+        .unwrap();
+    assert_eq!(listed.replies, 4);
+    assert!(history.iter().any(|m| m.message == "Anything but tacos"));
+
+    // Pages go back from the latest reply
+    let page = PageQuery {
+        before: None,
+        limit: Some(3),
+    };
+    let latest = bob.thread(&id, root.timestamp, &page).await.unwrap();
+    let texts: Vec<&str> = latest.iter().map(|m| m.message.as_str()).collect();
+    assert_eq!(texts, ["Sushi", "Tacos", "Salad"]);
+    let page = PageQuery {
+        before: Some(latest[0].timestamp),
+        limit: Some(3),
+    };
+    let older = bob.thread(&id, root.timestamp, &page).await.unwrap();
+    assert_eq!(older.len(), 1);
+    assert_eq!(older[0].timestamp, replies[0].timestamp);
+
+    // Replies don't start threads of their own
+    let error = bob
+        .thread(&id, replies[0].timestamp, &PageQuery::default())
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let nested = Message {
+        thread: Some(replies[0].timestamp),
+        ..message("Nested")
+    };
+    alice_events.send(nested).unwrap();
+    loop {
+        if let WsMessage::Event(WsEvent::Ephemeral { message }) =
+            next_message(&mut alice_events).await
+        {
+            assert!(message.contains("already part of a thread"), "{message}");
+            break;
+        }
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::Server;
+use discorde_client::models::user::UserInput;
+use std::io::ErrorKind;
+
+#[tokio::test]
+async fn signs_up_and_logs_in() {
+    let server = Server::start().await;
+    let mut client = server.client();
+
+    client.create_user("alice", "password").await.unwrap();
+    let user = client.login("alice", "password").await.unwrap();
+    assert_eq!(user.username, "alice");
+    assert!(!user.bot);
+    assert_eq!(client.session().unwrap().username, "alice");
+
+    let users = client.users().await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(client.user("alice").await.unwrap().username, "alice");
+}
+
+#[tokio::test]
+async fn refuses_bad_credentials() {
+    let server = Server::start().await;
+    let mut client = server.client();
+    client.create_user("alice", "password").await.unwrap();
+
+    let error = client.login("alice", "wrong").await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = client.create_user("alice", "other").await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    // Nothing but signing up works without logging in
+    let error = client.users().await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn finds_users() {
+    let server = Server::start().await;
+    let client = server.user("alice").await;
+
+    let error = client.user("nobody").await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn keeps_secrets_out_of_debug_output() {
+    let input = UserInput {
+        username: "alice".to_string(),
+        password: "hunter2".to_string(),
+    };
+    let printed = format!("{input:?}");
+    assert!(printed.contains("alice"));
+    assert!(!printed.contains("hunter2"));
+}
@@ -0,0 +1,315 @@
+mod common;
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::Router;
+use common::{connected, next_message, Server, TIMEOUT};
+use discorde_api::config::Config;
+use discorde_client::models::chat::ChatInput;
+use discorde_client::models::page::PageQuery;
+use discorde_client::models::webhook::DeadLetter;
+use discorde_client::models::ws::{WsEvent, WsMessage};
+use discorde_client::Client;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::future::IntoFuture;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Request received by the stand-in
+struct Delivery {
+    path: String,
+    event: String,
+    signature: String,
+    body: String,
+    at: Instant,
+}
+
+/// Answers left to give, the last one being given from then on
+type Answers = Arc<Mutex<Vec<StatusCode>>>;
+
+/// Local receiver standing in for the webhook, answering with `answers` in turn
+async fn stand_in(answers: Vec<StatusCode>) -> (String, mpsc::UnboundedReceiver<Delivery>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let answers: Answers = Arc::new(Mutex::new(answers));
+    let app = Router::new().fallback(
+        move |State(answers): State<Answers>, uri: Uri, headers: HeaderMap, body: Bytes| {
+            let tx = tx.clone();
+            async move {
+                let header = |name: &str| {
+                    headers
+                        .get(name)
+                        .map(|v| v.to_str().unwrap().to_string())
+                        .unwrap_or_default()
+                };
+                _ = tx.send(Delivery {
+                    path: uri.path().to_string(),
+                    event: header("x-discorde-event"),
+                    signature: header("x-discorde-signature"),
+                    body: String::from_utf8(body.to_vec()).unwrap(),
+                    at: Instant::now(),
+                });
+                let mut answers = answers.lock().unwrap();
+                let status = if answers.len() > 1 {
+                    answers.remove(0)
+                } else {
+                    answers[0]
+                };
+                (status, [(header::LOCATION, "/elsewhere")]).into_response()
+            }
+        },
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(axum::serve(listener, app.with_state(answers)).into_future());
+    (url, rx)
+}
+
+async fn next_delivery(deliveries: &mut mpsc::UnboundedReceiver<Delivery>) -> Delivery {
+    // Retries wait up to 8 seconds
+    tokio::time::timeout(Duration::from_secs(10), deliveries.recv())
+        .await
+        .expect("no delivery came")
+        .unwrap()
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Chat of `alice` with a webhook at `url`, returns the ids of both and the webhook secret
+async fn chat_with_webhook(server: &Server, alice: &Client, url: &str) -> (String, String, String) {
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec!["bob".to_string()],
+    };
+    let chat = alice.create_chat(&input).await.unwrap().id;
+    let res = reqwest::Client::new()
+        .post(format!("{}/chats/{chat}/webhooks", server.url()))
+        .bearer_auth(&alice.session().unwrap().token)
+        .json(&json!({ "url": url, "events": ["message.create", "message.edit"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+    let webhook: Value = res.json().await.unwrap();
+    let id = webhook["id"].as_str().unwrap().to_string();
+    let secret = webhook["secret"].as_str().unwrap().to_string();
+    (chat, id, secret)
+}
+
+async fn private_server() -> Server {
+    Server::start_with(Config {
+        allow_private_webhooks: true,
+        ..Config::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn retries_signed_deliveries() {
+    let (url, mut deliveries) = stand_in(vec![
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::NO_CONTENT,
+    ])
+    .await;
+    let server = private_server().await;
+    let alice = server.user("alice").await;
+    let bob = server.user("bob").await;
+    let (chat, webhook, secret) = chat_with_webhook(&server, &alice, &url).await;
+
+    let mut events = alice.connect(&chat).unwrap();
+    connected(&mut events).await;
+    events.send_text("hello").unwrap();
+
+    let first = next_delivery(&mut deliveries).await;
+    assert_eq!(first.event, "message.create");
+    assert_eq!(first.signature, sign(&secret, &first.body));
+    let payload: Value = serde_json::from_str(&first.body).unwrap();
+    assert_eq!(payload["chat"], chat.as_str());
+    assert_eq!(payload["data"]["message"], "hello");
+
+    // The same delivery comes back after 1 then 2 seconds
+    let mut last = first.at;
+    for delay in [1, 2] {
+        let retry = next_delivery(&mut deliveries).await;
+        assert_eq!(retry.body, first.body);
+        assert_eq!(retry.signature, first.signature);
+        assert!(retry.at - last >= Duration::from_secs(delay));
+        last = retry.at;
+    }
+
+    let message = alice.messages(&chat, &PageQuery::default()).await.unwrap();
+    let message = message.last().unwrap().timestamp;
+    let refused = bob.edit_message(&chat, message, "hijacked").await;
+    assert_eq!(
+        refused.unwrap_err().kind(),
+        std::io::ErrorKind::PermissionDenied
+    );
+    alice
+        .edit_message(&chat, message, "hello *again*")
+        .await
+        .unwrap();
+
+    loop {
+        if let WsMessage::Event(WsEvent::MessageEdit { text, rich, .. }) =
+            next_message(&mut events).await
+        {
+            assert_eq!(text, "hello *again*");
+            assert_eq!(rich.len(), 2);
+            break;
+        }
+    }
+    let edit = next_delivery(&mut deliveries).await;
+    assert_eq!(edit.event, "message.edit");
+    assert_eq!(edit.signature, sign(&secret, &edit.body));
+    let payload: Value = serde_json::from_str(&edit.body).unwrap();
+    assert_eq!(payload["data"]["message"], message);
+    assert_eq!(payload["data"]["text"], "hello *again*");
+
+    let failures: Vec<DeadLetter> = reqwest::Client::new()
+        .get(format!(
+            "{}/chats/{chat}/webhooks/{webhook}/failures",
+            server.url()
+        ))
+        .bearer_auth(&alice.session().unwrap().token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(failures.is_empty());
+}
+
+#[tokio::test]
+async fn files_abandoned_deliveries_as_dead_letters() {
+    // Redirects are not followed, they count as failures
+    let (url, mut deliveries) = stand_in(vec![StatusCode::TEMPORARY_REDIRECT]).await;
+    let server = private_server().await;
+    let alice = server.user("alice").await;
+    let (chat, webhook, _) = chat_with_webhook(&server, &alice, &url).await;
+
+    let mut events = alice.connect(&chat).unwrap();
+    connected(&mut events).await;
+    events.send_text("lost").unwrap();
+
+    let first = next_delivery(&mut deliveries).await;
+    let mut last = first.at;
+    for delay in [1, 2, 4, 8] {
+        let retry = next_delivery(&mut deliveries).await;
+        assert_eq!(retry.path, "/hook");
+        assert!(retry.at - last >= Duration::from_secs(delay));
+        last = retry.at;
+    }
+
+    let url = format!("{}/chats/{chat}/webhooks/{webhook}/failures", server.url());
+    let start = Instant::now();
+    let letter = loop {
+        let failures: Vec<DeadLetter> = reqwest::Client::new()
+            .get(&url)
+            .bearer_auth(&alice.session().unwrap().token)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if let Some(letter) = failures.into_iter().next() {
+            break letter;
+        }
+        assert!(start.elapsed() < TIMEOUT, "no dead letter was filed");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    assert_eq!(letter.event, "message.create");
+    assert_eq!(letter.attempts, 5);
+    assert_eq!(letter.payload, first.body);
+    assert!(letter.error.contains("307"), "{}", letter.error);
+    assert!(deliveries.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn files_pending_retries_on_shutdown() {
+    let (url, mut deliveries) = stand_in(vec![StatusCode::SERVICE_UNAVAILABLE]).await;
+    let mut server = private_server().await;
+    let alice = server.user("alice").await;
+    let (chat, webhook, _) = chat_with_webhook(&server, &alice, &url).await;
+
+    let mut events = alice.connect(&chat).unwrap();
+    connected(&mut events).await;
+    events.send_text("interrupted").unwrap();
+    let first = next_delivery(&mut deliveries).await;
+
+    // The retry waits a second, the shutdown doesn't
+    let start = Instant::now();
+    server.stop().await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    server.restart().await;
+    let mut alice = server.client();
+    alice.login("alice", "password").await.unwrap();
+    let failures: Vec<DeadLetter> = reqwest::Client::new()
+        .get(format!(
+            "{}/chats/{chat}/webhooks/{webhook}/failures",
+            server.url()
+        ))
+        .bearer_auth(&alice.session().unwrap().token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].attempts, 1);
+    assert_eq!(failures[0].payload, first.body);
+    assert!(
+        failures[0].error.contains("shut down"),
+        "{}",
+        failures[0].error
+    );
+    assert!(deliveries.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn refuses_private_webhook_urls() {
+    let server = Server::start().await;
+    let alice = server.user("alice").await;
+    let input = ChatInput {
+        private: false,
+        name: "general".to_string(),
+        members: vec![],
+    };
+    let chat = alice.create_chat(&input).await.unwrap().id;
+    let create = |url: &str| {
+        reqwest::Client::new()
+            .post(format!("{}/chats/{chat}/webhooks", server.url()))
+            .bearer_auth(&alice.session().unwrap().token)
+            .json(&json!({ "url": url }))
+            .send()
+    };
+
+    for url in [
+        "http://127.0.0.1:8080/hook",
+        "http://[::1]/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://10.0.0.1/hook",
+        "http://192.168.1.1/hook",
+        "http://[::ffff:127.0.0.1]/hook",
+        "ftp://example.com/hook",
+    ] {
+        let status = create(url).await.unwrap().status();
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST, "{url}");
+    }
+    let status = create("https://example.com/hook").await.unwrap().status();
+    assert_eq!(status, reqwest::StatusCode::CREATED);
+}
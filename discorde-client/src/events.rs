@@ -0,0 +1,193 @@
+use crate::Session;
+use discorde_api::models::chat::Message;
+use discorde_api::models::now;
+use discorde_api::models::ws::{WsCommand, WsMessage, WsRequest};
+use futures_util::{SinkExt, Stream, StreamExt};
+use reqwest::Url;
+use std::collections::VecDeque;
+use std::io::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::{self, Message as Frame};
+use tracing::warn;
+
+/// Wait before the first reconnection, doubled after every failure
+const FIRST_RETRY: Duration = Duration::from_millis(500);
+const MAX_RETRY: Duration = Duration::from_secs(30);
+
+/// What happens on a chat connection
+#[derive(Debug, Clone)]
+// Nearly every event is a message, boxing them would only add an allocation
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    /// The websocket is open, anything sent while it was down may have been missed
+    Connected,
+    /// The websocket dropped, it will be opened again after a while
+    Disconnected,
+    Message(WsMessage),
+}
+
+/// Events of a chat, read with [`Events::next`] or as a [`Stream`]
+///
+/// The stream ends when the server refuses the connection, because the token isn't valid or
+/// the user isn't a member of the chat.
+pub struct Events {
+    rx: UnboundedReceiver<Event>,
+    tx: UnboundedSender<String>,
+    username: String,
+    task: JoinHandle<()>,
+}
+
+impl Events {
+    pub(crate) fn spawn(url: Url, session: Session) -> Self {
+        let (event_tx, rx) = mpsc::unbounded_channel();
+        let (tx, outgoing) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run(url, session.token, outgoing, event_tx));
+        Self {
+            rx,
+            tx,
+            username: session.username,
+            task,
+        }
+    }
+
+    pub async fn next(&mut self) -> Option<Event> {
+        self.rx.recv().await
+    }
+
+    fn queue(&self, text: String) -> Result<(), Error> {
+        self.tx
+            .send(text)
+            .map_err(|_| Error::new(std::io::ErrorKind::NotConnected, "The connection is closed"))
+    }
+
+    /// Sends a message, which waits for the connection to be up again if it is down
+    pub fn send(&self, mut message: Message) -> Result<(), Error> {
+        message.author = self.username.clone();
+        let cmd = WsCommand {
+            from: self.username.clone(),
+            message,
+        };
+        self.queue(serde_json::to_string(&cmd)?)
+    }
+
+    /// Sends a plain text message, slash commands included
+    pub fn send_text(&self, text: &str) -> Result<(), Error> {
+        let message = Message {
+            message: text.to_string(),
+            ..Message::at(now())
+        };
+        self.send(message)
+    }
+
+    pub fn vote(&self, message: u64, options: Vec<usize>) -> Result<(), Error> {
+        self.queue(serde_json::to_string(&WsRequest::Vote {
+            message,
+            options,
+        })?)
+    }
+}
+
+impl Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Keeps the websocket open, reconnecting with a growing delay whenever it drops
+async fn run(
+    url: Url,
+    token: String,
+    mut outgoing: UnboundedReceiver<String>,
+    events: UnboundedSender<Event>,
+) {
+    let mut delay = FIRST_RETRY;
+    // Frames not sent yet, kept across reconnections
+    let mut pending = VecDeque::new();
+
+    loop {
+        let mut request = match url.as_str().into_client_request() {
+            Ok(request) => request,
+            Err(error) => {
+                warn!(?error, "The url can't be connected to");
+                return;
+            }
+        };
+        // Browsers can't set headers, so the server expects the token as a second protocol
+        let Ok(protocols) = HeaderValue::from_str(&format!("realProtocol, {token}")) else {
+            warn!("The token can't be sent in a header");
+            return;
+        };
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", protocols);
+
+        match tokio_tungstenite::connect_async(request).await {
+            Ok((ws, _)) => {
+                delay = FIRST_RETRY;
+                if events.send(Event::Connected).is_err() {
+                    return;
+                }
+                let (mut sink, mut stream) = ws.split();
+                loop {
+                    if let Some(text) = pending.front() {
+                        if sink.send(Frame::Text(String::clone(text))).await.is_err() {
+                            break;
+                        }
+                        pending.pop_front();
+                        continue;
+                    }
+                    select! {
+                        frame = stream.next() => match frame {
+                            Some(Ok(Frame::Text(text))) => match serde_json::from_str(&text) {
+                                Ok(msg) => {
+                                    if events.send(Event::Message(msg)).is_err() {
+                                        return;
+                                    }
+                                }
+                                Err(error) => warn!(?error, "Unexpected message from the server"),
+                            },
+                            // Pings are answered by tungstenite itself
+                            Some(Ok(Frame::Close(_)) | Err(_)) | None => break,
+                            Some(Ok(_)) => {}
+                        },
+                        text = outgoing.recv() => match text {
+                            Some(text) => pending.push_back(text),
+                            // Nobody is left to read events
+                            None => return,
+                        },
+                    }
+                }
+                if events.send(Event::Disconnected).is_err() {
+                    return;
+                }
+            }
+            // Rate limits are waited out like any other failure
+            Err(tungstenite::Error::Http(res))
+                if res.status().is_client_error()
+                    && res.status() != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                warn!(status = %res.status(), "The server refused the connection");
+                return;
+            }
+            Err(error) => warn!(?error, "Could not connect"),
+        }
+
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY);
+    }
+}
@@ -0,0 +1,303 @@
+//! Client of the Discorde API
+//!
+//! Wraps the REST endpoints with the models of the server itself, and keeps chat websockets
+//! open with [`Client::connect`].
+
+use discorde_api::models::chat::{
+    ChatInput, ChatPatch, ChatView, MessageEdit, MessageView, PinView,
+};
+use discorde_api::models::creds::{Credentials, Login};
+use discorde_api::models::mention::MentionView;
+use discorde_api::models::page::PageQuery;
+use discorde_api::models::poll::{PollView, VoteInput};
+use discorde_api::models::settings::UserChatView;
+use discorde_api::models::user::{UserInput, UserView};
+use discorde_api::models::Redacted;
+use reqwest::{Method, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+pub use discorde_api::models;
+pub use events::{Event, Events};
+
+mod events;
+
+/// Who requests are made as
+#[derive(Clone)]
+pub struct Session {
+    pub username: String,
+    /// Session token of a user, or API token of a bot
+    pub token: String,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("username", &self.username)
+            .field("token", &Redacted)
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base: Url,
+    session: Option<Session>,
+}
+
+/// Turns an error answer of the server into the error kind it stands for
+fn status_error(status: StatusCode) -> Error {
+    let kind = match status {
+        StatusCode::BAD_REQUEST => ErrorKind::InvalidInput,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ErrorKind::PermissionDenied,
+        StatusCode::NOT_FOUND => ErrorKind::NotFound,
+        _ => ErrorKind::Other,
+    };
+    Error::new(kind, format!("Server answered {status}"))
+}
+
+impl Client {
+    /// Client of the server at `base`, such as `http://localhost:3000`
+    pub fn new(base: &str) -> Result<Self, Error> {
+        let base = Url::parse(base).map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+        if base.cannot_be_a_base() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{base} can't be used as a base url"),
+            ));
+        }
+        Ok(Self {
+            http: reqwest::Client::new(),
+            base,
+            session: None,
+        })
+    }
+
+    /// Acts with an existing session, which is how bots authenticate with their tokens
+    pub fn with_session(mut self, session: Session) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Url of the endpoint made of `segments`, each of them escaped
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("checked in Client::new")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let request = self.http.request(method, self.url(segments));
+        match &self.session {
+            Some(session) => request.bearer_auth(&session.token),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, Error> {
+        let res = request.send().await.map_err(Error::other)?;
+        if !res.status().is_success() {
+            return Err(status_error(res.status()));
+        }
+        Ok(res)
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        self.send(request)
+            .await?
+            .json()
+            .await
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error))
+    }
+
+    pub async fn create_user(&self, username: &str, password: &str) -> Result<(), Error> {
+        let input = UserInput {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+        self.send(self.request(Method::POST, &["users"]).json(&input))
+            .await
+            .map(|_| ())
+    }
+
+    /// Logs in, making every following request as this user
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<UserView, Error> {
+        let login = Login {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+        let creds: Credentials = self
+            .json(self.request(Method::POST, &["login"]).json(&login))
+            .await?;
+        self.session = Some(Session {
+            username: creds.user.username.clone(),
+            token: creds.token,
+        });
+        Ok(creds.user)
+    }
+
+    pub async fn users(&self) -> Result<Vec<UserView>, Error> {
+        self.json(self.request(Method::GET, &["users"])).await
+    }
+
+    pub async fn user(&self, username: &str) -> Result<UserView, Error> {
+        self.json(self.request(Method::GET, &["users", username]))
+            .await
+    }
+
+    /// Mentions of the current user, `unread` leaving out those already read
+    pub async fn mentions(
+        &self,
+        unread: bool,
+        page: &PageQuery,
+    ) -> Result<Vec<MentionView>, Error> {
+        let request = self
+            .request(Method::GET, &["users", "me", "mentions"])
+            .query(page)
+            .query(&[("unread", unread)]);
+        self.json(request).await
+    }
+
+    pub async fn read_mention(&self, id: &str) -> Result<(), Error> {
+        let request = self.request(Method::PUT, &["users", "me", "mentions", id, "read"]);
+        self.send(request).await.map(|_| ())
+    }
+
+    pub async fn create_chat(&self, chat: &ChatInput) -> Result<ChatView, Error> {
+        self.json(self.request(Method::POST, &["chats"]).json(chat))
+            .await
+    }
+
+    /// Chats of the current user, in the order they picked
+    pub async fn chats(&self) -> Result<Vec<UserChatView>, Error> {
+        self.json(self.request(Method::GET, &["chats"])).await
+    }
+
+    pub async fn edit_chat(&self, chat: &str, patch: &ChatPatch) -> Result<ChatView, Error> {
+        self.json(self.request(Method::PATCH, &["chats", chat]).json(patch))
+            .await
+    }
+
+    /// Adds `user` to a chat, or joins it when `user` is the current user
+    pub async fn add_member(&self, chat: &str, user: &str) -> Result<(), Error> {
+        let request = self.request(Method::PUT, &["chats", chat, "members", user]);
+        self.send(request).await.map(|_| ())
+    }
+
+    /// Removes `user` from a chat, or leaves it when `user` is the current user
+    pub async fn remove_member(&self, chat: &str, user: &str) -> Result<(), Error> {
+        let request = self.request(Method::DELETE, &["chats", chat, "members", user]);
+        self.send(request).await.map(|_| ())
+    }
+
+    pub async fn messages(&self, chat: &str, page: &PageQuery) -> Result<Vec<MessageView>, Error> {
+        let request = self
+            .request(Method::GET, &["chats", chat, "messages"])
+            .query(page);
+        self.json(request).await
+    }
+
+    pub async fn thread(
+        &self,
+        chat: &str,
+        message: u64,
+        page: &PageQuery,
+    ) -> Result<Vec<MessageView>, Error> {
+        let message = message.to_string();
+        let request = self
+            .request(
+                Method::GET,
+                &["chats", chat, "messages", &message, "thread"],
+            )
+            .query(page);
+        self.json(request).await
+    }
+
+    pub async fn pins(&self, chat: &str) -> Result<Vec<PinView>, Error> {
+        self.json(self.request(Method::GET, &["chats", chat, "pins"]))
+            .await
+    }
+
+    pub async fn pin(&self, chat: &str, message: u64) -> Result<(), Error> {
+        let message = message.to_string();
+        let request = self.request(Method::PUT, &["chats", chat, "pins", &message]);
+        self.send(request).await.map(|_| ())
+    }
+
+    pub async fn unpin(&self, chat: &str, message: u64) -> Result<(), Error> {
+        let message = message.to_string();
+        let request = self.request(Method::DELETE, &["chats", chat, "pins", &message]);
+        self.send(request).await.map(|_| ())
+    }
+
+    /// Replaces the text of a message of the current user
+    pub async fn edit_message(&self, chat: &str, message: u64, text: &str) -> Result<(), Error> {
+        let edit = MessageEdit {
+            message: text.to_string(),
+        };
+        let message = message.to_string();
+        let request = self.request(Method::PATCH, &["chats", chat, "messages", &message]);
+        self.send(request.json(&edit)).await.map(|_| ())
+    }
+
+    pub async fn react(&self, chat: &str, message: u64, emoji: &str) -> Result<(), Error> {
+        let message = message.to_string();
+        let segments = ["chats", chat, "messages", &message, "reactions", emoji];
+        self.send(self.request(Method::PUT, &segments))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn unreact(&self, chat: &str, message: u64, emoji: &str) -> Result<(), Error> {
+        let message = message.to_string();
+        let segments = ["chats", chat, "messages", &message, "reactions", emoji];
+        self.send(self.request(Method::DELETE, &segments))
+            .await
+            .map(|_| ())
+    }
+
+    /// Replaces the ballot of the current user, an empty one withdrawing their vote
+    pub async fn vote(
+        &self,
+        chat: &str,
+        message: u64,
+        options: Vec<usize>,
+    ) -> Result<PollView, Error> {
+        let message = message.to_string();
+        let request = self
+            .request(Method::PUT, &["chats", chat, "messages", &message, "votes"])
+            .json(&VoteInput { options });
+        self.json(request).await
+    }
+
+    /// Marks the messages of a chat as read up to `message`
+    pub async fn read(&self, chat: &str, message: u64) -> Result<(), Error> {
+        let message = message.to_string();
+        let request = self.request(Method::PUT, &["chats", chat, "read", &message]);
+        self.send(request).await.map(|_| ())
+    }
+
+    /// Opens the websocket of a chat, which reconnects by itself until it is dropped
+    pub fn connect(&self, chat: &str) -> Result<Events, Error> {
+        let Some(session) = self.session.clone() else {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Log in before connecting to a chat",
+            ));
+        };
+        let mut url = self.url(&["chats", chat]);
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Can't make a websocket url"))?;
+        Ok(Events::spawn(url, session))
+    }
+}
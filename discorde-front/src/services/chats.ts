@@ -78,7 +78,9 @@ async function liveMessages(id: string): Promise<{
     ws.onmessage = event => {
       console.log(event)
       console.log(event.data)
-      const msg: WsCommand = JSON.parse(event.data)
+      const msg = JSON.parse(event.data)
+      // Events (reactions, ...) carry a type, plain messages don't
+      if ("type" in msg) return
       s.next(new Message(msg.message.timestamp, msg.message.author, msg.message.message))
     }
   })
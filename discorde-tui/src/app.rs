@@ -0,0 +1,410 @@
+use crate::ui;
+use crossterm::event::{
+    Event as TermEvent, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+};
+use discorde_client::models::chat::{Message, MessageKind, MessageView};
+use discorde_client::models::now;
+use discorde_client::models::page::PageQuery;
+use discorde_client::models::settings::UserChatView;
+use discorde_client::models::ws::{WsEvent, WsMessage};
+use discorde_client::{Client, Event, Events};
+use futures_util::StreamExt;
+use ratatui::DefaultTerminal;
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::time::Duration;
+use tokio::select;
+use tokio::time::Instant;
+
+/// Messages loaded when opening a chat
+const HISTORY: usize = 200;
+/// Delay before reloading the history after sending, to show what the server made of it
+const REFRESH_DELAY: Duration = Duration::from_millis(300);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Field {
+    Server,
+    Username,
+    Password,
+}
+
+pub struct LoginForm {
+    pub server: String,
+    pub username: String,
+    pub password: String,
+    pub focus: Field,
+    pub error: Option<String>,
+}
+
+impl LoginForm {
+    fn field(&mut self) -> &mut String {
+        match self.focus {
+            Field::Server => &mut self.server,
+            Field::Username => &mut self.username,
+            Field::Password => &mut self.password,
+        }
+    }
+
+    fn next(&mut self) {
+        self.focus = match self.focus {
+            Field::Server => Field::Username,
+            Field::Username => Field::Password,
+            Field::Password => Field::Server,
+        };
+    }
+
+    fn previous(&mut self) {
+        self.focus = match self.focus {
+            Field::Server => Field::Password,
+            Field::Username => Field::Server,
+            Field::Password => Field::Username,
+        };
+    }
+}
+
+/// Message as shown in the history, whether it came from the history or the websocket
+pub struct Entry {
+    pub timestamp: u64,
+    pub name: String,
+    pub text: String,
+    pub kind: MessageKind,
+}
+
+impl Entry {
+    fn from_view(message: MessageView) -> Self {
+        Self {
+            timestamp: message.timestamp,
+            name: message.nick.unwrap_or(message.author),
+            text: message.message,
+            kind: message.kind,
+        }
+    }
+
+    fn from_message(message: Message) -> Self {
+        Self {
+            timestamp: message.timestamp,
+            name: message.nick.unwrap_or(message.author),
+            text: message.message,
+            kind: message.kind,
+        }
+    }
+}
+
+/// Everything shown once logged in
+pub struct Session {
+    client: Client,
+    pub username: String,
+    pub chats: Vec<UserChatView>,
+    pub selected: usize,
+    pub entries: BTreeMap<u64, Entry>,
+    events: Option<Events>,
+    /// Whether the websocket dropped since the history was loaded
+    reconnecting: bool,
+    refresh_at: Option<Instant>,
+    pub input: String,
+    /// Messages scrolled past, counted from the most recent
+    pub scroll: usize,
+    pub status: String,
+}
+
+impl Session {
+    pub fn current(&self) -> Option<&UserChatView> {
+        self.chats.get(self.selected)
+    }
+
+    async fn load_chats(&mut self) {
+        match self.client.chats().await {
+            Ok(chats) => {
+                let current = self.current().map(|c| c.chat.id.clone());
+                self.chats = chats;
+                self.selected = current
+                    .and_then(|id| self.chats.iter().position(|c| c.chat.id == id))
+                    .unwrap_or(0);
+            }
+            Err(error) => self.status = format!("Could not list chats: {error}"),
+        }
+    }
+
+    async fn load_history(&mut self) {
+        let Some(id) = self.current().map(|c| c.chat.id.clone()) else {
+            return;
+        };
+        let page = PageQuery {
+            before: None,
+            limit: Some(HISTORY),
+        };
+        match self.client.messages(&id, &page).await {
+            Ok(messages) => {
+                self.entries = messages
+                    .into_iter()
+                    .map(|m| (m.timestamp, Entry::from_view(m)))
+                    .collect();
+            }
+            Err(error) => self.status = format!("Could not load messages: {error}"),
+        }
+        self.mark_read().await;
+    }
+
+    async fn mark_read(&mut self) {
+        let Some(id) = self.current().map(|c| c.chat.id.clone()) else {
+            return;
+        };
+        if let Some(last) = self.entries.keys().next_back() {
+            if self.client.read(&id, *last).await.is_ok() {
+                self.chats[self.selected].unread = 0;
+            }
+        }
+    }
+
+    /// Shows the selected chat, with its history and a websocket for what comes next
+    async fn open(&mut self) {
+        self.events = None;
+        self.entries.clear();
+        self.scroll = 0;
+        self.reconnecting = false;
+        self.load_history().await;
+        if let Some(id) = self.current().map(|c| c.chat.id.clone()) {
+            match self.client.connect(&id) {
+                Ok(events) => self.events = Some(events),
+                Err(error) => self.status = error.to_string(),
+            }
+        }
+    }
+
+    async fn switch(&mut self, forward: bool) {
+        if self.chats.is_empty() {
+            return;
+        }
+        let len = self.chats.len();
+        self.selected = if forward {
+            (self.selected + 1) % len
+        } else {
+            (self.selected + len - 1) % len
+        };
+        self.open().await;
+    }
+
+    fn send(&mut self) {
+        let text = std::mem::take(&mut self.input);
+        if text.trim().is_empty() {
+            return;
+        }
+        let Some(events) = &self.events else {
+            self.status = "Not connected to this chat".to_string();
+            return;
+        };
+        let message = Message {
+            message: text.clone(),
+            ..Message::at(now())
+        };
+        if let Err(error) = events.send(message.clone()) {
+            self.status = error.to_string();
+            return;
+        }
+        // The server doesn't echo messages to their sender, and commands are rewritten by it
+        if !text.starts_with('/') {
+            let entry = Entry {
+                name: self.username.clone(),
+                ..Entry::from_message(message)
+            };
+            self.entries.insert(entry.timestamp, entry);
+        }
+        self.scroll = 0;
+        self.refresh_at = Some(Instant::now() + REFRESH_DELAY);
+    }
+
+    async fn on_event(&mut self, event: Option<Event>) {
+        match event {
+            Some(Event::Connected) => {
+                self.status = "Connected".to_string();
+                // Messages sent while disconnected were missed
+                if self.reconnecting {
+                    self.reconnecting = false;
+                    self.load_history().await;
+                }
+            }
+            Some(Event::Disconnected) => {
+                self.status = "Connection lost, reconnecting…".to_string();
+                self.reconnecting = true;
+            }
+            Some(Event::Message(WsMessage::Command(cmd))) => {
+                let entry = Entry::from_message(cmd.message);
+                self.entries.insert(entry.timestamp, entry);
+                self.mark_read().await;
+            }
+            Some(Event::Message(WsMessage::Event(event))) => match event {
+                WsEvent::Ephemeral { message } => self.status = message,
+                WsEvent::MessageEdit { message, text, .. } => {
+                    if let Some(entry) = self.entries.get_mut(&message) {
+                        entry.text = text;
+                    }
+                }
+                WsEvent::MessageDelete { message } => {
+                    self.entries.remove(&message);
+                }
+                WsEvent::ChatUpdate { chat } => {
+                    if let Some(current) = self.chats.get_mut(self.selected) {
+                        current.chat = chat;
+                    }
+                }
+                _ => {}
+            },
+            None => {
+                self.events = None;
+                self.status = "The server refused the connection to this chat".to_string();
+            }
+        }
+    }
+
+    async fn on_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Tab => self.switch(true).await,
+            KeyCode::BackTab => self.switch(false).await,
+            KeyCode::Char('n') if ctrl => self.switch(true).await,
+            KeyCode::Char('p') if ctrl => self.switch(false).await,
+            KeyCode::Char('r') if ctrl => {
+                self.load_chats().await;
+                self.open().await;
+            }
+            KeyCode::PageUp => self.scroll = (self.scroll + 10).min(self.entries.len()),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Enter => self.send(),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) if !ctrl => self.input.push(c),
+            _ => {}
+        }
+    }
+}
+
+pub struct App {
+    pub login: LoginForm,
+    pub session: Option<Session>,
+    quit: bool,
+}
+
+impl App {
+    pub fn new(server: String, username: String) -> Self {
+        let focus = if username.is_empty() {
+            Field::Username
+        } else {
+            Field::Password
+        };
+        Self {
+            login: LoginForm {
+                server,
+                username,
+                password: String::new(),
+                focus,
+                error: None,
+            },
+            session: None,
+            quit: false,
+        }
+    }
+
+    pub async fn run(mut self, terminal: &mut DefaultTerminal) -> Result<(), Error> {
+        let mut keys = EventStream::new();
+        while !self.quit {
+            terminal.draw(|frame| ui::draw(frame, &self))?;
+            let refresh_at = self.session.as_ref().and_then(|s| s.refresh_at);
+            select! {
+                event = keys.next() => match event {
+                    Some(Ok(TermEvent::Key(key))) if key.kind == KeyEventKind::Press => {
+                        self.on_key(key).await;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => return Err(error),
+                    None => return Ok(()),
+                },
+                event = next_event(&mut self.session) => {
+                    if let Some(session) = &mut self.session {
+                        session.on_event(event).await;
+                    }
+                }
+                _ = sleep_until(refresh_at) => {
+                    if let Some(session) = &mut self.session {
+                        session.refresh_at = None;
+                        session.load_history().await;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if key.code == KeyCode::Esc || (ctrl && key.code == KeyCode::Char('c')) {
+            self.quit = true;
+            return;
+        }
+        if let Some(session) = &mut self.session {
+            session.on_key(key).await;
+            return;
+        }
+
+        match key.code {
+            KeyCode::Tab | KeyCode::Down => self.login.next(),
+            KeyCode::BackTab | KeyCode::Up => self.login.previous(),
+            KeyCode::Enter if self.login.focus != Field::Password => self.login.next(),
+            KeyCode::Enter => self.log_in().await,
+            KeyCode::Backspace => {
+                self.login.field().pop();
+            }
+            KeyCode::Char(c) if !ctrl => self.login.field().push(c),
+            _ => {}
+        }
+    }
+
+    async fn log_in(&mut self) {
+        let mut client = match Client::new(&self.login.server) {
+            Ok(client) => client,
+            Err(error) => {
+                self.login.error = Some(error.to_string());
+                return;
+            }
+        };
+        if let Err(error) = client
+            .login(&self.login.username, &self.login.password)
+            .await
+        {
+            self.login.error = Some(format!("Could not log in: {error}"));
+            return;
+        }
+
+        let mut session = Session {
+            client,
+            username: self.login.username.clone(),
+            chats: vec![],
+            selected: 0,
+            entries: BTreeMap::new(),
+            events: None,
+            reconnecting: false,
+            refresh_at: None,
+            input: String::new(),
+            scroll: 0,
+            status: "Tab and Shift+Tab switch chats, Esc quits".to_string(),
+        };
+        session.load_chats().await;
+        session.open().await;
+        self.login.password.clear();
+        self.session = Some(session);
+    }
+}
+
+async fn next_event(session: &mut Option<Session>) -> Option<Event> {
+    match session.as_mut().and_then(|s| s.events.as_mut()) {
+        Some(events) => events.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
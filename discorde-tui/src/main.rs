@@ -0,0 +1,32 @@
+use crate::app::App;
+use std::io::{Error, ErrorKind};
+
+mod app;
+mod ui;
+
+const DEFAULT_SERVER: &str = "http://localhost:3000";
+const USAGE: &str = "Usage: discorde-tui [--server <url>] [--user <username>]";
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let mut server =
+        std::env::var("DISCORDE_SERVER").unwrap_or_else(|_| DEFAULT_SERVER.to_string());
+    let mut username = String::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "--server" => &mut server,
+            "--user" => &mut username,
+            _ => return Err(Error::new(ErrorKind::InvalidInput, USAGE)),
+        };
+        *value = args
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, USAGE))?;
+    }
+
+    let mut terminal = ratatui::init();
+    let res = App::new(server, username).run(&mut terminal).await;
+    ratatui::restore();
+    res
+}
@@ -0,0 +1,174 @@
+use crate::app::{App, Entry, Field, LoginForm, Session};
+use discorde_client::models::chat::MessageKind;
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+
+const CHAT_LIST_WIDTH: u16 = 28;
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    match &app.session {
+        Some(session) => draw_session(frame, session),
+        None => draw_login(frame, &app.login),
+    }
+}
+
+fn draw_login(frame: &mut Frame, form: &LoginForm) {
+    let [area] = Layout::horizontal([Constraint::Length(50)])
+        .flex(ratatui::layout::Flex::Center)
+        .areas(frame.area());
+    let [_, server, username, password, error] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Fill(1),
+    ])
+    .areas(area);
+
+    let masked = "•".repeat(form.password.chars().count());
+    let fields = [
+        (server, "Server", form.server.as_str(), Field::Server),
+        (
+            username,
+            "Username",
+            form.username.as_str(),
+            Field::Username,
+        ),
+        (password, "Password", masked.as_str(), Field::Password),
+    ];
+    for (area, title, value, field) in fields {
+        let focused = form.focus == field;
+        let style = if focused {
+            Style::new().fg(Color::Cyan)
+        } else {
+            Style::new()
+        };
+        frame.render_widget(
+            Paragraph::new(value).block(Block::bordered().title(title).border_style(style)),
+            area,
+        );
+        if focused {
+            set_cursor(frame, area, value);
+        }
+    }
+    if let Some(message) = &form.error {
+        frame.render_widget(
+            Paragraph::new(message.as_str())
+                .red()
+                .wrap(Wrap { trim: true }),
+            error,
+        );
+    }
+}
+
+fn draw_session(frame: &mut Frame, session: &Session) {
+    let [list, main] =
+        Layout::horizontal([Constraint::Length(CHAT_LIST_WIDTH), Constraint::Fill(1)])
+            .areas(frame.area());
+    let [history, input, status] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .areas(main);
+
+    let items: Vec<ListItem> = session
+        .chats
+        .iter()
+        .map(|c| {
+            let mut spans = vec![Span::raw(c.chat.name.clone())];
+            if c.unread > 0 {
+                spans.push(Span::raw(format!(" ({})", c.unread)).bold());
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+    let mut state = ListState::default().with_selected(Some(session.selected));
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::bordered().title("Chats"))
+            .highlight_style(Style::new().reversed()),
+        list,
+        &mut state,
+    );
+
+    let title = session
+        .current()
+        .map(|c| match c.chat.topic.as_str() {
+            "" => c.chat.name.clone(),
+            topic => format!("{} — {topic}", c.chat.name),
+        })
+        .unwrap_or_default();
+    let block = Block::bordered().title(title);
+    let inner = block.inner(history);
+    frame.render_widget(block, history);
+    draw_history(frame, session, inner);
+
+    frame.render_widget(
+        Paragraph::new(session.input.as_str())
+            .block(Block::bordered().title(session.username.as_str())),
+        input,
+    );
+    set_cursor(frame, input, &session.input);
+    frame.render_widget(Paragraph::new(session.status.as_str()).dim(), status);
+}
+
+/// Bottom-aligned history, leaving out the messages scrolled past
+fn draw_history(frame: &mut Frame, session: &Session, area: Rect) {
+    let width = area.width.max(1) as usize;
+    let mut lines = vec![];
+    let mut height = 0;
+    for entry in session.entries.values().rev().skip(session.scroll) {
+        if height >= area.height as usize {
+            break;
+        }
+        let line = entry_line(entry);
+        height += line.width().div_ceil(width).max(1);
+        lines.push(line);
+    }
+    lines.reverse();
+    // The topmost message may only partly fit
+    let overflow = height.saturating_sub(area.height as usize) as u16;
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .scroll((overflow, 0)),
+        area,
+    );
+}
+
+fn entry_line(entry: &Entry) -> Line<'_> {
+    let time = Span::raw(format!("{} ", clock(entry.timestamp))).dim();
+    match entry.kind {
+        MessageKind::System => Line::from(vec![
+            time,
+            Span::styled(
+                entry.text.as_str(),
+                Style::new().add_modifier(Modifier::ITALIC | Modifier::DIM),
+            ),
+        ]),
+        MessageKind::User | MessageKind::Webhook => Line::from(vec![
+            time,
+            Span::raw(entry.name.as_str()).bold().fg(Color::Cyan),
+            Span::raw(": "),
+            Span::raw(entry.text.as_str()),
+        ]),
+    }
+}
+
+/// Time of day of a timestamp, in UTC
+fn clock(timestamp: u64) -> String {
+    let seconds = timestamp / 1000 % 86400;
+    format!("{:02}:{:02}", seconds / 3600, seconds / 60 % 60)
+}
+
+fn set_cursor(frame: &mut Frame, area: Rect, value: &str) {
+    let x = area.x + 1 + value.chars().count() as u16;
+    frame.set_cursor_position(Position::new(
+        x.min(area.right().saturating_sub(2)),
+        area.y + 1,
+    ));
+}